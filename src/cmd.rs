@@ -1,14 +1,56 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
-#[derive(Parser, Debug)]
-#[command(version)]
+use crate::config::SchemaKind;
+
+#[derive(Parser, Debug, Clone)]
+#[command(version, about, long_about = None)]
 pub struct Args {
+    /// Main config file, merged after the plan and user directories
     #[arg(short, long, default_value_t = String::from("./config.yml"), env("APP_CONFIG_PATH"))]
     pub config_path: String,
-    #[arg(short, long, default_value_t = String::from("TRACE"), env("APP_LOG_LEVEL"))]
-    pub log_level: String,
+    /// Plan directory, repeat the flag (or separate with commas in the env variable) to merge several
+    #[arg(long = "plans-dir", default_values_t = [String::from("./config/plans")], env("APP_PLANS_DIRS"), value_delimiter = ',')]
+    pub plans_dirs: Vec<String>,
+    /// User directory, repeat the flag (or separate with commas in the env variable) to merge several
+    #[arg(long = "users-dir", default_values_t = [String::from("./config/users")], env("APP_USERS_DIRS"), value_delimiter = ',')]
+    pub users_dirs: Vec<String>,
+    /// Directory with the frontend assets
+    #[arg(long, default_value_t = String::from("./static"), env("APP_STATIC_DIR"))]
+    pub static_dir: String,
+    /// Log filter like `info` or `plim_rusty=debug`, RUST_LOG is used when it's not set
+    #[arg(long, env("APP_LOG_LEVEL"))]
+    pub log_level: Option<String>,
+    #[arg(short, long)]
+    pub listen_address: Option<String>,
+    #[arg(long, action)]
+    pub validate_config: bool,
+    /// Print the JSON schema of a config part and exit
+    #[arg(long, value_enum)]
+    pub print_schema: Option<SchemaKind>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Apply pending etcd migrations and exit
+    Migrate {
+        /// Directory with the `<version>_<name>.yml` migration files
+        #[arg(long, default_value_t = String::from("./etcd_migrations"), env("APP_MIGRATIONS_DIR"))]
+        dir: String,
+        /// Print what pending migrations would change without writing to etcd
+        #[arg(long, action)]
+        dry_run: bool,
+    },
+}
+
+impl Args {
+    /// Config sources in merge order, later ones override earlier ones
+    pub fn config_paths(&self) -> Vec<String> {
+        self.plans_dirs.iter().chain(&self.users_dirs).chain(std::iter::once(&self.config_path)).cloned().collect()
+    }
 }
 
 pub fn parse() -> Args {
     Args::parse()
-}
\ No newline at end of file
+}
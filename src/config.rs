@@ -1,110 +1,323 @@
 use anyhow::{Context, Error};
-use etcd_client::{Client, GetOptions, GetResponse};
-use log::{error, info};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use etcd_client::{Certificate, Client, ConnectOptions, GetOptions, Identity, KeyValue, TlsOptions};
+use log::{error, info, warn};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::time::Duration;
+use crate::config_extends::{allow_extends_in_schema, reject_extends, TEMPLATES_KEY};
+use crate::config_sources::{replace_source, ConfigSource, ConfigSources};
+use crate::config_validation::{check_config, has_errors, path_within, validate_config_files, IssueSeverity};
+use crate::etcd_manager::EtcdManager;
 use crate::merge_yml::ConfigData;
+use crate::state::GitlabTokens;
 use bcrypt::verify;
 use derive_merge_struct::Merge;
+use schemars::JsonSchema;
 
-const PLANS_PATH: &str = "./config/plans";
-const USERS_PATH: &str = "./config/users";
-const ANSIBLE_PATH: &str = "./config/ansible";
-const MAIN_CONFIG_PATH: &str = "./config.yml";
-use clap::Parser;
+use crate::cmd::Args;
 
+/// Keys read per request when loading an etcd prefix
 const MAX_ETCD_KEYS_COUNT: i64 = 1000;
 
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
-    #[arg(short, long)]
-    listen_address: Option<String>,
-    #[arg(long, action)]
-    validate_config: bool,
+/// Config parts a JSON schema is generated for
+#[derive(Debug, Clone, Copy, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SchemaKind {
+    Config,
+    /// Complete plan as etcd and the plan API store it
+    Plan,
+    /// Plan in a config file, which can `extends` a template or plan
+    #[serde(rename = "plan-file")]
+    PlanFile,
+    User,
 }
 
-pub async fn load() -> Result<Config, Error> {
-    let mut config_data = ConfigData::new(vec![PLANS_PATH, USERS_PATH, MAIN_CONFIG_PATH]);
-    let _ = config_data.load_and_merge();
-    let mut conf: Config = serde_yaml::from_str(&config_data.merged_data.to_string())?;
-    let args = Args::parse();
-    if let Some(listen_address) = args.listen_address {
-        conf.plim.listen_address = listen_address;
+pub fn config_schema(kind: SchemaKind) -> serde_json::Value {
+    match kind {
+        SchemaKind::Config => {
+            let mut schema = schemars::schema_for!(Config).to_value();
+            allow_extends_in_schema(&mut schema, "/$defs/PlimPlan");
+            // templates are merged like plans but don't need to be complete on their own
+            let mut template = schema["$defs"]["PlimPlan"].clone();
+            if let Some(template) = template.as_object_mut() {
+                template.remove("if");
+                template.remove("else");
+                template.insert("title".to_string(), "PlimPlanTemplate".into());
+            }
+            schema["$defs"]["PlimPlanTemplate"] = template;
+            schema["properties"][TEMPLATES_KEY] = serde_json::json!({
+                "type": "object",
+                "additionalProperties": { "$ref": "#/$defs/PlimPlanTemplate" },
+            });
+            schema
+        }
+        SchemaKind::Plan => schemars::schema_for!(PlimPlan).to_value(),
+        SchemaKind::PlanFile => {
+            let mut schema = schemars::schema_for!(PlimPlan).to_value();
+            allow_extends_in_schema(&mut schema, "");
+            schema
+        }
+        SchemaKind::User => schemars::schema_for!(PlimUser).to_value(),
     }
+}
+
+/// Config from the files with the command line overrides, `load_etcd_configs` adds what is stored in etcd.
+/// `tokens` gets the secret providers of the config
+pub async fn load(args: &Args, tokens: &GitlabTokens) -> Result<Config, Error> {
+    if let Some(kind) = args.print_schema {
+        println!("{}", serde_json::to_string_pretty(&config_schema(kind))?);
+        std::process::exit(0);
+    }
+    let config_paths = args.config_paths();
+    let config_paths: Vec<&str> = config_paths.iter().map(String::as_str).collect();
     if args.validate_config {
         println!("Validating local config...");
-        println!("Config: {:?}", conf);
-        println!("Quitting...");
-        std::process::exit(0);
+        let (_, issues) = validate_config_files(&config_paths, tokens).await;
+        issues.iter().for_each(|issue| println!("{}", issue));
+        let errors = issues.iter().filter(|issue| issue.severity == IssueSeverity::Error).count();
+        println!("{} errors, {} warnings", errors, issues.len() - errors);
+        std::process::exit(if has_errors(&issues) { 1 } else { 0 });
     }
+    let mut conf = load_local(&config_paths, tokens).await?;
+    if let Some(listen_address) = &args.listen_address {
+        conf.plim.listen_address = listen_address.clone();
+    }
+    Ok(conf)
+}
+
+/// Config from the files only, without plans and users stored in etcd
+pub async fn load_local(config_paths: &[&str], tokens: &GitlabTokens) -> Result<Config, Error> {
+    let mut config_data = ConfigData::new(config_paths.to_vec());
+    let _ = config_data.load_and_merge();
+    let staged = config_data.prepare_secrets(tokens).await?;
+    let mut conf: Config = serde_yaml::from_str(&config_data.to_config_yaml(&staged)?)?;
+    conf.sources = config_data.sources;
+    tokens.adopt(&staged);
+    Ok(conf)
+}
+
+/// Read the config again for a running server, unlike `load` any unreadable config file fails the reload
+pub async fn reload(current: &Config, config_paths: &[String], tokens: &GitlabTokens, etcd: &EtcdManager) -> Result<Config, Error> {
+    let mut config_data = ConfigData::new(config_paths.iter().map(String::as_str).collect());
+    config_data.load_and_merge().context("Failed to read config files")?;
+    // the running config keeps its secrets until the new one is accepted
+    let staged = config_data.prepare_secrets(tokens).await?;
+    let mut conf: Config = serde_yaml::from_str(&config_data.to_config_yaml(&staged)?).context("Failed to parse config")?;
+    conf.sources = config_data.sources;
+    // the listener is already bound, a new address needs a restart
+    conf.plim.listen_address = current.plim.listen_address.clone();
+    etcd.update_clusters(&conf.etcd_data_map).await;
+    let conf = load_etcd_configs(conf, Some(current), etcd).await;
+    conf.validate(&staged)?;
+    tokens.adopt(&staged);
+    Ok(conf)
+}
+
+/// Add the plans and users stored in etcd. A reload passes the running config as `current`,
+/// when etcd can't be read its etcd plans and users are kept instead of dropped
+pub async fn load_etcd_configs(conf: Config, current: Option<&Config>, etcd: &EtcdManager) -> Config {
     let etcd_confs = conf.etcd_configs.clone();
-    conf = match load_with_etcd_configs::<PlimPlan>(conf.clone(), &etcd_confs.plans.key_prefix_path, &etcd_confs.plans.etcd_name).await.context("Failed to load plan etcd configs") {
-        Ok(etcd_plan_configs_merged) => etcd_plan_configs_merged,
-        Err(e) => {
-            error!("Failed to load plan etcd configs: {:?}", e);
-            conf
-        }
-    };
-    conf = match load_with_etcd_configs::<PlimUser>(conf.clone(), &etcd_confs.users.key_prefix_path, &etcd_confs.users.etcd_name).await.context("Failed to load users etcd configs") {
-        Ok(etcd_user_configs_merged) => etcd_user_configs_merged,
+    let conf = load_or_keep_etcd_configs::<PlimPlan>(conf, current, &etcd_confs.plans, etcd, "plans").await;
+    load_or_keep_etcd_configs::<PlimUser>(conf, current, &etcd_confs.users, etcd, "users").await
+}
+
+async fn load_or_keep_etcd_configs<T: DeserializeOwned + EtcdConfigLoader>(mut conf: Config, current: Option<&Config>, etcd_config: &EtcdConfig, etcd: &EtcdManager, kind: &str) -> Config {
+    match load_with_etcd_configs::<T>(conf.clone(), etcd_config, etcd).await {
+        Ok(merged) => merged,
         Err(e) => {
-            error!("Failed to load users etcd configs: {:?}", e);
+            error!("Failed to load {} etcd configs: {:?}", kind, e);
+            if let Some(current) = current {
+                let kept = T::keep_etcd_entries(current, &mut conf, &etcd_config.etcd_name);
+                if kept > 0 {
+                    warn!("Keeping {} {} of the running config from etcd {}", kept, kind, etcd_config.etcd_name);
+                }
+            }
             conf
         }
-    };
-
-    Ok(conf)
+    }
 }
 
 pub trait EtcdConfigLoader {
     fn load_into_config(&self, conf: &mut Config, name: String);
+    fn remove_from_config(conf: &mut Config, name: &str) where Self: Sized;
+    fn sources(conf: &mut Config) -> &mut BTreeMap<String, Vec<ConfigSource>> where Self: Sized;
+    fn from_config(conf: &Config, name: &str) -> Option<Self> where Self: Sized;
+    fn config_sources(conf: &Config) -> &BTreeMap<String, Vec<ConfigSource>> where Self: Sized;
+
+    /// Load a value read from etcd, its key becomes the only source of `name`
+    fn load_from_etcd(&self, conf: &mut Config, name: String, source: ConfigSource) where Self: Sized {
+        replace_source(Self::sources(conf), &name, source);
+        self.load_into_config(conf, name);
+    }
+
+    /// Copy the entries `current` read from `etcd_name` into `conf`, returns how many there were
+    fn keep_etcd_entries(current: &Config, conf: &mut Config, etcd_name: &str) -> usize where Self: Sized {
+        let mut kept = 0;
+        for (name, sources) in Self::config_sources(current) {
+            let [source @ ConfigSource::Etcd { etcd_name: source_etcd, .. }] = sources.as_slice() else { continue };
+            if source_etcd != etcd_name {
+                continue;
+            }
+            if let Some(value) = Self::from_config(current, name) {
+                value.load_from_etcd(conf, name.clone(), source.clone());
+                kept += 1;
+            }
+        }
+        kept
+    }
 }
 
 impl EtcdConfigLoader for PlimPlan {
     fn load_into_config(&self, conf: &mut Config, name: String) {
         conf.plans.insert(name, self.clone());
     }
+    fn remove_from_config(conf: &mut Config, name: &str) {
+        conf.plans.remove(name);
+        conf.sources.plans.remove(name);
+    }
+    fn sources(conf: &mut Config) -> &mut BTreeMap<String, Vec<ConfigSource>> {
+        &mut conf.sources.plans
+    }
+    fn from_config(conf: &Config, name: &str) -> Option<Self> {
+        conf.plans.get(name).cloned()
+    }
+    fn config_sources(conf: &Config) -> &BTreeMap<String, Vec<ConfigSource>> {
+        &conf.sources.plans
+    }
 }
 
 impl EtcdConfigLoader for PlimUser {
     fn load_into_config(&self, conf: &mut Config, name: String) {
         conf.users.insert(name, self.clone());
     }
+    fn remove_from_config(conf: &mut Config, name: &str) {
+        conf.users.remove(name);
+        conf.sources.users.remove(name);
+    }
+    fn sources(conf: &mut Config) -> &mut BTreeMap<String, Vec<ConfigSource>> {
+        &mut conf.sources.users
+    }
+    fn from_config(conf: &Config, name: &str) -> Option<Self> {
+        conf.users.get(name).cloned()
+    }
+    fn config_sources(conf: &Config) -> &BTreeMap<String, Vec<ConfigSource>> {
+        &conf.sources.users
+    }
+}
+
+/// Name a plan or user stored under `prefix_path` gets in the config
+pub fn etcd_config_name(etcd_name: &str, prefix_path: &str, key: &str) -> String {
+    let key = key.strip_prefix(prefix_path).unwrap_or(key);
+    format!("{}_{}", etcd_name, key.trim_start_matches('/').trim_end_matches('/'))
 }
 
-pub async fn load_with_etcd_configs<T: for<'de> Deserialize<'de> + EtcdConfigLoader>(mut conf: Config, prefix_path: &str, etcd_name: &str) -> Result<Config, Error> {
-    let etcd_data = load_etcd_config_by_prefix_path(conf.clone(), 
-        prefix_path, etcd_name).await.context("Failed to load etcd config data")?;
-    for kv in etcd_data.kvs() {
-        let key_bytes = kv.key().to_vec();
-        let key = String::from_utf8_lossy(&key_bytes).replace(&conf.etcd_configs.plans.key_prefix_path, "");
-        let value_bytes = kv.value().to_vec();
-        let value = String::from_utf8_lossy(&value_bytes);
-        info!("Loading plan: {:?}", key);
-        let yaml_config: T = serde_yaml::from_str(&value).context("Failed to parse plan")?;
-        let plan_name = format!("{}_{}", etcd_name, key.trim_start_matches('/').trim_end_matches('/'));
-        yaml_config.load_into_config(&mut conf, plan_name);
+/// Config name and parsed value of one key below `etcd_config`, the name is also returned for keys which fail
+pub fn parse_etcd_config<T: DeserializeOwned>(etcd_config: &EtcdConfig, kv: &KeyValue) -> (String, Result<T, Error>) {
+    let key = String::from_utf8_lossy(kv.key());
+    let name = etcd_config_name(&etcd_config.etcd_name, &etcd_config.key_prefix_path, &key);
+    if name.len() == etcd_config.etcd_name.len() + 1 {
+        return (name, Err(anyhow::anyhow!("Etcd key {} has no name below {}", key, etcd_config.key_prefix_path)));
     }
-    Ok(conf)
+    let value = serde_yaml::from_slice::<serde_yaml::Value>(kv.value()).map_err(Error::new)
+        .and_then(|value| {
+            reject_extends(&name, &value)?;
+            Ok(serde_yaml::from_value(value)?)
+        })
+        .context(format!("Failed to parse etcd key {}", key));
+    (name, value)
+}
+
+/// Parsed entries of an etcd prefix with the key each came from, keys which failed are logged and kept in `skipped`
+pub struct EtcdPrefixEntries<T> {
+    pub revision: i64,
+    pub values: Vec<(String, T, ConfigSource)>,
+    pub skipped: Vec<String>,
+}
+
+/// Read and parse every key below `etcd_config`
+pub async fn load_etcd_prefix<T: DeserializeOwned>(client: &mut Client, etcd_config: &EtcdConfig) -> Result<EtcdPrefixEntries<T>, etcd_client::Error> {
+    let (kvs, revision) = get_etcd_prefix(client, &etcd_config.key_prefix()).await?;
+    let mut entries = EtcdPrefixEntries { revision, values: Vec::new(), skipped: Vec::new() };
+    for kv in &kvs {
+        match parse_etcd_config::<T>(etcd_config, kv) {
+            (name, Ok(value)) => entries.values.push((name, value, ConfigSource::etcd(&etcd_config.etcd_name, kv))),
+            (name, Err(e)) => {
+                error!("Skipping etcd config {}: {:#}", name, e);
+                entries.skipped.push(name);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Every key below `prefix` and the revision they were read at.
+/// Keys are read `MAX_ETCD_KEYS_COUNT` at a time, each page continues after the last key of the one before
+pub async fn get_etcd_prefix(client: &mut Client, prefix: &str) -> Result<(Vec<KeyValue>, i64), etcd_client::Error> {
+    let range_end = prefix_range_end(prefix.as_bytes());
+    let mut kvs = Vec::new();
+    let mut start = prefix.as_bytes().to_vec();
+    let mut revision = 0;
+    loop {
+        let mut options = GetOptions::new().with_range(range_end.clone()).with_limit(MAX_ETCD_KEYS_COUNT);
+        // later pages read the same revision, so keys changed in between don't mix two states
+        if revision > 0 {
+            options = options.with_revision(revision);
+        }
+        let mut response = client.get(start.clone(), Some(options)).await?;
+        if revision == 0 {
+            revision = response.header().map(|header| header.revision()).unwrap_or_default();
+        }
+        let more = response.more();
+        let page = response.take_kvs();
+        let Some(last) = page.last() else { break };
+        start = last.key().to_vec();
+        start.push(0);
+        kvs.extend(page);
+        if !more {
+            break;
+        }
+    }
+    Ok((kvs, revision))
+}
+
+/// First key after every key starting with `prefix`, like etcd's own prefix option computes it
+fn prefix_range_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return end;
+        }
+    }
+    // a prefix of only 0xff bytes covers every key after it
+    vec![0]
 }
 
-async fn load_etcd_config_by_prefix_path(conf: Config, prefix_path: &str, etcd_name: &str) -> Result<GetResponse, Error> {
-    let opts = GetOptions::new()
-    .with_prefix()
-    .with_limit(MAX_ETCD_KEYS_COUNT);
-    let etcd_data_map = match conf.etcd_data_map.get(etcd_name) {
-        Some(etcd_data_map) => etcd_data_map,
-        None => return Err(anyhow::anyhow!("Etcd data map not found")),
+pub async fn load_with_etcd_configs<T: DeserializeOwned + EtcdConfigLoader>(mut conf: Config, etcd_config: &EtcdConfig, etcd: &EtcdManager) -> Result<Config, Error> {
+    let mut client = etcd.client(&etcd_config.etcd_name).await?;
+    let entries = match load_etcd_prefix::<T>(&mut client, etcd_config).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            etcd.report_failure(&etcd_config.etcd_name, &e).await;
+            return Err(Error::new(e).context(format!("Failed to read etcd {} prefix {}", etcd_config.etcd_name, etcd_config.key_prefix())));
+        }
     };
-    let etcd_client = Client::connect(etcd_data_map.address.clone(), None).await.context("Failed to connect to etcd")?;
-    let etcd_data = etcd_client.clone().get(prefix_path, Some(opts)).await.context("Failed to load etcd config data response")?;
-    // trace!("Etcd data: {:?}", etcd_data);
-    Ok(etcd_data)
+    let loaded = entries.values.len();
+    for (name, value, source) in entries.values {
+        value.load_from_etcd(&mut conf, name, source);
+    }
+    if entries.skipped.is_empty() {
+        info!("Loaded {} configs from etcd {} prefix {}", loaded, etcd_config.etcd_name, etcd_config.key_prefix());
+    } else {
+        warn!("Loaded {} configs from etcd {} prefix {}, skipped {}: {}", loaded, etcd_config.etcd_name,
+            etcd_config.key_prefix(), entries.skipped.len(), entries.skipped.join(", "));
+    }
+    Ok(conf)
 }
 
 
-#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[derive(Debug, Default, Serialize, Deserialize, Clone, JsonSchema)]
 // #[serde(deny_unknown_fields)]
 pub struct Config {
     pub plim: PlimConfig,
@@ -116,21 +329,78 @@ pub struct Config {
     #[serde(default = "default_etcd_configs")]
     pub etcd_configs: EtcdConfigs,
     pub plans: HashMap<String, PlimPlan>,
+    #[serde(default)]
+    pub secrets: SecretsConfig,
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub sources: ConfigSources,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct EtcdConfigs {
     pub plans: EtcdConfig,
     pub users: EtcdConfig,
     pub ansible_inventories: EtcdConfig,
+    pub migrations: Option<EtcdConfig>, // applied migration versions, defaults to /plim/migrations on the plans etcd
 }
 
-#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct EtcdConfig {
     pub etcd_name: String,
     pub key_prefix_path: String,
 }
 
+impl EtcdConfig {
+    /// `key_prefix_path` with one trailing slash, so `/plans` doesn't match `/plans-old/...`
+    pub fn key_prefix(&self) -> String {
+        format!("{}/", self.key_prefix_path.trim_end_matches('/'))
+    }
+}
+
+/// Providers for `<provider>:<key>` token variables, their own settings read env variables only
+#[derive(Debug, Default, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SecretsConfig {
+    pub cache_ttl_secs: Option<u64>, // how long a fetched secret is used before it is fetched again
+    pub file: Option<FileSecretsConfig>,
+    pub etcd: Option<EtcdSecretsConfig>,
+    pub vault: Option<VaultSecretsConfig>,
+    pub registry: Option<HashMap<String, RegisteredSecret>>, // when set plans can only use these secrets
+}
+
+/// Secret plans name in their token variables and the plans allowed to use it
+#[derive(Debug, Default, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RegisteredSecret {
+    pub source: Option<String>, // token variable with the value, the secret name by default
+    #[serde(default)]
+    pub plans: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>, // plans in any of these groups may use the secret
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct FileSecretsConfig {
+    pub dir: String, // `file:<name>` reads `<dir>/<name>`
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct EtcdSecretsConfig {
+    pub etcd_name: String,
+    pub key_prefix_path: String, // `etcd:<key>` reads `<key_prefix_path>/<key>`
+    pub password_var: String, // env variable with the ansible vault password the values are encrypted with
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct VaultSecretsConfig {
+    pub address: String,
+    pub token_var: String, // env variable with the vault token
+    pub mount: Option<String>, // KV version 2 mount, `secret` by default
+}
+
 fn default_etcd_map() -> HashMap<String, EtcdDataMap> {
     HashMap::new()
 }
@@ -139,16 +409,80 @@ fn default_etcd_configs() -> EtcdConfigs {
     EtcdConfigs::default()
 }
 
-#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq, JsonSchema)]
 pub struct EtcdDataMap {
     pub address: Vec<String>,
+    pub tls: Option<EtcdTlsConfig>,
+    pub user: Option<String>,
+    pub password_var: Option<String>, // env variable with the password of `user`
+    pub connect_timeout_secs: Option<u64>,
+    pub request_timeout_secs: Option<u64>,
+}
+
+/// PEM files, `cert_path` and `key_path` together enable client certificate auth
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq, JsonSchema)]
+pub struct EtcdTlsConfig {
+    pub ca_cert_path: Option<String>,
+    pub cert_path: Option<String>,
+    pub key_path: Option<String>,
+    pub domain_name: Option<String>, // name checked against the server certificate instead of the address host
+}
+
+impl EtcdDataMap {
+    pub async fn connect(&self, tokens: &GitlabTokens) -> Result<Client, Error> {
+        Client::connect(self.address.clone(), Some(self.connect_options(tokens)?)).await.context("Failed to connect to etcd")
+    }
+
+    fn connect_options(&self, tokens: &GitlabTokens) -> Result<ConnectOptions, Error> {
+        let mut options = ConnectOptions::new();
+        if let Some(tls) = &self.tls {
+            options = options.with_tls(tls.tls_options()?);
+        }
+        match (&self.user, &self.password_var) {
+            (Some(user), Some(password_var)) => {
+                let password = tokens.lookup(password_var)
+                    .ok_or_else(|| anyhow::anyhow!("Etcd password variable {} is not set", password_var))?;
+                options = options.with_user(user, password);
+            }
+            (None, None) => {}
+            _ => return Err(anyhow::anyhow!("Etcd user and password_var must be set together")),
+        }
+        if let Some(secs) = self.connect_timeout_secs {
+            options = options.with_connect_timeout(Duration::from_secs(secs));
+        }
+        if let Some(secs) = self.request_timeout_secs {
+            options = options.with_timeout(Duration::from_secs(secs));
+        }
+        Ok(options)
+    }
 }
 
-#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+impl EtcdTlsConfig {
+    fn tls_options(&self) -> Result<TlsOptions, Error> {
+        let read = |path: &str| fs::read(path).context(format!("Failed to read etcd tls file {}", path));
+        let mut tls = TlsOptions::new();
+        if let Some(ca_cert_path) = &self.ca_cert_path {
+            tls = tls.ca_certificate(Certificate::from_pem(read(ca_cert_path)?));
+        }
+        match (&self.cert_path, &self.key_path) {
+            (Some(cert_path), Some(key_path)) => tls = tls.identity(Identity::from_pem(read(cert_path)?, read(key_path)?)),
+            (None, None) => {}
+            _ => return Err(anyhow::anyhow!("Etcd tls cert_path and key_path must be set together")),
+        }
+        if let Some(domain_name) = &self.domain_name {
+            tls = tls.domain_name(domain_name);
+        }
+        Ok(tls)
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct PlimConfig {
     pub listen_address: String,
     pub jwt_token_duration_hours: i64,
     pub webhook_token_length: u8,
+    pub watch_config: Option<bool>, // reload the config when a file under the config paths changes
+    pub jwt_secret_var: Option<String>, // token variable the JWTs are signed with, TOKEN_SECRET by default
 }
 
 #[derive(Debug, Default, Deserialize, Serialize)]
@@ -156,12 +490,14 @@ pub struct Database {
     pub url: String,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct GitlabConfig {
     pub api_endpoint: String,
+    pub file_cache_ttl_secs: Option<u64>, // how long a cached repository file is served before it is revalidated
+    pub file_cache_max_entries: Option<usize>, // cached repository files kept, the least recently used one goes first
 }
 
-#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct PlimUser {
     pub full_name: String,
     pub email: String,
@@ -193,7 +529,8 @@ impl PlimUser {
 }
 
 // TODO: make two enum for plan types: one for plan types and one for plan types with ansible
-#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct PlimPlan {
     #[serde(rename = "type")]
     pub type_name: PlanType,
@@ -205,7 +542,8 @@ pub struct PlimPlan {
     pub views: Vec<PlimPlanViewType>
 }
 
-#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct PlimPlanWebhook {
     pub name: String,
     pub trigger_token: String,
@@ -215,7 +553,7 @@ pub struct PlimPlanWebhook {
     pub ansible: Option<AnsibleConfig>,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq, Eq, JsonSchema)]
 pub enum WebhookType {
     #[default]
     #[serde(rename = "static")]
@@ -224,7 +562,7 @@ pub enum WebhookType {
     Dynamic,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq, Eq, JsonSchema)]
 pub enum PlanType {
     #[default]
     #[serde(rename = "gitlab-ansible-base64")]
@@ -237,9 +575,11 @@ pub enum PlanType {
     GitlabNative,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct PlimPlanGitlabSettings {
     #[serde(rename = "projectId", alias = "project_id")]
+    #[schemars(rename = "project_id")] // the name config files use
     pub project_id: u64,
     pub token_var: String,
     #[serde(rename = "ref")]
@@ -250,7 +590,8 @@ pub struct PlimPlanGitlabSettings {
     pub execute_api_type: ExecuteApiType,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct RefSelect {
     pub ref_select_enabled: bool,
     pub branch_enabled: bool,
@@ -273,7 +614,7 @@ fn default_allow_ref_select() -> RefSelect {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 #[derive(Default)]
 pub enum ExecuteApiType {
     #[default]
@@ -286,7 +627,7 @@ pub enum ExecuteApiType {
 
 
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 #[serde(untagged)]
 pub enum AnyValue {
     String(String),
@@ -311,7 +652,7 @@ impl Default for AnyValue {
 }
 
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 #[serde(untagged)]
 pub enum PlimPlanViewType {
     Multi(MultiValueView),
@@ -356,13 +697,16 @@ impl GetPlanViewData for PlimPlanViewType {
     }
 }
 
-#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct OneValueView {
     pub text: String,
     #[serde(rename = "type")]
     pub type_name: String,
     pub key: Option<AnyValue>,
     pub value: Option<AnyValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>, // date-picker display format
     #[serde(default = "default_data_source")]
     pub data_source: Option<DataSource>,
 }
@@ -382,7 +726,7 @@ impl GetPlanViewData for OneValueView {
 }
 
 // always have data (in other same as one value view)
-#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct MultiValueView {
     pub text: String,
@@ -402,20 +746,25 @@ fn default_data_source() -> Option<DataSource> {
         key_path: String::default(),
     })
 }
-#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq, Eq, JsonSchema)]
 pub enum DataSourceType {
     #[default]
     #[serde(rename = "static_config")]
     StaticConfig,
     #[serde(rename = "etcd")]
     Etcd,
+    #[serde(rename = "ansible_playbook_tags")]
+    AnsiblePlaybookTags, // tags found in the plan playbook
 }
 
-#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct DataSource {
     #[serde(rename = "type")]
     pub source_type: DataSourceType,
+    #[serde(default)]
     pub etcd_name: String,
+    #[serde(default)]
     pub key_path: String,
 }
 
@@ -433,7 +782,7 @@ impl GetPlanViewData for MultiValueView {
     }
 }
 // checkbox-list only
-#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct CheckboxListView {
     pub text: String,
@@ -455,7 +804,7 @@ impl GetPlanViewData for CheckboxListView {
 }
 
 // active-choice like views
-#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct DynamicView {
     pub text: String,
@@ -469,15 +818,21 @@ pub struct DynamicView {
     pub data_source: Option<DataSource>,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone, JsonSchema)]
 #[derive(Merge)]
+#[schemars(deny_unknown_fields)]
 // default values for plan, can be overridden after
 pub struct AnsibleConfig {
     pub is_inventory_inline: Option<bool>,
     pub backend_inventory: AnsibleBackendType,
+    pub backend_inventories: Option<Vec<AnsibleBackendType>>, // merged after backend_inventory into one inventory
+    pub inventory_conflict_policy: Option<InventoryConflictPolicy>, // which value a var defined by several backends keeps
     pub playbook: String,
+    pub playbook_source: Option<PlaybookSource>, // where plim reads the playbook from to offer its tags, gitlab by default
     pub inventory: String,
+    pub inventories: Option<Vec<String>>, // additional -i, --inventory INVENTORY
     pub extra_vars: Option<HashMap<String, String>>,
+    pub extra_vars_files: Option<Vec<String>>, // -e @FILE
     pub private_key: Option<String>, // --private-key, --key-file PRIVATE_KEY_FILE
     pub remote_user: Option<String>,
     pub connection: Option<String>, // -c, --connection CONNECTION (default=ssh)
@@ -500,6 +855,10 @@ pub struct AnsibleConfig {
     pub limit_hosts: Option<Vec<String>>, // --limit LIMIT
     pub verbosity: Option<u64>, // -v, --verbose
     pub vault_password_file: Option<String>, // --vault-password-file VAULT_PASSWORD_FILE
+    pub vault_ids: Option<Vec<String>>, // --vault-id VAULT_IDS
+    #[serde(alias = "ask_vault_password")]
+    pub ask_vault_pass: Option<bool>, // -J, --ask-vault-pass
+    pub vault_password_var: Option<String>, // env variable with the vault password for inventory !vault values
     pub syntax_check: Option<bool>, // --syntax-check
     pub diff: Option<bool>, // -D, --diff
     pub check: Option<bool>, // -C, --check don't make any changes; instead, try to predict some of the changes that may occur
@@ -507,13 +866,14 @@ pub struct AnsibleConfig {
     pub list_tasks: Option<bool>, // --list-tasks
     pub list_tags: Option<bool>, // --list-tags
     pub start_at_task: Option<String>, // --start-at-task TASK_NAME
+    pub flush_cache: Option<bool>, // --flush-cache
     pub become_password_file: Option<String>, // --become-password-file, --become-pass-file BECOME_PASSWORD_FILE
     pub connection_password_file: Option<String>, // --connection-password-file, --conn-pass-file CONNECTION_PASSWORD_FILE
     pub module_path: Option<String>, // -M, --module-path MODULE_PATH
     pub version: Option<bool>, // show program's version number,  executable location and exit
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 #[serde(untagged)]
 pub enum AnsibleBackendType {
     Local(AnsibleLocalBackend),
@@ -527,8 +887,34 @@ impl Default for AnsibleBackendType {
     }
 }
 
+impl AnsibleConfig {
+    /// Every inventory backend of the plan in merge order
+    pub fn inventory_backends(&self) -> Vec<&AnsibleBackendType> {
+        std::iter::once(&self.backend_inventory).chain(self.backend_inventories.iter().flatten()).collect()
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq, Eq, JsonSchema)]
+pub enum InventoryConflictPolicy {
+    #[default]
+    #[serde(rename = "last_wins")]
+    LastWins, // like ansible with several -i, the later inventory overrides
+    #[serde(rename = "first_wins")]
+    FirstWins,
+    #[serde(rename = "error")]
+    Error,
+}
 
-#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq, Eq, JsonSchema)]
+pub enum PlaybookSource {
+    #[default]
+    #[serde(rename = "gitlab")]
+    Gitlab, // plan gitlab project and ref
+    #[serde(rename = "local")]
+    Local,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone, JsonSchema)]
 pub enum AnsibleInventoryType {
     #[default]
     #[serde(rename = "local")]
@@ -539,10 +925,24 @@ pub enum AnsibleInventoryType {
     Etcd,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+// backends are untagged, a schema which pins `type` keeps editors from matching the wrong one
+fn local_backend_type_schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+    schemars::json_schema!({"const": "local"})
+}
+
+fn gitlab_backend_type_schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+    schemars::json_schema!({"const": "gitlab"})
+}
+
+fn etcd_backend_type_schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+    schemars::json_schema!({"const": "etcd"})
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct AnsibleGitlabBackend {
     #[serde(rename = "type")]
+    #[schemars(schema_with = "gitlab_backend_type_schema")]
     pub type_name: AnsibleInventoryType,
     pub token_var: Option<String>,
     pub ref_name: Option<String>,
@@ -550,24 +950,45 @@ pub struct AnsibleGitlabBackend {
     pub file_path: String,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct AnsibleEtcdBackend {
     #[serde(rename = "type")]
+    #[schemars(schema_with = "etcd_backend_type_schema")]
     pub type_name: AnsibleInventoryType,
     pub etcd_name: String,
     pub key_path: String,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct AnsibleLocalBackend {
     #[serde(rename = "type")]
+    #[schemars(schema_with = "local_backend_type_schema")]
     pub type_name: AnsibleInventoryType,
     pub file_path: String,
 }
 
 impl Config {
+    /// Checks a config has to pass before it replaces the running one
+    pub fn validate(&self, tokens: &GitlabTokens) -> Result<(), Error> {
+        let mut issues = check_config(self, tokens);
+        // a broken etcd plan or user only breaks itself, it must not block the config files from reloading
+        let etcd_paths = self.sources.etcd_paths();
+        for issue in issues.iter_mut().filter(|issue| etcd_paths.iter().any(|path| path_within(&issue.path, path))) {
+            issue.severity = IssueSeverity::Warning;
+        }
+        for issue in issues.iter().filter(|issue| issue.severity == IssueSeverity::Warning) {
+            warn!("Config {}", issue);
+        }
+        let errors: Vec<String> = issues.iter().filter(|issue| issue.severity == IssueSeverity::Error).map(ToString::to_string).collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("Invalid config: {}", errors.join("; ")))
+        }
+    }
+
     pub fn get_user(&self, username: &str) -> Result<&PlimUser, anyhow::Error> {
         self.users.get(username).ok_or_else(|| anyhow::anyhow!("User '{}' not found", username))
     }
@@ -589,3 +1010,251 @@ impl Config {
 
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+plim:
+  listen_address: "127.0.0.1:3000"
+  jwt_token_duration_hours: 24
+  webhook_token_length: 12
+gitlab:
+  api_endpoint: "https://gitlab.example/api/v4"
+admins: [admin]
+users: {}
+plans: {}
+etcd_data_map:
+  down:
+    address: ["127.0.0.1:1"]
+    connect_timeout_secs: 1
+etcd_configs:
+  plans:
+    etcd_name: down
+    key_prefix_path: /plans
+  users:
+    etcd_name: down
+    key_prefix_path: /users
+  ansible_inventories:
+    etcd_name: down
+    key_prefix_path: /ansible
+"#;
+
+    const PLAN: &str = r#"
+type: gitlab-native
+groups: [ops]
+gitlab:
+  project_id: 1
+  token_var: DEPLOY_TOKEN
+  ref: main
+  execute_api_type: create
+views: []
+"#;
+
+    fn etcd_source(key: &str) -> ConfigSource {
+        ConfigSource::Etcd { etcd_name: "down".to_string(), key: key.to_string(), revision: 7 }
+    }
+
+    #[tokio::test]
+    async fn reload_keeps_etcd_entries_when_etcd_is_unreachable() {
+        let dir = std::env::temp_dir().join(format!("plim-reload-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.yml");
+        fs::write(&path, CONFIG).unwrap();
+
+        let mut current: Config = serde_yaml::from_str(CONFIG).unwrap();
+        let plan: PlimPlan = serde_yaml::from_str(PLAN).unwrap();
+        plan.load_from_etcd(&mut current, "down_deploy".to_string(), etcd_source("/plans/deploy"));
+        let user = PlimUser { full_name: "Ops".to_string(), email: "ops@example.com".to_string(), groups: vec!["ops".to_string()], disabled: true, ..Default::default() };
+        user.load_from_etcd(&mut current, "down_ops".to_string(), etcd_source("/users/ops"));
+        // plans and users of the files are not taken over from the running config
+        current.plans.insert("stale".to_string(), plan.clone());
+
+        let tokens = GitlabTokens::new();
+        let etcd = EtcdManager::new(&current.etcd_data_map, tokens.clone());
+        let reloaded = reload(&current, &[path.to_string_lossy().to_string()], &tokens, &etcd).await.unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(reloaded.plans.keys().collect::<Vec<_>>(), vec!["down_deploy"]);
+        assert_eq!(reloaded.sources.plans["down_deploy"], vec![etcd_source("/plans/deploy")]);
+        assert_eq!(reloaded.users.keys().collect::<Vec<_>>(), vec!["down_ops"]);
+        assert_eq!(reloaded.sources.users["down_ops"], vec![etcd_source("/users/ops")]);
+    }
+
+    #[tokio::test]
+    async fn rejected_reload_keeps_the_running_secrets() {
+        let dir = std::env::temp_dir().join(format!("plim-reload-secrets-{}", std::process::id()));
+        let secrets_dir = dir.join("secrets");
+        fs::create_dir_all(&secrets_dir).unwrap();
+        fs::write(secrets_dir.join("admin_name"), "Admin").unwrap();
+        let path = dir.join("config.yml");
+        let secrets = format!("secrets:\n  file:\n    dir: {}\n", secrets_dir.display());
+        // an enabled user without a password hash fails validation
+        let rejected = format!("{}{}users:\n  admin: {{full_name: \"${{file:admin_name}}\", email: a@example.com, groups: [], hashed_password: \"\", disabled: false}}\n",
+            CONFIG.replace("users: {}\n", ""), secrets);
+        fs::write(&path, &rejected).unwrap();
+
+        let current: Config = serde_yaml::from_str(CONFIG).unwrap();
+        let tokens = GitlabTokens::with_vars(&[]);
+        let etcd = EtcdManager::new(&current.etcd_data_map, tokens.clone());
+        let paths = [path.to_string_lossy().to_string()];
+        assert!(reload(&current, &paths, &tokens, &etcd).await.is_err());
+        assert_eq!(tokens.lookup("file:admin_name"), None);
+
+        fs::write(&path, rejected.replace("disabled: false", "disabled: true")).unwrap();
+        let reloaded = reload(&current, &paths, &tokens, &etcd).await.unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(reloaded.users["admin"].full_name, "Admin");
+        assert_eq!(tokens.lookup("file:admin_name").as_deref(), Some("Admin"));
+    }
+
+    #[test]
+    fn plan_schema_denies_unknown_fields() {
+        let schema = config_schema(SchemaKind::Plan);
+        assert_eq!(schema["additionalProperties"], false);
+        let open: Vec<&String> = schema["$defs"].as_object().unwrap().iter()
+            .filter(|(_, definition)| definition.get("properties").is_some() && definition["additionalProperties"] != false)
+            .map(|(name, _)| name)
+            .collect();
+        assert!(open.is_empty(), "open definitions: {:?}", open);
+    }
+
+    #[test]
+    fn view_schemas_match_one_view_kind() {
+        let schema = config_schema(SchemaKind::Plan);
+        let views = schema["$defs"]["PlimPlanViewType"]["anyOf"].as_array().unwrap();
+        let matching = |view: serde_json::Value| -> usize {
+            views.iter()
+                .map(|variant| &schema["$defs"][variant["$ref"].as_str().unwrap().trim_start_matches("#/$defs/")])
+                .filter(|variant| {
+                    let properties = variant["properties"].as_object().unwrap();
+                    let required = variant["required"].as_array().map(Vec::as_slice).unwrap_or_default();
+                    let fields = view.as_object().unwrap();
+                    fields.keys().all(|field| properties.contains_key(field))
+                        && required.iter().all(|field| fields.contains_key(field.as_str().unwrap()))
+                })
+                .count()
+        };
+        let one = serde_json::json!({"text": "Limit", "type": "input-field", "key": "LIMIT"});
+        let multi = serde_json::json!({"text": "Env", "type": "select", "key": "ENV", "data": ["dev"]});
+        let misspelled = serde_json::json!({"text": "Env", "type": "select", "key": "ENV", "dat": ["dev"]});
+        assert_eq!(matching(one), 1);
+        assert_eq!(matching(multi), 1);
+        assert_eq!(matching(misspelled), 0);
+    }
+
+    /// Enough of JSON schema for the keywords schemars emits
+    fn schema_accepts(root: &serde_json::Value, schema: &serde_json::Value, value: &serde_json::Value) -> bool {
+        use serde_json::Value;
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            return schema_accepts(root, root.pointer(reference.trim_start_matches('#')).unwrap(), value);
+        }
+        if let Some(variants) = schema.get("anyOf").and_then(Value::as_array)
+            && !variants.iter().any(|variant| schema_accepts(root, variant, value)) {
+            return false;
+        }
+        if let Some(condition) = schema.get("if") {
+            let branch = if schema_accepts(root, condition, value) { schema.get("then") } else { schema.get("else") };
+            if branch.is_some_and(|branch| !schema_accepts(root, branch, value)) {
+                return false;
+            }
+        }
+        if schema.get("const").is_some_and(|constant| constant != value)
+            || schema.get("enum").and_then(Value::as_array).is_some_and(|values| !values.contains(value)) {
+            return false;
+        }
+        let type_matches = |name: &str| match name {
+            "object" => value.is_object(),
+            "array" => value.is_array(),
+            "string" => value.is_string(),
+            "integer" => value.is_u64() || value.is_i64(),
+            "number" => value.is_number(),
+            "boolean" => value.is_boolean(),
+            "null" => value.is_null(),
+            _ => true,
+        };
+        match schema.get("type") {
+            Some(Value::String(name)) if !type_matches(name) => return false,
+            Some(Value::Array(names)) if !names.iter().filter_map(Value::as_str).any(type_matches) => return false,
+            _ => {}
+        }
+        if let Some(object) = value.as_object() {
+            let properties = schema.get("properties").and_then(Value::as_object);
+            let required = schema.get("required").and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default();
+            if required.iter().filter_map(Value::as_str).any(|field| !object.contains_key(field)) {
+                return false;
+            }
+            for (field, field_value) in object {
+                let accepted = match (properties.and_then(|properties| properties.get(field)), schema.get("additionalProperties")) {
+                    (Some(property), _) => schema_accepts(root, property, field_value),
+                    (None, Some(Value::Bool(false))) => false,
+                    (None, Some(additional)) if additional.is_object() => schema_accepts(root, additional, field_value),
+                    (None, _) => true,
+                };
+                if !accepted {
+                    return false;
+                }
+            }
+        }
+        if let (Some(items), Some(array)) = (schema.get("items"), value.as_array()) {
+            return array.iter().all(|item| schema_accepts(root, items, item));
+        }
+        true
+    }
+
+    const EXTENDING_PLAN: &str = r#"
+extends: ansible-prod
+gitlab:
+  ref: release
+ansible:
+  playbook: web.yml
+views:
+  - key: LIMIT
+    value: web
+"#;
+
+    #[test]
+    fn plan_file_schema_accepts_extending_plans() {
+        let schema = config_schema(SchemaKind::PlanFile);
+        let accepts = |yaml: &str| schema_accepts(&schema, &schema, &serde_yaml::from_str(yaml).unwrap());
+        assert!(accepts(EXTENDING_PLAN));
+        assert!(accepts(PLAN));
+        // without extends nothing fills in the missing fields
+        assert!(!accepts(&EXTENDING_PLAN.replace("extends: ansible-prod", "")));
+        assert!(!accepts(&EXTENDING_PLAN.replace("ref: release", "reff: release")));
+        // lists other than views are replaced whole, their items stay complete
+        assert!(!accepts(&format!("{}webhooks:\n  - name: deploy\n", EXTENDING_PLAN)));
+
+        let plan_schema = config_schema(SchemaKind::Plan);
+        assert!(schema_accepts(&plan_schema, &plan_schema, &serde_yaml::from_str(PLAN).unwrap()));
+        assert!(!schema_accepts(&plan_schema, &plan_schema, &serde_yaml::from_str(EXTENDING_PLAN).unwrap()));
+    }
+
+    #[test]
+    fn config_schema_accepts_templates_and_extends() {
+        let schema = config_schema(SchemaKind::Config);
+        let config: serde_json::Value = serde_yaml::from_str(&format!(
+            "templates:\n  ansible-prod:\n    groups: [ops]\nplans:\n  deploy-web:\n{}",
+            EXTENDING_PLAN.lines().map(|line| format!("    {}\n", line)).collect::<String>(),
+        )).unwrap();
+        assert!(schema_accepts(&schema, &schema["properties"]["templates"], &config["templates"]));
+        assert!(schema_accepts(&schema, &schema["properties"]["plans"], &config["plans"]));
+        let incomplete = serde_json::json!({"deploy-web": {"groups": ["ops"]}});
+        assert!(!schema_accepts(&schema, &schema["properties"]["plans"], &incomplete));
+    }
+
+    #[test]
+    fn keep_etcd_entries_only_takes_the_configured_etcd() {
+        let mut current: Config = serde_yaml::from_str(CONFIG).unwrap();
+        let plan: PlimPlan = serde_yaml::from_str(PLAN).unwrap();
+        plan.load_from_etcd(&mut current, "down_deploy".to_string(), etcd_source("/plans/deploy"));
+        plan.load_from_etcd(&mut current, "other_deploy".to_string(), ConfigSource::Etcd { etcd_name: "other".to_string(), key: "/plans/deploy".to_string(), revision: 3 });
+        current.plans.insert("from_file".to_string(), plan);
+        current.sources.plans.insert("from_file".to_string(), vec![ConfigSource::File { path: "plans/deploy.yml".to_string() }]);
+
+        let mut conf: Config = serde_yaml::from_str(CONFIG).unwrap();
+        assert_eq!(PlimPlan::keep_etcd_entries(&current, &mut conf, "down"), 1);
+        assert_eq!(conf.plans.keys().collect::<Vec<_>>(), vec!["down_deploy"]);
+    }
+}
@@ -0,0 +1,358 @@
+use std::collections::{BTreeSet, HashMap};
+
+use serde_yaml::{Mapping, Value};
+use thiserror::Error;
+
+pub const TEMPLATES_KEY: &str = "templates";
+const PLANS_KEY: &str = "plans";
+pub const EXTENDS_KEY: &str = "extends";
+const VIEWS_KEY: &str = "views";
+const VIEW_KEY: &str = "key";
+
+#[derive(Error, Debug)]
+pub enum ExtendsError {
+    #[error("{from} extends unknown template or plan {parent}")]
+    Unknown { from: String, parent: String },
+    #[error("Circular extends: {}", .0.join(" -> "))]
+    Circular(Vec<String>),
+    #[error("{0}.extends is not a template or plan name")]
+    InvalidName(String),
+    #[error("{0} uses extends, which is only resolved for plans in config files")]
+    NotSupported(String),
+}
+
+impl ExtendsError {
+    /// Config path of the `extends` which failed
+    pub fn path(&self) -> String {
+        match self {
+            ExtendsError::Unknown { from, .. } | ExtendsError::InvalidName(from) | ExtendsError::NotSupported(from) => format!("{}.{}", from, EXTENDS_KEY),
+            ExtendsError::Circular(chain) => format!("{}.{}", chain[0], EXTENDS_KEY),
+        }
+    }
+}
+
+/// Merge every plan over the template or plan it `extends` and drop the `templates` section.
+/// `extends` looks in `templates` first, then in `plans`
+pub fn resolve_extends(config: &mut Value) -> Result<(), ExtendsError> {
+    let Value::Mapping(config) = config else {
+        return Ok(());
+    };
+    let templates = match config.remove(TEMPLATES_KEY) {
+        Some(Value::Mapping(templates)) => templates,
+        _ => Mapping::new(),
+    };
+    let Some(Value::Mapping(plans)) = config.get(PLANS_KEY) else {
+        return Ok(());
+    };
+    let mut resolver = Resolver { templates: &templates, plans, resolved: HashMap::new(), chain: Vec::new() };
+    let mut resolved_plans = Mapping::new();
+    for name in plans.keys() {
+        let plan = match name {
+            Value::String(name) => resolver.resolve(&Source::Plan(name.clone()))?,
+            _ => plans[name].clone(),
+        };
+        resolved_plans.insert(name.clone(), plan);
+    }
+    config.insert(Value::from(PLANS_KEY), Value::Mapping(resolved_plans));
+    Ok(())
+}
+
+/// Plans stored in etcd are not merged with the config files, their `extends` is rejected instead of ignored
+pub fn reject_extends(path: &str, plan: &Value) -> Result<(), ExtendsError> {
+    match plan.get(EXTENDS_KEY) {
+        Some(_) => Err(ExtendsError::NotSupported(path.to_string())),
+        None => Ok(()),
+    }
+}
+
+/// Open the plan schema at `plan_pointer` to plans in config files: `extends` is allowed and every field a parent
+/// can supply is optional. A plan without `extends` still needs the fields the plan schema requires
+pub fn allow_extends_in_schema(schema: &mut serde_json::Value, plan_pointer: &str) {
+    let Some(plan) = schema.pointer(plan_pointer).cloned() else {
+        return;
+    };
+    let mut merged = BTreeSet::new();
+    for (name, property) in plan["properties"].as_object().into_iter().flatten() {
+        merged_definitions(schema, property, &mut merged);
+        if name == VIEWS_KEY {
+            merged_definitions(schema, &property["items"], &mut merged);
+        }
+    }
+    for name in merged {
+        if let Some(definition) = schema["$defs"][name.as_str()].as_object_mut() {
+            definition.remove("required");
+        }
+    }
+    let Some(plan) = schema.pointer_mut(plan_pointer).and_then(serde_json::Value::as_object_mut) else {
+        return;
+    };
+    if let Some(required) = plan.remove("required") {
+        plan.insert("if".to_string(), serde_json::json!({ "required": [EXTENDS_KEY] }));
+        plan.insert("else".to_string(), serde_json::json!({ "required": required }));
+    }
+    if let Some(properties) = plan.get_mut("properties").and_then(serde_json::Value::as_object_mut) {
+        properties.insert(EXTENDS_KEY.to_string(), serde_json::json!({
+            "type": "string",
+            "description": "Template or plan this plan is merged over",
+        }));
+    }
+}
+
+/// Definitions reached through maps, which `extends` merges key by key. Lists are replaced whole
+/// so their items keep the required fields
+fn merged_definitions(schema: &serde_json::Value, node: &serde_json::Value, merged: &mut BTreeSet<String>) {
+    if let Some(name) = node.get("$ref").and_then(serde_json::Value::as_str).and_then(|r| r.strip_prefix("#/$defs/"))
+        && merged.insert(name.to_string())
+    {
+        merged_definitions(schema, &schema["$defs"][name], merged);
+    }
+    for variants in ["anyOf", "oneOf", "allOf"].iter().filter_map(|key| node.get(key).and_then(serde_json::Value::as_array)) {
+        variants.iter().for_each(|variant| merged_definitions(schema, variant, merged));
+    }
+    for property in node.get("properties").and_then(serde_json::Value::as_object).into_iter().flatten().map(|(_, property)| property) {
+        merged_definitions(schema, property, merged);
+    }
+}
+
+#[derive(Clone)]
+enum Source {
+    Template(String),
+    Plan(String),
+}
+
+impl Source {
+    fn path(&self) -> String {
+        match self {
+            Source::Template(name) => format!("{}.{}", TEMPLATES_KEY, name),
+            Source::Plan(name) => format!("{}.{}", PLANS_KEY, name),
+        }
+    }
+}
+
+struct Resolver<'a> {
+    templates: &'a Mapping,
+    plans: &'a Mapping,
+    resolved: HashMap<String, Value>,
+    chain: Vec<String>,
+}
+
+impl Resolver<'_> {
+    fn resolve(&mut self, source: &Source) -> Result<Value, ExtendsError> {
+        let path = source.path();
+        if let Some(resolved) = self.resolved.get(&path) {
+            return Ok(resolved.clone());
+        }
+        if let Some(start) = self.chain.iter().position(|p| *p == path) {
+            let mut cycle = self.chain[start..].to_vec();
+            cycle.push(path);
+            return Err(ExtendsError::Circular(cycle));
+        }
+        let mut value = match source {
+            Source::Template(name) => self.templates[name.as_str()].clone(),
+            Source::Plan(name) => self.plans[name.as_str()].clone(),
+        };
+        let parent = match &mut value {
+            Value::Mapping(plan) => plan.remove(EXTENDS_KEY),
+            _ => None,
+        };
+        let resolved = match parent {
+            None => value,
+            Some(Value::String(parent)) => {
+                let parent_source = if self.templates.contains_key(parent.as_str()) {
+                    Source::Template(parent)
+                } else if self.plans.contains_key(parent.as_str()) {
+                    Source::Plan(parent)
+                } else {
+                    return Err(ExtendsError::Unknown { from: path, parent });
+                };
+                self.chain.push(path.clone());
+                let mut base = self.resolve(&parent_source)?;
+                self.chain.pop();
+                merge_plan(&mut base, value);
+                base
+            }
+            Some(_) => return Err(ExtendsError::InvalidName(path)),
+        };
+        self.resolved.insert(path, resolved.clone());
+        Ok(resolved)
+    }
+}
+
+/// Deep merge `over` into `base`, views with the same key are merged, other lists are replaced
+fn merge_plan(base: &mut Value, over: Value) {
+    match (base, over) {
+        (Value::Mapping(base), Value::Mapping(over)) => {
+            for (key, value) in over {
+                match base.get_mut(&key) {
+                    Some(Value::Sequence(base_views)) if key.as_str() == Some(VIEWS_KEY) => {
+                        if let Value::Sequence(views) = value {
+                            merge_views(base_views, views);
+                        } else {
+                            base.insert(key, value);
+                        }
+                    }
+                    Some(base_value) => merge_value(base_value, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, over) => *base = over,
+    }
+}
+
+/// Deep merge maps of `over` into `base`, anything else in `over` replaces `base`
+pub fn merge_value(base: &mut Value, over: Value) {
+    match (base, over) {
+        (Value::Mapping(base), Value::Mapping(over)) => {
+            for (key, value) in over {
+                match base.get_mut(&key) {
+                    Some(base_value) => merge_value(base_value, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, over) => *base = over,
+    }
+}
+
+/// Views keep the order of `base`, new keys are appended
+fn merge_views(base: &mut Vec<Value>, views: Vec<Value>) {
+    for view in views {
+        let existing = view.get(VIEW_KEY)
+            .and_then(|key| base.iter_mut().find(|base_view| base_view.get(VIEW_KEY) == Some(key)));
+        match existing {
+            Some(base_view) => merge_value(base_view, view),
+            None => base.push(view),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolved(yaml: &str) -> Result<Value, ExtendsError> {
+        let mut config: Value = serde_yaml::from_str(yaml).unwrap();
+        resolve_extends(&mut config).map(|_| config)
+    }
+
+    #[test]
+    fn plans_merge_over_templates() {
+        let config = resolved(r#"
+templates:
+  base:
+    type: gitlab-native
+    groups: [ops]
+    gitlab: { project_id: 1, token_var: TOKEN, ref: main }
+plans:
+  deploy:
+    extends: base
+    gitlab: { ref: release }
+"#).unwrap();
+        assert!(config.get(TEMPLATES_KEY).is_none());
+        let plan = &config["plans"]["deploy"];
+        assert!(plan.get(EXTENDS_KEY).is_none());
+        assert_eq!(plan["type"], Value::from("gitlab-native"));
+        assert_eq!(plan["gitlab"]["project_id"], Value::from(1));
+        assert_eq!(plan["gitlab"]["ref"], Value::from("release"));
+    }
+
+    #[test]
+    fn templates_extend_templates() {
+        let config = resolved(r#"
+templates:
+  base:
+    groups: [ops]
+    gitlab: { project_id: 1, ref: main }
+  prod:
+    extends: base
+    gitlab: { ref: prod }
+  prod-eu:
+    extends: prod
+    groups: [ops-eu]
+plans:
+  deploy:
+    extends: prod-eu
+    gitlab: { token_var: TOKEN }
+"#).unwrap();
+        let plan = &config["plans"]["deploy"];
+        // lists are replaced, maps are merged through the whole chain
+        assert_eq!(plan["groups"], serde_yaml::from_str::<Value>("[ops-eu]").unwrap());
+        assert_eq!(plan["gitlab"], serde_yaml::from_str::<Value>("{ project_id: 1, ref: prod, token_var: TOKEN }").unwrap());
+    }
+
+    #[test]
+    fn templates_are_looked_up_before_plans() {
+        let config = resolved(r#"
+templates:
+  shared: { groups: [from-template] }
+plans:
+  shared: { groups: [from-plan] }
+  deploy: { extends: shared }
+"#).unwrap();
+        assert_eq!(config["plans"]["deploy"]["groups"][0], Value::from("from-template"));
+        assert_eq!(config["plans"]["shared"]["groups"][0], Value::from("from-plan"));
+    }
+
+    #[test]
+    fn views_are_merged_by_key() {
+        let config = resolved(r#"
+templates:
+  base:
+    views:
+      - { key: LIMIT, type: input-field, text: Limit, value: all }
+      - { key: TAGS, type: select, text: Tags, data: [a, b] }
+plans:
+  deploy:
+    extends: base
+    views:
+      - { key: TAGS, data: [c] }
+      - { key: DRY_RUN, type: checkbox, text: Dry run, value: true }
+      - { key: LIMIT, value: web }
+"#).unwrap();
+        let views = config["plans"]["deploy"]["views"].as_sequence().unwrap();
+        let keys: Vec<&str> = views.iter().map(|view| view["key"].as_str().unwrap()).collect();
+        assert_eq!(keys, vec!["LIMIT", "TAGS", "DRY_RUN"]);
+        assert_eq!(views[0], serde_yaml::from_str::<Value>("{ key: LIMIT, type: input-field, text: Limit, value: web }").unwrap());
+        assert_eq!(views[1]["data"], serde_yaml::from_str::<Value>("[c]").unwrap());
+        assert_eq!(views[1]["text"], Value::from("Tags"));
+    }
+
+    #[test]
+    fn cycles_are_reported_with_their_chain() {
+        let error = resolved(r#"
+templates:
+  a: { extends: b }
+  b: { extends: a }
+plans:
+  deploy: { extends: a }
+"#).unwrap_err();
+        assert_eq!(error.to_string(), "Circular extends: templates.a -> templates.b -> templates.a");
+        assert_eq!(error.path(), "templates.a.extends");
+
+        let error = resolved("plans:\n  deploy: { extends: deploy }\n").unwrap_err();
+        assert_eq!(error.to_string(), "Circular extends: plans.deploy -> plans.deploy");
+    }
+
+    #[test]
+    fn unknown_and_invalid_parents_are_errors() {
+        let error = resolved("plans:\n  deploy: { extends: missing }\n").unwrap_err();
+        assert_eq!(error.to_string(), "plans.deploy extends unknown template or plan missing");
+        assert_eq!(error.path(), "plans.deploy.extends");
+
+        let error = resolved("plans:\n  deploy: { extends: [a, b] }\n").unwrap_err();
+        assert_eq!(error.path(), "plans.deploy.extends");
+    }
+
+    #[test]
+    fn reject_extends_only_fails_when_it_is_set() {
+        let plan: Value = serde_yaml::from_str("{ extends: base, groups: [ops] }").unwrap();
+        let error = reject_extends("plans.etcd_deploy", &plan).unwrap_err();
+        assert_eq!(error.path(), "plans.etcd_deploy.extends");
+        assert!(reject_extends("plans.etcd_deploy", &serde_yaml::from_str("{ groups: [ops] }").unwrap()).is_ok());
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Error};
+use etcd_client::{EventType, WatchOptions};
+use log::{error, info, warn};
+use notify::{RecursiveMode, Watcher};
+use serde::de::DeserializeOwned;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+
+use crate::config::{load_etcd_prefix, parse_etcd_config, EtcdConfig, EtcdConfigLoader, PlimPlan, PlimUser};
+use crate::config_sources::ConfigSource;
+use crate::etcd_manager::EtcdManagerError;
+use crate::state::AppState;
+
+/// Editors write a file in several steps, changes coming within this window trigger one reload
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+const ETCD_WATCH_RETRY: Duration = Duration::from_secs(5);
+
+async fn reload(state: &AppState, trigger: &str) {
+    info!("Reloading config on {}", trigger);
+    if let Err(e) = state.reload_config().await {
+        error!("Config reload on {} failed, keeping the running config: {:?}", trigger, e);
+    }
+}
+
+pub fn spawn_reload_on_sighup(state: AppState) -> Result<(), Error> {
+    let mut hangups = signal(SignalKind::hangup()).context("Failed to listen for SIGHUP")?;
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            reload(&state, "SIGHUP").await;
+        }
+    });
+    Ok(())
+}
+
+/// Reload when a file under the config paths changes
+pub fn spawn_config_watcher(state: AppState) -> Result<(), Error> {
+    let (sender, mut changes) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+        Ok(event) if !event.kind.is_access() => {
+            let _ = sender.send(event.paths);
+        }
+        Ok(_) => {}
+        Err(e) => warn!("Config watcher error: {:?}", e),
+    }).context("Failed to create config watcher")?;
+    for path in &state.config_paths {
+        if !Path::new(path).exists() {
+            warn!("Config path {} does not exist, it is not watched", path);
+            continue;
+        }
+        watcher.watch(Path::new(path), RecursiveMode::Recursive).context(format!("Failed to watch {}", path))?;
+        info!("Watching config path {}", path);
+    }
+    tokio::spawn(async move {
+        // the watcher stops when dropped, it lives as long as this task
+        let _watcher = watcher;
+        while let Some(paths) = changes.recv().await {
+            tokio::time::sleep(WATCH_DEBOUNCE).await;
+            while changes.try_recv().is_ok() {}
+            reload(&state, &format!("change of {:?}", paths)).await;
+        }
+    });
+    Ok(())
+}
+
+/// Keep the plans and users stored in etcd in sync with the running config
+pub fn spawn_etcd_config_watchers(state: AppState) {
+    let etcd_configs = state.config.load().etcd_configs.clone();
+    spawn_etcd_config_watcher::<PlimPlan>(state.clone(), etcd_configs.plans);
+    spawn_etcd_config_watcher::<PlimUser>(state, etcd_configs.users);
+}
+
+fn spawn_etcd_config_watcher<T: DeserializeOwned + EtcdConfigLoader + Send + Sync + 'static>(state: AppState, etcd_config: EtcdConfig) {
+    if etcd_config.etcd_name.is_empty() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut known_names = BTreeSet::new();
+        loop {
+            if let Err(e) = watch_etcd_configs::<T>(&state, &etcd_config, &mut known_names).await {
+                if e.downcast_ref::<EtcdManagerError>().is_none() {
+                    state.etcd.report_failure(&etcd_config.etcd_name, format!("{:#}", e)).await;
+                }
+                warn!("Etcd watch on {} {} stopped, retrying: {:?}", etcd_config.etcd_name, etcd_config.key_prefix_path, e);
+            }
+            tokio::time::sleep(ETCD_WATCH_RETRY).await;
+        }
+    });
+}
+
+/// Resync the prefix then apply its events until the watch ends, `known_names` are the names taken from etcd so far
+async fn watch_etcd_configs<T: DeserializeOwned + EtcdConfigLoader>(state: &AppState, etcd_config: &EtcdConfig, known_names: &mut BTreeSet<String>) -> Result<(), Error> {
+    let prefix = etcd_config.key_prefix();
+    let mut client = state.etcd.client(&etcd_config.etcd_name).await?;
+
+    // changes made while no watch was running are picked up here
+    let entries = load_etcd_prefix::<T>(&mut client, etcd_config).await.context("Failed to read etcd prefix")?;
+    let revision = entries.revision;
+    let mut names: BTreeSet<String> = entries.values.iter().map(|(name, _, _)| name.clone()).collect();
+    // a broken value keeps the last good one
+    names.extend(entries.skipped.into_iter().filter(|name| known_names.contains(name)));
+    let values = entries.values;
+    let removed: Vec<String> = known_names.difference(&names).cloned().collect();
+    state.config.update(|conf| {
+        removed.iter().for_each(|name| T::remove_from_config(conf, name));
+        values.iter().for_each(|(name, value, source)| value.load_from_etcd(conf, name.clone(), source.clone()));
+    }).await;
+    *known_names = names;
+
+    let options = WatchOptions::new().with_prefix().with_start_revision(revision + 1);
+    let (_watcher, mut stream) = client.watch(prefix.as_str(), Some(options)).await.context("Failed to watch etcd prefix")?;
+    info!("Watching etcd {} prefix {} from revision {}", etcd_config.etcd_name, prefix, revision + 1);
+    while let Some(response) = stream.message().await.context("Etcd watch stream failed")? {
+        if response.canceled() {
+            return Err(anyhow::anyhow!("Etcd watch canceled: {}", response.cancel_reason()));
+        }
+        for event in response.events() {
+            let Some(kv) = event.kv() else { continue };
+            match (event.event_type(), parse_etcd_config::<T>(etcd_config, kv)) {
+                (EventType::Put, (name, Ok(value))) => {
+                    info!("Etcd config {} updated", name);
+                    let source = ConfigSource::etcd(&etcd_config.etcd_name, kv);
+                    state.config.update(|conf| value.load_from_etcd(conf, name.clone(), source.clone())).await;
+                    known_names.insert(name);
+                }
+                (EventType::Put, (name, Err(e))) => error!("Skipping etcd config {}: {:?}", name, e),
+                (EventType::Delete, (name, _)) => {
+                    info!("Etcd config {} deleted", name);
+                    state.config.update(|conf| T::remove_from_config(conf, &name)).await;
+                    known_names.remove(&name);
+                }
+            }
+        }
+    }
+    Err(anyhow::anyhow!("Etcd watch stream closed"))
+}
@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use etcd_client::KeyValue;
+use log::warn;
+use serde::Serialize;
+use serde_yaml::Value;
+
+const PLANS_KEY: &str = "plans";
+const USERS_KEY: &str = "users";
+
+/// Where a part of the config was read from
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ConfigSource {
+    File { path: String },
+    Etcd { etcd_name: String, key: String, revision: i64 },
+}
+
+impl ConfigSource {
+    /// `revision` is the last revision the key was changed at
+    pub fn etcd(etcd_name: &str, kv: &KeyValue) -> Self {
+        ConfigSource::Etcd {
+            etcd_name: etcd_name.to_string(),
+            key: String::from_utf8_lossy(kv.key()).to_string(),
+            revision: kv.mod_revision(),
+        }
+    }
+
+    /// Same file or etcd key, a newer revision of a key is not another source
+    fn same_location(&self, other: &ConfigSource) -> bool {
+        match (self, other) {
+            (ConfigSource::File { path }, ConfigSource::File { path: other }) => path == other,
+            (ConfigSource::Etcd { etcd_name, key, .. }, ConfigSource::Etcd { etcd_name: other_name, key: other_key, .. }) => {
+                etcd_name == other_name && key == other_key
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::File { path } => write!(f, "file {}", path),
+            ConfigSource::Etcd { etcd_name, key, revision } => write!(f, "etcd {} key {} (revision {})", etcd_name, key, revision),
+        }
+    }
+}
+
+/// Sources of every top-level key, plan and user in merge order, the last source of a name wins
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ConfigSources {
+    pub keys: BTreeMap<String, Vec<ConfigSource>>,
+    pub plans: BTreeMap<String, Vec<ConfigSource>>,
+    pub users: BTreeMap<String, Vec<ConfigSource>>,
+}
+
+impl ConfigSources {
+    /// Record what one config file defines, `value` is the file before it is merged
+    pub fn add_file(&mut self, path: &str, value: &Value) {
+        let Value::Mapping(mapping) = value else { return };
+        let source = ConfigSource::File { path: path.to_string() };
+        for (key, value) in mapping {
+            let Some(key) = key.as_str() else { continue };
+            self.keys.entry(key.to_string()).or_default().push(source.clone());
+            let names = match key {
+                PLANS_KEY => &mut self.plans,
+                USERS_KEY => &mut self.users,
+                _ => continue,
+            };
+            let Value::Mapping(entries) = value else { continue };
+            for name in entries.keys().filter_map(Value::as_str) {
+                names.entry(name.to_string()).or_default().push(source.clone());
+            }
+        }
+    }
+
+    /// Config path of every plan and user read from etcd
+    pub fn etcd_paths(&self) -> Vec<String> {
+        [(PLANS_KEY, &self.plans), (USERS_KEY, &self.users)].into_iter()
+            .flat_map(|(key, names)| names.iter()
+                .filter(|(_, sources)| matches!(sources.as_slice(), [ConfigSource::Etcd { .. }]))
+                .map(move |(name, _)| format!("{}.{}", key, name)))
+            .collect()
+    }
+
+    /// Config path and sources of every plan and user defined more than once
+    pub fn duplicates(&self) -> Vec<(String, &[ConfigSource])> {
+        [(PLANS_KEY, &self.plans), (USERS_KEY, &self.users)].into_iter()
+            .flat_map(|(key, names)| names.iter()
+                .filter(|(_, sources)| sources.len() > 1)
+                .map(move |(name, sources)| (format!("{}.{}", key, name), sources.as_slice())))
+            .collect()
+    }
+}
+
+/// Etcd entries replace a plan or user as a whole, `source` becomes its only source.
+/// Returns the other locations it replaced, they are logged as a warning
+pub fn replace_source(sources: &mut BTreeMap<String, Vec<ConfigSource>>, name: &str, source: ConfigSource) -> Vec<ConfigSource> {
+    let replaced: Vec<ConfigSource> = sources.get(name).into_iter().flatten()
+        .filter(|previous| !previous.same_location(&source))
+        .cloned()
+        .collect();
+    if !replaced.is_empty() {
+        warn!("{} from {} replaces the one from {}", name, source, replaced.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "));
+    }
+    sources.insert(name.to_string(), vec![source]);
+    replaced
+}
+
+/// Sources in merge order for logs and validation messages
+pub fn describe(sources: &[ConfigSource]) -> String {
+    sources.iter().map(ToString::to_string).collect::<Vec<_>>().join(", then ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str) -> ConfigSource {
+        ConfigSource::File { path: path.to_string() }
+    }
+
+    fn etcd(key: &str, revision: i64) -> ConfigSource {
+        ConfigSource::Etcd { etcd_name: "main".to_string(), key: key.to_string(), revision }
+    }
+
+    fn sources_of(files: &[(&str, &str)]) -> ConfigSources {
+        let mut sources = ConfigSources::default();
+        for (path, yaml) in files {
+            sources.add_file(path, &serde_yaml::from_str(yaml).unwrap());
+        }
+        sources
+    }
+
+    #[test]
+    fn plan_in_two_files_is_a_duplicate() {
+        let sources = sources_of(&[
+            ("config.yml", "plim: {}\nplans:\n  deploy: {}\n  backup: {}\n"),
+            ("plans/deploy.yml", "plans:\n  deploy: {}\n"),
+        ]);
+        assert_eq!(sources.plans["deploy"], [file("config.yml"), file("plans/deploy.yml")]);
+        let duplicates = sources.duplicates();
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].0, "plans.deploy");
+        assert_eq!(duplicates[0].1, [file("config.yml"), file("plans/deploy.yml")]);
+        assert_eq!(describe(duplicates[0].1), "file config.yml, then file plans/deploy.yml");
+    }
+
+    #[test]
+    fn top_level_keys_are_not_duplicates() {
+        let sources = sources_of(&[("a.yml", "plim: {}\nusers:\n  ops: {}\n"), ("b.yml", "plim: {}\nusers:\n  dev: {}\n")]);
+        assert_eq!(sources.keys["plim"].len(), 2);
+        assert!(sources.duplicates().is_empty());
+    }
+
+    #[test]
+    fn etcd_entry_replaces_a_file_plan() {
+        let mut sources = sources_of(&[("config.yml", "plans:\n  deploy: {}\n"), ("plans/deploy.yml", "plans:\n  deploy: {}\n")]);
+        let replaced = replace_source(&mut sources.plans, "deploy", etcd("/plans/deploy", 4));
+        assert_eq!(replaced, [file("config.yml"), file("plans/deploy.yml")]);
+        assert_eq!(sources.plans["deploy"], [etcd("/plans/deploy", 4)]);
+        assert!(sources.duplicates().is_empty());
+        assert_eq!(sources.etcd_paths(), ["plans.deploy"]);
+    }
+
+    #[test]
+    fn newer_revision_of_the_same_key_is_no_duplicate() {
+        let mut sources = ConfigSources::default();
+        assert!(replace_source(&mut sources.users, "ops", etcd("/users/ops", 4)).is_empty());
+        assert!(replace_source(&mut sources.users, "ops", etcd("/users/ops", 9)).is_empty());
+        assert_eq!(sources.users["ops"], [etcd("/users/ops", 9)]);
+        assert!(sources.duplicates().is_empty());
+        // the same key name on another etcd is another location
+        let other = ConfigSource::Etcd { etcd_name: "backup".to_string(), key: "/users/ops".to_string(), revision: 9 };
+        assert_eq!(replace_source(&mut sources.users, "ops", other), [etcd("/users/ops", 9)]);
+    }
+
+    #[test]
+    fn etcd_paths_skip_file_entries() {
+        let mut sources = sources_of(&[("config.yml", "plans:\n  local: {}\nusers:\n  admin: {}\n")]);
+        replace_source(&mut sources.users, "ops", etcd("/users/ops", 2));
+        assert_eq!(sources.etcd_paths(), ["users.ops"]);
+    }
+}
@@ -0,0 +1,704 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_yaml::Value;
+
+use crate::config::{AnsibleBackendType, AnsibleConfig, AnsibleEtcdBackend, AnsibleGitlabBackend, AnsibleLocalBackend, AnyValue, CheckboxListView,
+    Config, DataSource, DataSourceType, DynamicView, MultiValueView, OneValueView, PlimPlan, PlimPlanViewType};
+use crate::config_extends::{reject_extends, resolve_extends, EXTENDS_KEY};
+use crate::config_sources::describe;
+use crate::secret_registry::PlanScope;
+use crate::secrets::split_secret_ref;
+use crate::merge_yml::{get_yaml_files_in_folder, interpolate_value, ConfigData};
+use crate::state::GitlabTokens;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IssueSeverity {
+    Error,
+    Warning,
+}
+
+/// Problem found in the config, `path` is the yaml path like `plans.test.views[0].key`
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigIssue {
+    pub severity: IssueSeverity,
+    pub file: Option<String>,
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            IssueSeverity::Error => "error",
+            IssueSeverity::Warning => "warning",
+        };
+        write!(f, "{}: ", severity)?;
+        if let Some(file) = &self.file {
+            write!(f, "{}: ", file)?;
+        }
+        if !self.path.is_empty() {
+            write!(f, "{}: ", self.path)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+pub fn has_errors(issues: &[ConfigIssue]) -> bool {
+    issues.iter().any(|issue| issue.severity == IssueSeverity::Error)
+}
+
+#[derive(Default)]
+struct Issues(Vec<ConfigIssue>);
+
+impl Issues {
+    fn push(&mut self, severity: IssueSeverity, path: impl Into<String>, message: impl Into<String>) {
+        self.0.push(ConfigIssue { severity, file: None, path: path.into(), message: message.into() });
+    }
+    fn error(&mut self, path: impl Into<String>, message: impl Into<String>) {
+        self.push(IssueSeverity::Error, path, message);
+    }
+    fn warning(&mut self, path: impl Into<String>, message: impl Into<String>) {
+        self.push(IssueSeverity::Warning, path, message);
+    }
+}
+
+/// File which defines each yaml path, a later file overrides an earlier one the same way the merge does
+#[derive(Default)]
+struct SourceMap(BTreeMap<String, String>);
+
+impl SourceMap {
+    fn collect(&mut self, file: &str, path: String, value: &Value) {
+        match value {
+            Value::Mapping(mapping) => {
+                for (key, value) in mapping {
+                    let key = match key {
+                        Value::String(key) => key.clone(),
+                        key => serde_yaml::to_string(key).unwrap_or_default().trim().to_string(),
+                    };
+                    let child = if path.is_empty() { key } else { format!("{}.{}", path, key) };
+                    self.collect(file, child, value);
+                }
+            }
+            Value::Sequence(sequence) => {
+                for (index, value) in sequence.iter().enumerate() {
+                    self.collect(file, format!("{}[{}]", path, index), value);
+                }
+            }
+            _ => {}
+        }
+        self.0.insert(path, file.to_string());
+    }
+
+    /// File of the longest known path the issue path starts with
+    fn file_of(&self, path: &str) -> Option<String> {
+        let mut path = path;
+        loop {
+            if let Some(file) = self.0.get(path) {
+                return Some(file.clone());
+            }
+            path = &path[..path.rfind(['.', '['])?];
+        }
+    }
+}
+
+/// Read the config files strictly and report every problem, the config is returned when it could be parsed
+pub async fn validate_config_files(paths: &[&str], tokens: &GitlabTokens) -> (Option<Config>, Vec<ConfigIssue>) {
+    let mut issues = Issues::default();
+    let mut sources = SourceMap::default();
+    for path in paths {
+        let files = match get_yaml_files_in_folder(path) {
+            Ok(files) => files,
+            Err(e) => {
+                issues.0.push(ConfigIssue { severity: IssueSeverity::Error, file: Some(path.to_string()), path: String::new(), message: e.to_string() });
+                continue;
+            }
+        };
+        for file in files {
+            let parsed = fs::read_to_string(&file).map_err(|e| e.to_string())
+                .and_then(|content| serde_yaml::from_str::<Value>(&content).map_err(|e| e.to_string()));
+            match parsed {
+                Ok(value) => sources.collect(&file, String::new(), &value),
+                Err(e) => issues.0.push(ConfigIssue { severity: IssueSeverity::Error, file: Some(file), path: String::new(), message: e }),
+            }
+        }
+    }
+
+    let mut config_data = ConfigData::new(paths.to_vec());
+    if let Err(e) = config_data.load_and_merge() {
+        issues.error("", format!("Failed to merge config files: {:#}", e));
+    }
+    let tokens = match config_data.prepare_secrets(tokens).await {
+        Ok(staged) => staged,
+        Err(e) => {
+            issues.error("secrets", format!("{:#}", e));
+            tokens.clone()
+        }
+    };
+    let tokens = &tokens;
+    for (path, duplicate_sources) in config_data.sources.duplicates() {
+        issues.warning(path, format!("defined more than once and merged: {}", describe(duplicate_sources)));
+    }
+    let mut merged_value: Value = serde_yaml::from_str(&config_data.merged_data.to_string()).unwrap_or(Value::Null);
+    for (path, error) in interpolate_value(&mut merged_value, String::new(), tokens) {
+        issues.error(path, error.to_string());
+    }
+    if let Err(e) = resolve_extends(&mut merged_value) {
+        issues.error(e.path(), e.to_string());
+    }
+    for (name, plan) in merged_value.get("plans").and_then(Value::as_mapping).into_iter().flatten() {
+        if let Some(name) = name.as_str() {
+            check_plan_fields(&mut issues, &format!("plans.{}", name), plan);
+        }
+    }
+    let merged = serde_yaml::to_string(&merged_value).unwrap_or_default();
+    let mut track = serde_path_to_error::Track::new();
+    let deserializer = serde_path_to_error::Deserializer::new(serde_yaml::Deserializer::from_str(&merged), &mut track);
+    let mut ignored = Vec::new();
+    let config = match serde_ignored::deserialize::<_, _, Config>(deserializer, |path| ignored.push(ignored_path(&path))) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            issues.error(track.path().to_string(), e.to_string());
+            None
+        }
+    };
+    for path in ignored {
+        issues.error(path, "unknown field");
+    }
+    if let Some(config) = &config {
+        issues.0.extend(check_config(config, tokens));
+    }
+
+    let mut issues = issues.0;
+    for issue in issues.iter_mut().filter(|issue| issue.file.is_none()) {
+        issue.file = sources.file_of(&issue.path);
+    }
+    issues.sort_by(|a, b| (&a.file, &a.path).cmp(&(&b.file, &b.path)));
+    (config, issues)
+}
+
+/// Strictly parse one plan and check it in the context of `config`, issues of other plans are left out
+pub fn validate_plan(config: &Config, name: &str, value: Value, tokens: &GitlabTokens) -> (Option<PlimPlan>, Vec<ConfigIssue>) {
+    let plan_path = format!("plans.{}", name);
+    let mut issues = Issues::default();
+    let mut value = value;
+    if let Err(e) = reject_extends(&plan_path, &value) {
+        issues.error(e.path(), e.to_string());
+        if let Some(plan) = value.as_mapping_mut() {
+            plan.remove(EXTENDS_KEY);
+        }
+    }
+    check_plan_fields(&mut issues, &plan_path, &value);
+    let mut track = serde_path_to_error::Track::new();
+    let deserializer = serde_path_to_error::Deserializer::new(value, &mut track);
+    let mut ignored = Vec::new();
+    let plan = match serde_ignored::deserialize::<_, _, PlimPlan>(deserializer, |path| ignored.push(ignored_path(&path))) {
+        Ok(plan) => Some(plan),
+        Err(e) => {
+            issues.error(join_path(&plan_path, &track.path().to_string()), e.to_string());
+            None
+        }
+    };
+    for path in ignored {
+        issues.error(join_path(&plan_path, &path), "unknown field");
+    }
+    if let Some(plan) = &plan {
+        let mut config = config.clone();
+        config.plans = HashMap::from([(name.to_string(), plan.clone())]);
+        issues.0.extend(check_config(&config, tokens).into_iter()
+            .filter(|issue| path_within(&issue.path, &plan_path)));
+    }
+    (plan, issues.0)
+}
+
+/// `path` is `parent` or a path below it
+pub fn path_within(path: &str, parent: &str) -> bool {
+    path.strip_prefix(parent).is_some_and(|rest| rest.is_empty() || rest.starts_with(['.', '[']))
+}
+
+/// Untagged enums buffer their content, so `serde_ignored` never sees unknown fields of views and backends.
+/// They are checked against the fields of the variant the mapping is meant for
+fn check_plan_fields(issues: &mut Issues, plan_path: &str, plan: &Value) {
+    check_view_fields(issues, &format!("{}.views", plan_path), plan.get("views"));
+    check_backend_fields(issues, &format!("{}.ansible", plan_path), plan.get("ansible"));
+    for (index, webhook) in plan.get("webhooks").and_then(Value::as_sequence).into_iter().flatten().enumerate() {
+        let webhook_path = format!("{}.webhooks[{}]", plan_path, index);
+        check_view_fields(issues, &format!("{}.views", webhook_path), webhook.get("views"));
+        check_backend_fields(issues, &format!("{}.ansible", webhook_path), webhook.get("ansible"));
+    }
+}
+
+fn check_view_fields(issues: &mut Issues, path: &str, views: Option<&Value>) {
+    for (index, view) in views.and_then(Value::as_sequence).into_iter().flatten().enumerate() {
+        let Some(view) = view.as_mapping() else { continue };
+        let fields = if view.contains_key("keys") {
+            schema_fields::<CheckboxListView>()
+        } else if view.contains_key("referenced_key") {
+            schema_fields::<DynamicView>()
+        } else if view.contains_key("data") {
+            schema_fields::<MultiValueView>()
+        } else {
+            schema_fields::<OneValueView>()
+        };
+        check_fields(issues, &format!("{}[{}]", path, index), view, &fields);
+    }
+}
+
+fn check_backend_fields(issues: &mut Issues, path: &str, ansible: Option<&Value>) {
+    let Some(ansible) = ansible else { return };
+    let backends = std::iter::once((format!("{}.backend_inventory", path), ansible.get("backend_inventory")))
+        .chain(ansible.get("backend_inventories").and_then(Value::as_sequence).into_iter().flatten().enumerate()
+            .map(|(index, backend)| (format!("{}.backend_inventories[{}]", path, index), Some(backend))));
+    for (backend_path, backend) in backends {
+        let Some(backend) = backend.and_then(Value::as_mapping) else { continue };
+        let fields = match backend.get("type").and_then(Value::as_str) {
+            Some("local") => schema_fields::<AnsibleLocalBackend>(),
+            Some("gitlab") => schema_fields::<AnsibleGitlabBackend>(),
+            Some("etcd") => schema_fields::<AnsibleEtcdBackend>(),
+            // an unknown type fails to parse on its own
+            _ => continue,
+        };
+        check_fields(issues, &backend_path, backend, &fields);
+    }
+}
+
+fn check_fields(issues: &mut Issues, path: &str, mapping: &serde_yaml::Mapping, fields: &[String]) {
+    for key in mapping.keys().filter_map(Value::as_str) {
+        if !fields.iter().any(|field| field == key) {
+            issues.error(format!("{}.{}", path, key), "unknown field");
+        }
+    }
+}
+
+/// Field names of `T` as config files spell them
+fn schema_fields<T: JsonSchema>() -> Vec<String> {
+    let schema = schemars::schema_for!(T);
+    schema.get("properties").and_then(serde_json::Value::as_object)
+        .map(|properties| properties.keys().cloned().collect())
+        .unwrap_or_default()
+}
+
+fn join_path(parent: &str, path: &str) -> String {
+    match path {
+        "" | "." => parent.to_string(),
+        path if path.starts_with('[') => format!("{}{}", parent, path),
+        path => format!("{}.{}", parent, path),
+    }
+}
+
+/// Same path format as `serde_path_to_error` uses
+fn ignored_path(path: &serde_ignored::Path) -> String {
+    match path {
+        serde_ignored::Path::Root => String::new(),
+        serde_ignored::Path::Seq { parent, index } => format!("{}[{}]", ignored_path(parent), index),
+        serde_ignored::Path::Map { parent, key } => match ignored_path(parent) {
+            parent if parent.is_empty() => key.clone(),
+            parent => format!("{}.{}", parent, key),
+        },
+        serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => ignored_path(parent),
+    }
+}
+
+/// Checks on a parsed config, they don't need the config files
+pub fn check_config(config: &Config, tokens: &GitlabTokens) -> Vec<ConfigIssue> {
+    let mut issues = Issues::default();
+    if config.gitlab.api_endpoint.is_empty() {
+        issues.error("gitlab.api_endpoint", "gitlab api endpoint is empty");
+    }
+    for (index, admin) in config.admins.iter().enumerate() {
+        if !config.users.contains_key(admin) {
+            issues.warning(format!("admins[{}]", index), format!("admin {} is not a defined user", admin));
+        }
+    }
+    for (name, user) in &config.users {
+        if !user.disabled && user.hashed_password.is_empty() {
+            issues.error(format!("users.{}.hashed_password", name), "enabled user has no password hash");
+        }
+    }
+    let etcd_configs = [
+        ("plans", &config.etcd_configs.plans),
+        ("users", &config.etcd_configs.users),
+        ("ansible_inventories", &config.etcd_configs.ansible_inventories),
+    ];
+    for (kind, etcd_config) in etcd_configs {
+        check_etcd_name(&mut issues, config, &format!("etcd_configs.{}.etcd_name", kind), &etcd_config.etcd_name);
+    }
+    for (name, etcd) in &config.etcd_data_map {
+        let etcd_path = format!("etcd_data_map.{}", name);
+        match (&etcd.user, &etcd.password_var) {
+            (Some(_), Some(password_var)) => check_token_var(&mut issues, tokens, &format!("{}.password_var", etcd_path), password_var),
+            (None, None) => {}
+            _ => issues.error(&etcd_path, "user and password_var must be set together"),
+        }
+        if let Some(tls) = &etcd.tls {
+            if tls.cert_path.is_some() != tls.key_path.is_some() {
+                issues.error(format!("{}.tls", etcd_path), "cert_path and key_path must be set together");
+            }
+            for (field, path) in [("ca_cert_path", &tls.ca_cert_path), ("cert_path", &tls.cert_path), ("key_path", &tls.key_path)] {
+                if let Some(path) = path.as_deref().filter(|path| !Path::new(path).is_file()) {
+                    issues.error(format!("{}.tls.{}", etcd_path, field), format!("file {} does not exist", path));
+                }
+            }
+        }
+    }
+
+    for (name, secret) in config.secrets.registry.iter().flatten() {
+        for (index, plan) in secret.plans.iter().enumerate() {
+            if !config.plans.contains_key(plan) {
+                issues.warning(format!("secrets.registry.{}.plans[{}]", name, index), format!("plan {} is not defined", plan));
+            }
+        }
+    }
+
+    let user_groups: HashSet<&String> = config.users.values().flat_map(|user| &user.groups).collect();
+    for (name, plan) in &config.plans {
+        let plan_path = format!("plans.{}", name);
+        let scope = PlanScope::new(name, plan);
+        for (index, group) in plan.groups.iter().enumerate() {
+            if !user_groups.contains(group) {
+                issues.warning(format!("{}.groups[{}]", plan_path, index), format!("no user belongs to group {}", group));
+            }
+        }
+        check_plan_token_var(&mut issues, tokens, scope, &format!("{}.gitlab.token_var", plan_path), &plan.gitlab.token_var);
+        let ref_select = &plan.gitlab.ref_select;
+        for (field, regex) in [("branch_regex", &ref_select.branch_regex), ("tag_regex", &ref_select.tag_regex)] {
+            if let Some(Err(e)) = regex.as_deref().map(Regex::new) {
+                issues.error(format!("{}.gitlab.ref_select.{}", plan_path, field), format!("invalid regex: {}", e));
+            }
+        }
+        check_views(&mut issues, config, &format!("{}.views", plan_path), &plan.views);
+        if let Some(ansible) = &plan.ansible {
+            check_ansible(&mut issues, config, tokens, scope, &format!("{}.ansible", plan_path), ansible);
+        }
+        let mut webhook_names = HashSet::new();
+        for (index, webhook) in plan.webhooks.iter().flatten().enumerate() {
+            let webhook_path = format!("{}.webhooks[{}]", plan_path, index);
+            if !webhook_names.insert(&webhook.name) {
+                issues.error(format!("{}.name", webhook_path), format!("webhook {} is defined more than once", webhook.name));
+            }
+            if let Err(e) = tokens.resolve_for_plan(&webhook.trigger_token, scope) {
+                issues.error(format!("{}.trigger_token", webhook_path), e.to_string());
+            }
+            if let Some(views) = &webhook.views {
+                check_views(&mut issues, config, &format!("{}.views", webhook_path), views);
+            }
+            if let Some(ansible) = &webhook.ansible {
+                check_ansible(&mut issues, config, tokens, scope, &format!("{}.ansible", webhook_path), ansible);
+            }
+        }
+    }
+    issues.0
+}
+
+fn check_etcd_name(issues: &mut Issues, config: &Config, path: &str, etcd_name: &str) {
+    if !etcd_name.is_empty() && !config.etcd_data_map.contains_key(etcd_name) {
+        issues.error(path, format!("etcd {} is not defined in etcd_data_map", etcd_name));
+    }
+}
+
+fn check_token_var(issues: &mut Issues, tokens: &GitlabTokens, path: &str, token_var: &str) {
+    if !tokens.contains(token_var) && split_secret_ref(token_var).is_some() {
+        issues.error(path, format!("secret {} could not be fetched", token_var));
+    } else if !tokens.contains(token_var) {
+        issues.error(path, format!("env variable {} is not set", token_var));
+    }
+}
+
+/// A token variable of a plan must be allowed by the secret registry, then its source must be set
+fn check_plan_token_var(issues: &mut Issues, tokens: &GitlabTokens, plan: PlanScope<'_>, path: &str, token_var: &str) {
+    match tokens.resolve_for_plan(token_var, plan) {
+        Ok(source) => check_token_var(issues, tokens, path, &source),
+        Err(e) => issues.error(path, e.to_string()),
+    }
+}
+
+fn is_empty_key(key: &Option<AnyValue>) -> bool {
+    key.as_ref().is_none_or(|key| key.to_string().is_empty())
+}
+
+fn check_views(issues: &mut Issues, config: &Config, path: &str, views: &[PlimPlanViewType]) {
+    for (index, view) in views.iter().enumerate() {
+        let view_path = format!("{}[{}]", path, index);
+        let data_source = match view {
+            PlimPlanViewType::One(view) => {
+                if is_empty_key(&view.key) {
+                    issues.error(format!("{}.key", view_path), "view has no key");
+                }
+                &view.data_source
+            }
+            PlimPlanViewType::Multi(view) => {
+                if is_empty_key(&view.key) {
+                    issues.error(format!("{}.key", view_path), "view has no key");
+                }
+                &view.data_source
+            }
+            PlimPlanViewType::Dynamic(view) => {
+                if is_empty_key(&view.key) {
+                    issues.error(format!("{}.key", view_path), "view has no key");
+                }
+                &view.data_source
+            }
+            PlimPlanViewType::CheckboxList(view) => {
+                if view.keys.is_empty() || view.keys.iter().any(String::is_empty) {
+                    issues.error(format!("{}.keys", view_path), "checkbox list view has no keys or an empty key");
+                }
+                if view.keys.len() != view.values.len() {
+                    issues.error(format!("{}.values", view_path), format!("{} keys but {} values", view.keys.len(), view.values.len()));
+                }
+                &view.data_source
+            }
+        };
+        if let Some(DataSource { source_type: DataSourceType::Etcd, etcd_name, key_path }) = data_source {
+            check_etcd_name(issues, config, &format!("{}.data_source.etcd_name", view_path), etcd_name);
+            if key_path.is_empty() {
+                issues.error(format!("{}.data_source.key_path", view_path), "etcd data source has no key_path");
+            }
+        }
+    }
+}
+
+fn check_ansible(issues: &mut Issues, config: &Config, tokens: &GitlabTokens, plan: PlanScope<'_>, path: &str, ansible: &AnsibleConfig) {
+    let backends = std::iter::once((format!("{}.backend_inventory", path), &ansible.backend_inventory))
+        .chain(ansible.backend_inventories.iter().flatten().enumerate()
+            .map(|(index, backend)| (format!("{}.backend_inventories[{}]", path, index), backend)));
+    for (backend_path, backend) in backends {
+        match backend {
+            AnsibleBackendType::Etcd(etcd) => check_etcd_name(issues, config, &format!("{}.etcd_name", backend_path), &etcd.etcd_name),
+            AnsibleBackendType::Gitlab(gitlab) => {
+                if let Some(token_var) = &gitlab.token_var {
+                    check_plan_token_var(issues, tokens, plan, &format!("{}.token_var", backend_path), token_var);
+                }
+            }
+            AnsibleBackendType::Local(_) => {}
+        }
+    }
+    if let Some(password_var) = &ansible.vault_password_var {
+        check_plan_token_var(issues, tokens, plan, &format!("{}.vault_password_var", path), password_var);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config_sources::ConfigSource;
+
+    use super::*;
+
+    const CONFIG: &str = r#"
+plim:
+  listen_address: "127.0.0.1:3000"
+  jwt_token_duration_hours: 24
+  webhook_token_length: 12
+gitlab:
+  api_endpoint: "https://gitlab.example/api/v4"
+admins: [admin]
+users:
+  admin:
+    full_name: Admin
+    email: admin@example.com
+    groups: [ops]
+    hashed_password: "$2b$12$4ukjsg7ReEJI8zlnml9hCex4io.3NUIOeUOW6d1JQIm7CZnUmTedi"
+    disabled: false
+plans: {}
+secrets:
+  registry:
+    DEPLOY_TOKEN: { groups: [ops] }
+"#;
+
+    const PLAN: &str = r#"
+type: gitlab-ansible-native
+groups: [ops]
+gitlab:
+  project_id: 1
+  token_var: DEPLOY_TOKEN
+  ref: main
+  execute_api_type: create
+ansible:
+  playbook: site.yml
+  inventory: hosts.yml
+  backend_inventory:
+    type: gitlab
+    file_path: hosts.yml
+    token_var: DEPLOY_TOKEN
+views:
+  - text: Limit
+    type: input-field
+    key: LIMIT
+    value: web
+  - text: Date
+    type: date-picker
+    format: M/D/YYYY
+    key: DATE
+    value: "2024-09-26"
+  - text: Env
+    type: select
+    key: ENV
+    data: [dev, prod]
+"#;
+
+    fn config() -> Config {
+        serde_yaml::from_str(CONFIG).unwrap()
+    }
+
+    fn plan() -> Value {
+        serde_yaml::from_str(PLAN).unwrap()
+    }
+
+    /// Tokens of `vars` with the registry of `CONFIG`
+    fn tokens_with(vars: &[(&str, &str)]) -> GitlabTokens {
+        let tokens = GitlabTokens::with_vars(vars);
+        let config = config();
+        tokens.adopt(&tokens.staged(&config.secrets, &config.etcd_data_map, None).unwrap());
+        tokens
+    }
+
+    fn tokens() -> GitlabTokens {
+        tokens_with(&[("DEPLOY_TOKEN", "secret")])
+    }
+
+    fn errors(issues: &[ConfigIssue]) -> Vec<String> {
+        issues.iter().filter(|issue| issue.severity == IssueSeverity::Error)
+            .map(|issue| format!("{}: {}", issue.path, issue.message))
+            .collect()
+    }
+
+    #[test]
+    fn valid_plan_has_no_errors() {
+        let (plan, issues) = validate_plan(&config(), "deploy", plan(), &tokens());
+        assert!(plan.is_some());
+        assert_eq!(errors(&issues), Vec::<String>::new());
+    }
+
+    #[test]
+    fn unknown_view_fields_are_reported() {
+        let mut value = plan();
+        // a select view with `data` misspelled still parses as a one value view
+        value["views"][2].as_mapping_mut().unwrap().remove("data");
+        value["views"][2]["dat"] = serde_yaml::from_str("[dev, prod]").unwrap();
+        value["views"][0]["lable"] = Value::from("Limit");
+        let (_, issues) = validate_plan(&config(), "deploy", value, &tokens());
+        assert_eq!(errors(&issues), vec![
+            "plans.deploy.views[0].lable: unknown field",
+            "plans.deploy.views[2].dat: unknown field",
+        ]);
+    }
+
+    #[test]
+    fn unknown_backend_fields_are_reported() {
+        let mut value = plan();
+        value["ansible"]["backend_inventory"]["ref"] = Value::from("main");
+        let (plan, issues) = validate_plan(&config(), "deploy", value, &tokens());
+        assert!(plan.is_none());
+        assert!(errors(&issues).contains(&"plans.deploy.ansible.backend_inventory.ref: unknown field".to_string()), "{:?}", issues);
+    }
+
+    #[test]
+    fn unknown_webhook_view_fields_are_reported() {
+        let mut value = plan();
+        value["webhooks"] = serde_yaml::from_str(r#"
+- name: push
+  trigger_token: DEPLOY_TOKEN
+  type: static
+  views:
+    - text: Limit
+      type: hidden
+      key: LIMIT
+      value: all
+      hidden: true
+"#).unwrap();
+        let (_, issues) = validate_plan(&config(), "deploy", value, &tokens());
+        assert_eq!(errors(&issues), vec!["plans.deploy.webhooks[0].views[0].hidden: unknown field"]);
+    }
+
+    #[test]
+    fn unset_token_var_is_an_error() {
+        let (_, issues) = validate_plan(&config(), "deploy", plan(), &tokens_with(&[]));
+        assert_eq!(errors(&issues), vec![
+            "plans.deploy.gitlab.token_var: env variable DEPLOY_TOKEN is not set",
+            "plans.deploy.ansible.backend_inventory.token_var: env variable DEPLOY_TOKEN is not set",
+        ]);
+    }
+
+    #[test]
+    fn issues_of_other_plans_are_left_out() {
+        let mut config = config();
+        let mut broken = plan();
+        broken["gitlab"]["token_var"] = Value::from("UNSET_TOKEN");
+        config.plans.insert("broken".to_string(), serde_yaml::from_value(broken).unwrap());
+        let (_, issues) = validate_plan(&config, "deploy", plan(), &tokens());
+        assert_eq!(errors(&issues), Vec::<String>::new());
+    }
+
+    #[test]
+    fn etcd_plan_issues_do_not_fail_validate() {
+        let mut broken = plan();
+        broken["gitlab"]["token_var"] = Value::from("UNSET_TOKEN");
+        let broken: PlimPlan = serde_yaml::from_value(broken).unwrap();
+
+        let mut config = config();
+        config.plans.insert("etcd_deploy".to_string(), broken.clone());
+        config.sources.plans.insert("etcd_deploy".to_string(), vec![ConfigSource::Etcd { etcd_name: "etcd".to_string(), key: "/plans/deploy".to_string(), revision: 2 }]);
+        assert!(config.validate(&tokens()).is_ok());
+
+        config.sources.plans.insert("etcd_deploy".to_string(), vec![ConfigSource::File { path: "plans/deploy.yml".to_string() }]);
+        let error = config.validate(&tokens()).unwrap_err().to_string();
+        assert!(error.contains("plans.etcd_deploy.gitlab.token_var: Secret UNSET_TOKEN is not in secrets.registry"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn config_files_report_unknown_fields_with_their_file() {
+        let dir = std::env::temp_dir().join(format!("plim-validation-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut config: Value = serde_yaml::from_str(CONFIG).unwrap();
+        config.as_mapping_mut().unwrap().remove("plans");
+        fs::write(dir.join("config.yml"), serde_yaml::to_string(&config).unwrap()).unwrap();
+        let mut value = plan();
+        value["views"][1]["fromat"] = Value::from("D.M.YYYY");
+        let plans = Value::Mapping(serde_yaml::Mapping::from_iter([(Value::from("plans"), Value::Mapping(serde_yaml::Mapping::from_iter([(Value::from("deploy"), value)])))]));
+        fs::write(dir.join("plans.yml"), serde_yaml::to_string(&plans).unwrap()).unwrap();
+
+        let (config, issues) = validate_config_files(&[dir.to_str().unwrap()], &tokens()).await;
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(config.is_some());
+        let unknown: Vec<_> = issues.iter().filter(|issue| issue.message == "unknown field").collect();
+        assert_eq!(unknown.len(), 1, "{:?}", issues);
+        assert_eq!(unknown[0].path, "plans.deploy.views[1].fromat");
+        assert_eq!(unknown[0].file.as_deref(), Some(dir.join("plans.yml").to_str().unwrap()));
+    }
+
+    #[test]
+    fn extends_is_rejected_for_single_plans() {
+        let mut value = plan();
+        value["extends"] = Value::from("base");
+        let (_, issues) = validate_plan(&config(), "etcd_deploy", value, &tokens());
+        assert_eq!(errors(&issues), vec!["plans.etcd_deploy.extends: plans.etcd_deploy uses extends, which is only resolved for plans in config files"]);
+    }
+
+    #[test]
+    fn plans_need_a_registry_for_token_variables() {
+        let mut config = config();
+        config.secrets.registry = None;
+        let tokens = GitlabTokens::with_vars(&[("DEPLOY_TOKEN", "secret")]);
+        tokens.adopt(&tokens.staged(&config.secrets, &config.etcd_data_map, None).unwrap());
+        let (_, issues) = validate_plan(&config, "deploy", plan(), &tokens);
+        assert_eq!(errors(&issues), vec![
+            "plans.deploy.gitlab.token_var: Secret DEPLOY_TOKEN can't be used, plans only reach the secrets in secrets.registry and it is not set",
+            "plans.deploy.ansible.backend_inventory.token_var: Secret DEPLOY_TOKEN can't be used, plans only reach the secrets in secrets.registry and it is not set",
+        ]);
+    }
+
+    #[test]
+    fn path_within_only_matches_whole_segments() {
+        assert!(path_within("plans.a", "plans.a"));
+        assert!(path_within("plans.a.views[0]", "plans.a"));
+        assert!(path_within("plans.a[0]", "plans.a"));
+        assert!(!path_within("plans.ab.views", "plans.a"));
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use etcd_client::Client;
+use log::{info, warn};
+use serde::Serialize;
+use thiserror::Error;
+use tokio::sync::{Mutex, RwLock};
+
+use crate::config::EtcdDataMap;
+use crate::state::GitlabTokens;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Error, Debug)]
+pub enum EtcdManagerError {
+    #[error("Etcd {0} is not in etcd_data_map")]
+    UnknownCluster(String),
+    #[error("Etcd {name} is unavailable, next connect in {retry_in_secs}s: {error}")]
+    Unavailable { name: String, error: String, retry_in_secs: u64 },
+}
+
+/// Etcd clients by `etcd_data_map` name, connected on first use and again after a failure with a growing delay
+pub struct EtcdManager {
+    clusters: RwLock<HashMap<String, Arc<Cluster>>>,
+    tokens: GitlabTokens,
+}
+
+struct Cluster {
+    config: EtcdDataMap,
+    connection: Mutex<Connection>,
+}
+
+#[derive(Default)]
+struct Connection {
+    client: Option<Client>,
+    failures: u32,
+    retry_at: Option<Instant>,
+    last_error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EtcdClusterHealth {
+    name: String,
+    address: Vec<String>,
+    pub healthy: bool,
+    version: Option<String>,
+    failures: u32,
+    last_error: Option<String>,
+    retry_in_secs: Option<u64>,
+}
+
+impl EtcdManager {
+    pub fn new(etcd_data_map: &HashMap<String, EtcdDataMap>, tokens: GitlabTokens) -> Self {
+        let clusters = etcd_data_map.iter()
+            .map(|(name, config)| (name.clone(), Arc::new(Cluster::new(config.clone()))))
+            .collect();
+        Self { clusters: RwLock::new(clusters), tokens }
+    }
+
+    /// Client of `name`, connects when there is none and the backoff after the last failure has passed
+    pub async fn client(&self, name: &str) -> Result<Client, EtcdManagerError> {
+        let cluster = self.cluster(name).await?;
+        let mut connection = cluster.connection.lock().await;
+        if let Some(client) = &connection.client {
+            return Ok(client.clone());
+        }
+        if let Some(retry_at) = connection.retry_at.filter(|retry_at| *retry_at > Instant::now()) {
+            return Err(EtcdManagerError::Unavailable {
+                name: name.to_string(),
+                error: connection.last_error.clone().unwrap_or_default(),
+                retry_in_secs: retry_at.saturating_duration_since(Instant::now()).as_secs(),
+            });
+        }
+        match cluster.config.connect(&self.tokens).await {
+            Ok(client) => {
+                info!("Connected to etcd {}", name);
+                *connection = Connection { client: Some(client.clone()), ..Connection::default() };
+                Ok(client)
+            }
+            Err(e) => {
+                let error = format!("{:#}", e);
+                connection.fail(name, error.clone());
+                Err(EtcdManagerError::Unavailable { name: name.to_string(), error, retry_in_secs: connection.backoff().as_secs() })
+            }
+        }
+    }
+
+    /// Drop the client of `name` after a failed request, the next `client` call connects again
+    pub async fn report_failure(&self, name: &str, error: impl ToString) {
+        if let Ok(cluster) = self.cluster(name).await {
+            cluster.connection.lock().await.fail(name, error.to_string());
+        }
+    }
+
+    /// Take the clusters of a reloaded config, clusters with unchanged settings keep their connection
+    pub async fn update_clusters(&self, etcd_data_map: &HashMap<String, EtcdDataMap>) {
+        let mut clusters = self.clusters.write().await;
+        clusters.retain(|name, cluster| etcd_data_map.get(name) == Some(&cluster.config));
+        for (name, config) in etcd_data_map {
+            clusters.entry(name.clone()).or_insert_with(|| {
+                info!("Etcd {} settings changed, reconnecting on next use", name);
+                Arc::new(Cluster::new(config.clone()))
+            });
+        }
+    }
+
+    /// Ask every cluster for its status, unhealthy clusters drop their client
+    pub async fn health(&self) -> Vec<EtcdClusterHealth> {
+        let mut names: Vec<String> = self.clusters.read().await.keys().cloned().collect();
+        names.sort();
+        let mut health = Vec::new();
+        for name in names {
+            let status = match self.client(&name).await {
+                Ok(mut client) => {
+                    let status = match tokio::time::timeout(HEALTH_TIMEOUT, client.status()).await {
+                        Ok(Ok(status)) => Ok(status.version().to_string()),
+                        Ok(Err(e)) => Err(e.to_string()),
+                        Err(_) => Err(format!("status request timed out after {}s", HEALTH_TIMEOUT.as_secs())),
+                    };
+                    if let Err(e) = &status {
+                        self.report_failure(&name, e).await;
+                    }
+                    status
+                }
+                // still waiting for the backoff, the failure is already counted
+                Err(e) => Err(e.to_string()),
+            };
+            let Ok(cluster) = self.cluster(&name).await else { continue };
+            let connection = cluster.connection.lock().await;
+            health.push(EtcdClusterHealth {
+                name,
+                address: cluster.config.address.clone(),
+                healthy: status.is_ok(),
+                version: status.ok(),
+                failures: connection.failures,
+                last_error: connection.last_error.clone(),
+                retry_in_secs: connection.retry_at
+                    .filter(|retry_at| *retry_at > Instant::now())
+                    .map(|retry_at| retry_at.saturating_duration_since(Instant::now()).as_secs()),
+            });
+        }
+        health
+    }
+
+    async fn cluster(&self, name: &str) -> Result<Arc<Cluster>, EtcdManagerError> {
+        self.clusters.read().await.get(name).cloned().ok_or_else(|| EtcdManagerError::UnknownCluster(name.to_string()))
+    }
+}
+
+impl Cluster {
+    fn new(config: EtcdDataMap) -> Self {
+        Self { config, connection: Mutex::new(Connection::default()) }
+    }
+}
+
+impl Connection {
+    fn fail(&mut self, name: &str, error: String) {
+        self.client = None;
+        self.failures += 1;
+        self.retry_at = Some(Instant::now() + self.backoff());
+        warn!("Etcd {} failed {} times, next connect in {}s: {}", name, self.failures, self.backoff().as_secs(), error);
+        self.last_error = Some(error);
+    }
+
+    /// 1s, 2s, 4s ... up to a minute
+    fn backoff(&self) -> Duration {
+        MIN_BACKOFF.saturating_mul(2u32.saturating_pow(self.failures.saturating_sub(1))).min(MAX_BACKOFF)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn etcd(address: &str) -> EtcdDataMap {
+        EtcdDataMap { address: vec![address.to_string()], ..EtcdDataMap::default() }
+    }
+
+    fn manager(clusters: &[(&str, EtcdDataMap)]) -> EtcdManager {
+        let etcd_data_map = clusters.iter().map(|(name, config)| (name.to_string(), config.clone())).collect();
+        EtcdManager::new(&etcd_data_map, GitlabTokens::with_vars(&[]))
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_a_minute() {
+        let backoff = |failures| Connection { failures, ..Connection::default() }.backoff().as_secs();
+        assert_eq!((1..=8).map(backoff).collect::<Vec<_>>(), [1, 2, 4, 8, 16, 32, 60, 60]);
+        assert_eq!(backoff(u32::MAX), 60);
+    }
+
+    #[test]
+    fn fail_counts_and_schedules_the_retry() {
+        let mut connection = Connection::default();
+        connection.fail("main", "refused".to_string());
+        connection.fail("main", "refused again".to_string());
+        assert_eq!(connection.failures, 2);
+        assert_eq!(connection.last_error.as_deref(), Some("refused again"));
+        let retry_in = connection.retry_at.unwrap().saturating_duration_since(Instant::now());
+        assert!(retry_in > Duration::from_secs(1) && retry_in <= Duration::from_secs(2), "{:?}", retry_in);
+    }
+
+    #[tokio::test]
+    async fn failure_drops_the_client_until_the_backoff_passed() {
+        // the client connects lazily, nothing listens on the address
+        let manager = manager(&[("main", etcd("127.0.0.1:1"))]);
+        assert!(manager.client("main").await.is_ok());
+        manager.report_failure("main", "request failed").await;
+        let cluster = manager.cluster("main").await.unwrap();
+        assert!(cluster.connection.lock().await.client.is_none());
+        match manager.client("main").await {
+            Err(EtcdManagerError::Unavailable { error, retry_in_secs, .. }) => {
+                assert_eq!(error, "request failed");
+                assert!(retry_in_secs <= 1);
+            }
+            other => panic!("expected the backoff, got {:?}", other.map(|_| ())),
+        }
+        // a successful connect resets the failures
+        cluster.connection.lock().await.retry_at = Some(Instant::now());
+        assert!(manager.client("main").await.is_ok());
+        assert_eq!(cluster.connection.lock().await.failures, 0);
+    }
+
+    #[tokio::test]
+    async fn failed_connect_starts_the_backoff() {
+        // a user without password_var fails before anything is sent
+        let config = EtcdDataMap { user: Some("plim".to_string()), ..etcd("127.0.0.1:1") };
+        let manager = manager(&[("main", config)]);
+        assert!(matches!(manager.client("main").await, Err(EtcdManagerError::Unavailable { retry_in_secs: 1, .. })));
+        assert!(matches!(manager.client("main").await, Err(EtcdManagerError::Unavailable { .. })));
+        assert_eq!(manager.cluster("main").await.unwrap().connection.lock().await.failures, 1);
+        assert!(matches!(manager.client("other").await, Err(EtcdManagerError::UnknownCluster(_))));
+    }
+
+    #[tokio::test]
+    async fn update_clusters_keeps_unchanged_clusters() {
+        let manager = manager(&[("kept", etcd("127.0.0.1:1")), ("changed", etcd("127.0.0.1:2")), ("removed", etcd("127.0.0.1:3"))]);
+        let kept = manager.cluster("kept").await.unwrap();
+        let changed = manager.cluster("changed").await.unwrap();
+        kept.connection.lock().await.fail("kept", "refused".to_string());
+
+        let reloaded = [("kept", etcd("127.0.0.1:1")), ("changed", etcd("127.0.0.1:4")), ("added", etcd("127.0.0.1:5"))];
+        manager.update_clusters(&reloaded.iter().map(|(name, config)| (name.to_string(), config.clone())).collect()).await;
+
+        assert!(Arc::ptr_eq(&kept, &manager.cluster("kept").await.unwrap()));
+        assert_eq!(manager.cluster("kept").await.unwrap().connection.lock().await.failures, 1);
+        let changed_now = manager.cluster("changed").await.unwrap();
+        assert!(!Arc::ptr_eq(&changed, &changed_now));
+        assert_eq!(changed_now.config.address, ["127.0.0.1:4"]);
+        assert!(manager.cluster("added").await.is_ok());
+        assert!(matches!(manager.cluster("removed").await, Err(EtcdManagerError::UnknownCluster(_))));
+    }
+}
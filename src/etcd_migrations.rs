@@ -0,0 +1,472 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Error};
+use etcd_client::{Client, Compare, CompareOp, GetOptions, Txn, TxnOp};
+use log::info;
+use serde::Deserialize;
+use serde_json::json;
+use serde_yaml::Value;
+
+use crate::config::{get_etcd_prefix, Config, EtcdConfig};
+use crate::config_extends::merge_value;
+use crate::etcd_manager::EtcdManager;
+use crate::state::GitlabTokens;
+
+const DEFAULT_MIGRATIONS_PREFIX: &str = "/plim/migrations";
+/// etcd's default `--max-txn-ops`
+const MAX_TXN_OPS: usize = 128;
+
+/// One `<version>_<name>.yml` file, versions are applied in ascending order
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MigrationFile {
+    #[serde(default)]
+    description: String,
+    #[serde(with = "serde_yaml::with::singleton_map_recursive")]
+    steps: Vec<MigrationStep>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum MigrationStep {
+    Put {
+        #[serde(flatten)]
+        target: StepTarget,
+        value: Option<String>,
+        value_file: Option<String>, // relative to the migrations directory
+    },
+    Delete {
+        #[serde(flatten)]
+        target: StepTarget,
+        #[serde(default)]
+        prefix: bool,
+    },
+    /// Change the YAML values of one key or, without `key`, of every key below the target prefix
+    Transform {
+        #[serde(flatten)]
+        target: StepTarget,
+        merge: Option<Value>,
+        #[serde(default)]
+        remove: Vec<String>, // dotted field paths
+    },
+}
+
+#[derive(Debug, Deserialize)]
+struct StepTarget {
+    /// Config prefix the key is relative to, without it `key` is a full etcd key
+    target: Option<MigrationTarget>,
+    /// Etcd from `etcd_data_map` for full keys, the migrations etcd by default
+    etcd_name: Option<String>,
+    #[serde(default)]
+    key: String,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum MigrationTarget {
+    Plans,
+    Users,
+    AnsibleInventories,
+}
+
+struct Migration {
+    version: u64,
+    name: String,
+    file: MigrationFile,
+}
+
+#[derive(Debug)]
+enum EtcdChange {
+    Put { key: String, value: String },
+    Delete { key: String },
+}
+
+impl EtcdChange {
+    fn to_txn_op(&self) -> TxnOp {
+        match self {
+            EtcdChange::Put { key, value } => TxnOp::put(key.as_str(), value.as_str(), None),
+            EtcdChange::Delete { key } => TxnOp::delete(key.as_str(), None),
+        }
+    }
+}
+
+impl std::fmt::Display for EtcdChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EtcdChange::Put { key, value } => write!(f, "put {} ({} bytes)", key, value.len()),
+            EtcdChange::Delete { key } => write!(f, "delete {}", key),
+        }
+    }
+}
+
+/// What the steps of one migration wrote so far per etcd and key, `None` is deleted.
+/// Later steps read through it and each key ends up with one change, etcd rejects a txn which writes a key twice
+#[derive(Debug, Default)]
+struct Overlay(BTreeMap<String, BTreeMap<String, Option<String>>>);
+
+impl Overlay {
+    fn write(&mut self, etcd_name: &str, key: String, value: Option<String>) {
+        self.0.entry(etcd_name.to_string()).or_default().insert(key, value);
+    }
+
+    /// `stored` values of `key` or, with `prefix`, of every key below it with the writes of earlier steps applied
+    fn apply(&self, etcd_name: &str, key: &str, prefix: bool, stored: Vec<(String, String)>) -> BTreeMap<String, String> {
+        let mut values: BTreeMap<String, String> = stored.into_iter().collect();
+        let written = self.0.get(etcd_name).into_iter().flatten()
+            .filter(|(written_key, _)| if prefix { written_key.starts_with(key) } else { *written_key == key });
+        for (written_key, value) in written {
+            match value {
+                Some(value) => values.insert(written_key.clone(), value.clone()),
+                None => values.remove(written_key),
+            };
+        }
+        values
+    }
+
+    /// Current values like `apply`, read from etcd
+    async fn read(&self, etcd: &EtcdManager, etcd_name: &str, key: &str, prefix: bool) -> Result<BTreeMap<String, String>, Error> {
+        let mut client = etcd.client(etcd_name).await?;
+        let kvs = match prefix {
+            true => get_etcd_prefix(&mut client, key).await.map(|(kvs, _)| kvs),
+            false => client.get(key, None).await.map(|mut response| response.take_kvs()),
+        }.with_context(|| format!("Failed to read {}", key))?;
+        let stored = kvs.iter()
+            .map(|kv| Ok((kv.key_str()?.to_string(), kv.value_str()?.to_string())))
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(self.apply(etcd_name, key, prefix, stored))
+    }
+
+    fn into_changes(self) -> BTreeMap<String, Vec<EtcdChange>> {
+        self.0.into_iter()
+            .map(|(etcd_name, values)| {
+                let changes = values.into_iter()
+                    .map(|(key, value)| match value {
+                        Some(value) => EtcdChange::Put { key, value },
+                        None => EtcdChange::Delete { key },
+                    })
+                    .collect();
+                (etcd_name, changes)
+            })
+            .collect()
+    }
+}
+
+/// Txns of at most `MAX_TXN_OPS` ops, the last one keeps room for `reserved` more
+fn txn_chunks(changes: &[EtcdChange], reserved: usize) -> Vec<Vec<TxnOp>> {
+    let mut chunks: Vec<Vec<TxnOp>> = changes.chunks(MAX_TXN_OPS)
+        .map(|chunk| chunk.iter().map(EtcdChange::to_txn_op).collect())
+        .collect();
+    if chunks.last().is_none_or(|last| last.len() + reserved > MAX_TXN_OPS) {
+        chunks.push(Vec::new());
+    }
+    chunks
+}
+
+/// Apply the migrations in `dir` which are not recorded in the migrations etcd yet.
+/// Every step can run again with the same result, so a migration failing halfway is safe to retry
+pub async fn run(conf: &Config, dir: &str, dry_run: bool) -> Result<(), Error> {
+    let migrations = read_migrations(Path::new(dir))?;
+    let tracking = migrations_etcd_config(conf);
+    let etcd = EtcdManager::new(&conf.etcd_data_map, GitlabTokens::new());
+    let applied = applied_versions(&mut etcd.client(&tracking.etcd_name).await?, &tracking.key_prefix_path).await?;
+    let pending: Vec<&Migration> = migrations.iter().filter(|m| !applied.contains(&m.version)).collect();
+    info!("Migrations: {} found, {} applied, {} pending", migrations.len(), applied.len(), pending.len());
+    if pending.is_empty() {
+        println!("No pending migrations");
+    }
+    for migration in pending {
+        let mut overlay = Overlay::default();
+        for step in &migration.file.steps {
+            apply_step(conf, &etcd, &tracking, Path::new(dir), step, &mut overlay).await
+                .with_context(|| format!("Migration {} {} failed", migration.version, migration.name))?;
+        }
+        let mut changes = overlay.into_changes();
+        println!("{} {}: {}", migration.version, migration.name, migration.file.description);
+        for (etcd_name, etcd_changes) in &changes {
+            etcd_changes.iter().for_each(|change| println!("  {}: {}", etcd_name, change));
+        }
+        if dry_run {
+            continue;
+        }
+        // other etcds first, the migration counts as applied once the tracking etcd commits its last txn
+        let tracking_changes = changes.remove(&tracking.etcd_name).unwrap_or_default();
+        for (etcd_name, etcd_changes) in changes {
+            for ops in txn_chunks(&etcd_changes, 0).into_iter().filter(|ops| !ops.is_empty()) {
+                etcd.client(&etcd_name).await?.txn(Txn::new().and_then(ops)).await
+                    .with_context(|| format!("Failed to apply migration {} to etcd {}", migration.version, etcd_name))?;
+            }
+        }
+        let version_key = format!("{}/{}", tracking.key_prefix_path.trim_end_matches('/'), migration.version);
+        let record = json!({
+            "name": migration.name,
+            "description": migration.file.description,
+            "applied_at": chrono::Utc::now().to_rfc3339(),
+        });
+        let mut chunks = txn_chunks(&tracking_changes, 1);
+        let mut last = chunks.pop().unwrap_or_default();
+        for ops in chunks {
+            etcd.client(&tracking.etcd_name).await?.txn(Txn::new().and_then(ops)).await
+                .with_context(|| format!("Failed to apply migration {}", migration.version))?;
+        }
+        last.push(TxnOp::put(version_key.as_str(), record.to_string(), None));
+        let txn = Txn::new()
+            .when([Compare::version(version_key.as_str(), CompareOp::Equal, 0)])
+            .and_then(last);
+        let response = etcd.client(&tracking.etcd_name).await?.txn(txn).await
+            .with_context(|| format!("Failed to apply migration {}", migration.version))?;
+        if response.succeeded() {
+            info!("Applied migration {} {}", migration.version, migration.name);
+        } else {
+            info!("Migration {} was applied by another run", migration.version);
+        }
+    }
+    Ok(())
+}
+
+fn migrations_etcd_config(conf: &Config) -> EtcdConfig {
+    conf.etcd_configs.migrations.clone().unwrap_or_else(|| EtcdConfig {
+        etcd_name: conf.etcd_configs.plans.etcd_name.clone(),
+        key_prefix_path: DEFAULT_MIGRATIONS_PREFIX.to_string(),
+    })
+}
+
+fn read_migrations(dir: &Path) -> Result<Vec<Migration>, Error> {
+    let mut migrations: Vec<Migration> = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read migrations directory {}", dir.display()))? {
+        let path = entry?.path();
+        let is_yaml = path.extension().is_some_and(|ext| ext == "yml" || ext == "yaml");
+        let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+        // other files in the directory are values for `value_file`
+        let Some((version, name)) = stem.split_once('_') else { continue };
+        let Ok(version) = version.parse::<u64>() else { continue };
+        if !is_yaml {
+            continue;
+        }
+        if let Some(other) = migrations.iter().find(|m| m.version == version) {
+            return Err(anyhow::anyhow!("Migrations {} and {} have the same version {}", other.name, name, version));
+        }
+        let content = fs::read_to_string(&path).with_context(|| format!("Failed to read migration {}", path.display()))?;
+        let file = serde_yaml::from_str(&content).with_context(|| format!("Failed to parse migration {}", path.display()))?;
+        migrations.push(Migration { version, name: name.to_string(), file });
+    }
+    migrations.sort_by_key(|m| m.version);
+    Ok(migrations)
+}
+
+async fn applied_versions(client: &mut Client, prefix: &str) -> Result<BTreeSet<u64>, Error> {
+    let prefix = format!("{}/", prefix.trim_end_matches('/'));
+    let response = client.get(prefix.as_str(), Some(GetOptions::new().with_prefix().with_keys_only())).await
+        .context("Failed to read applied migrations")?;
+    Ok(response.kvs().iter()
+        .filter_map(|kv| kv.key_str().ok()?.strip_prefix(&prefix)?.parse().ok())
+        .collect())
+}
+
+/// Write the changes of one step to `overlay`, deletes and transforms read the current values through it
+async fn apply_step(conf: &Config, etcd: &EtcdManager, tracking: &EtcdConfig, dir: &Path, step: &MigrationStep, overlay: &mut Overlay) -> Result<(), Error> {
+    match step {
+        MigrationStep::Put { target, value, value_file } => {
+            let (etcd_name, key) = target.resolve(conf, tracking)?;
+            let value = match (value, value_file) {
+                (Some(value), None) => value.clone(),
+                (None, Some(value_file)) => fs::read_to_string(dir.join(value_file))
+                    .with_context(|| format!("Failed to read value file {}", value_file))?,
+                _ => return Err(anyhow::anyhow!("put {} needs either value or value_file", key)),
+            };
+            overlay.write(&etcd_name, key, Some(value));
+        }
+        MigrationStep::Delete { target, prefix: false } => {
+            let (etcd_name, key) = target.resolve(conf, tracking)?;
+            overlay.write(&etcd_name, key, None);
+        }
+        MigrationStep::Delete { target, prefix: true } => {
+            // one delete per key, a range delete would overlap the puts of other steps in the same txn
+            let (etcd_name, key) = target.resolve(conf, tracking)?;
+            for key in overlay.read(etcd, &etcd_name, &key, true).await?.into_keys() {
+                overlay.write(&etcd_name, key, None);
+            }
+        }
+        MigrationStep::Transform { target, merge, remove } => {
+            let (etcd_name, key) = target.resolve(conf, tracking)?;
+            let values = overlay.read(etcd, &etcd_name, &key, target.key.is_empty()).await
+                .with_context(|| format!("Failed to read {} to transform", key))?;
+            for (key, current) in values {
+                let current: Value = serde_yaml::from_str(&current)
+                    .with_context(|| format!("Failed to parse {} to transform", key))?;
+                let mut value = current.clone();
+                if let Some(merge) = merge {
+                    merge_value(&mut value, merge.clone());
+                }
+                remove.iter().for_each(|path| remove_path(&mut value, path));
+                if value != current {
+                    overlay.write(&etcd_name, key, Some(serde_yaml::to_string(&value)?));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+impl StepTarget {
+    fn resolve(&self, conf: &Config, tracking: &EtcdConfig) -> Result<(String, String), Error> {
+        let Some(target) = self.target else {
+            if !self.key.starts_with('/') {
+                return Err(anyhow::anyhow!("key {} needs a target or a full path", self.key));
+            }
+            let etcd_name = self.etcd_name.clone().unwrap_or_else(|| tracking.etcd_name.clone());
+            return Ok((etcd_name, self.key.clone()));
+        };
+        let etcd_config = match target {
+            MigrationTarget::Plans => &conf.etcd_configs.plans,
+            MigrationTarget::Users => &conf.etcd_configs.users,
+            MigrationTarget::AnsibleInventories => &conf.etcd_configs.ansible_inventories,
+        };
+        let prefix = etcd_config.key_prefix_path.trim_end_matches('/');
+        let key = match self.key.trim_start_matches('/') {
+            "" => format!("{}/", prefix),
+            key => format!("{}/{}", prefix, key),
+        };
+        Ok((etcd_config.etcd_name.clone(), key))
+    }
+}
+
+fn remove_path(value: &mut Value, path: &str) {
+    match path.split_once('.') {
+        Some((field, rest)) => {
+            if let Some(child) = value.get_mut(field) {
+                remove_path(child, rest);
+            }
+        }
+        None => {
+            if let Value::Mapping(map) = value {
+                map.remove(path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::EtcdConfigs;
+
+    use super::*;
+
+    fn config() -> Config {
+        let etcd_config = |etcd_name: &str, key_prefix_path: &str| EtcdConfig { etcd_name: etcd_name.to_string(), key_prefix_path: key_prefix_path.to_string() };
+        Config {
+            etcd_configs: EtcdConfigs {
+                plans: etcd_config("main", "/plans/"),
+                users: etcd_config("main", "/users"),
+                ansible_inventories: etcd_config("inventories", "/ansible"),
+                migrations: None,
+            },
+            ..Default::default()
+        }
+    }
+
+    fn target(yaml: &str) -> StepTarget {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("plim-migrations-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn targets_resolve_below_their_prefix() {
+        let conf = config();
+        let tracking = migrations_etcd_config(&conf);
+        assert_eq!(tracking.etcd_name, "main");
+        assert_eq!(target("{target: plans, key: deploy}").resolve(&conf, &tracking).unwrap(), ("main".to_string(), "/plans/deploy".to_string()));
+        assert_eq!(target("{target: users, key: /ops}").resolve(&conf, &tracking).unwrap(), ("main".to_string(), "/users/ops".to_string()));
+        assert_eq!(target("{target: ansible_inventories}").resolve(&conf, &tracking).unwrap(), ("inventories".to_string(), "/ansible/".to_string()));
+    }
+
+    #[test]
+    fn full_keys_use_their_etcd_or_the_migrations_one() {
+        let conf = config();
+        let tracking = migrations_etcd_config(&conf);
+        assert_eq!(target("{key: /test/old}").resolve(&conf, &tracking).unwrap(), ("main".to_string(), "/test/old".to_string()));
+        assert_eq!(target("{key: /test/old, etcd_name: other}").resolve(&conf, &tracking).unwrap(), ("other".to_string(), "/test/old".to_string()));
+        let error = target("{key: test/old}").resolve(&conf, &tracking).unwrap_err();
+        assert_eq!(error.to_string(), "key test/old needs a target or a full path");
+    }
+
+    #[test]
+    fn remove_path_removes_nested_fields() {
+        let mut value: Value = serde_yaml::from_str("{ansible: {vault_pass_file: x, playbook: site.yml}, groups: [ops]}").unwrap();
+        remove_path(&mut value, "ansible.vault_pass_file");
+        remove_path(&mut value, "groups");
+        // missing fields and paths through non-mappings are left alone
+        remove_path(&mut value, "gitlab.ref");
+        remove_path(&mut value, "ansible.playbook.name");
+        assert_eq!(value, serde_yaml::from_str::<Value>("{ansible: {playbook: site.yml}}").unwrap());
+    }
+
+    #[test]
+    fn migrations_are_read_in_version_order() {
+        let dir = temp_dir("read");
+        fs::write(dir.join("0002_rename.yaml"), "steps:\n  - delete: {key: /test/old, prefix: true}\n").unwrap();
+        fs::write(dir.join("0001_seed.yml"), "description: Seed\nsteps:\n  - put: {target: plans, key: deploy, value_file: plan.yml}\n").unwrap();
+        // value files and files without a version are not migrations
+        fs::write(dir.join("0003_plan.json"), "{}").unwrap();
+        fs::write(dir.join("plan.yml"), "groups: [ops]\n").unwrap();
+        let migrations = read_migrations(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let versions: Vec<(u64, &str)> = migrations.iter().map(|m| (m.version, m.name.as_str())).collect();
+        assert_eq!(versions, vec![(1, "seed"), (2, "rename")]);
+        assert_eq!(migrations[0].file.description, "Seed");
+        assert!(matches!(migrations[1].file.steps[0], MigrationStep::Delete { prefix: true, .. }));
+    }
+
+    #[test]
+    fn duplicate_versions_and_bad_files_are_errors() {
+        let dir = temp_dir("duplicate");
+        fs::write(dir.join("0001_seed.yml"), "steps: []\n").unwrap();
+        fs::write(dir.join("1_other.yml"), "steps: []\n").unwrap();
+        let error = read_migrations(&dir).err().unwrap();
+        assert!(error.to_string().contains("have the same version 1"), "{}", error);
+
+        fs::remove_file(dir.join("1_other.yml")).unwrap();
+        fs::write(dir.join("0002_bad.yml"), "steps:\n  - rename: {key: /a}\n").unwrap();
+        let error = read_migrations(&dir).err().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(error.to_string().starts_with("Failed to parse migration"), "{}", error);
+    }
+
+    #[test]
+    fn overlay_folds_writes_per_key() {
+        let mut overlay = Overlay::default();
+        overlay.write("main", "/plans/a".to_string(), Some("1".to_string()));
+        overlay.write("main", "/plans/b".to_string(), None);
+        overlay.write("main", "/plans/a".to_string(), Some("2".to_string()));
+        overlay.write("main", "/plans-old/c".to_string(), Some("3".to_string()));
+        overlay.write("other", "/plans/d".to_string(), Some("4".to_string()));
+
+        let stored = vec![("/plans/b".to_string(), "old".to_string()), ("/plans/e".to_string(), "5".to_string())];
+        let values = overlay.apply("main", "/plans/", true, stored.clone());
+        assert_eq!(values, BTreeMap::from([("/plans/a".to_string(), "2".to_string()), ("/plans/e".to_string(), "5".to_string())]));
+        assert!(overlay.apply("main", "/plans/b", false, stored[..1].to_vec()).is_empty());
+
+        let changes: Vec<String> = overlay.into_changes().iter()
+            .flat_map(|(etcd_name, changes)| changes.iter().map(move |change| format!("{}: {}", etcd_name, change)))
+            .collect();
+        assert_eq!(changes, vec!["main: put /plans-old/c (1 bytes)", "main: put /plans/a (1 bytes)", "main: delete /plans/b", "other: put /plans/d (1 bytes)"]);
+    }
+
+    #[test]
+    fn txns_stay_below_the_op_limit() {
+        let changes: Vec<EtcdChange> = (0..MAX_TXN_OPS * 2).map(|index| EtcdChange::Delete { key: format!("/k/{}", index) }).collect();
+        let sizes = |chunks: Vec<Vec<TxnOp>>| chunks.iter().map(Vec::len).collect::<Vec<_>>();
+        assert_eq!(sizes(txn_chunks(&changes, 0)), vec![MAX_TXN_OPS, MAX_TXN_OPS]);
+        // the version record needs room in the last txn
+        assert_eq!(sizes(txn_chunks(&changes, 1)), vec![MAX_TXN_OPS, MAX_TXN_OPS, 0]);
+        assert_eq!(sizes(txn_chunks(&changes[..10], 1)), vec![10]);
+        assert_eq!(sizes(txn_chunks(&[], 1)), vec![0]);
+    }
+}
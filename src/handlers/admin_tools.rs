@@ -13,6 +13,26 @@ pub async fn gen_password_hash(
     (StatusCode::OK, Json(json!(password_hash))).into_response()
 }
 
+/// Load the config again, the running config stays when the new one fails to load or validate
+pub async fn reload_config(State(state): State<AppState>) -> impl IntoResponse {
+    match state.reload_config().await {
+        Ok(config) => (StatusCode::OK, Json(json!({"reloaded": true, "plans": config.plans.len(), "users": config.users.len()}))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(json!({"error": format!("{:#}", e)}))),
+    }
+}
+
+/// File or etcd key each top-level key, plan and user was read from, in merge order
+pub async fn get_config_sources(State(state): State<AppState>) -> impl IntoResponse {
+    (StatusCode::OK, Json(json!(state.config.load().sources)))
+}
+
+/// Status of every etcd in `etcd_data_map`, 503 when one of them is unreachable
+pub async fn get_etcd_health(State(state): State<AppState>) -> impl IntoResponse {
+    let clusters = state.etcd.health().await;
+    let status = if clusters.iter().all(|cluster| cluster.healthy) { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(json!({"clusters": clusters})))
+}
+
 #[derive(Deserialize, Debug)]
 pub struct PasswordStringRequest {
     password: String,
@@ -27,11 +47,15 @@ struct GenPasswordHash {
 
 impl GenPasswordHash {
     pub fn new(password: String) -> Self {
-        Self { password_hash: if let Ok(hash) = hash(password, DEFAULT_COST) {
+        Self { password_hash: if let Ok(hash) = hash_password(&password) {
             hash
         } else {
             error!("Failed to hash password returning empty string");
             String::new()
         } }
     }
-}
\ No newline at end of file
+}
+
+pub fn hash_password(password: &str) -> Result<String, bcrypt::BcryptError> {
+    hash(password, DEFAULT_COST)
+}
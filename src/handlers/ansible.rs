@@ -1,93 +1,40 @@
-use anyhow::{Context, Error};
+use anyhow::Error;
+use axum::extract::Query;
+use axum::http::header;
 use log::{trace, warn};
 use reqwest::StatusCode;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use indexmap::IndexMap;
 use std::fs;
-use crate::config::{AnsibleBackendType, PlimPlan};
-use configparser::ini::Ini;
+use crate::config::{AnsibleBackendType, AnsibleConfig, AnsibleGitlabBackend, AnyValue, PlaybookSource, PlimPlan};
+use crate::jwt::Claims;
+use crate::secret_registry::PlanScope;
+use super::etcd::get_available_plans;
 use super::gitlab::TriggerPipelineRequest;
 use super::handlers::*;
+use export::InventoryFormat;
+use pattern::HostPattern;
+use playbook::PlaybookSummary;
+use vault::VaultPayload;
+
+pub mod ini;
+pub mod inventory;
+pub mod export;
+pub mod pattern;
+pub mod validate;
+pub mod diff;
+pub mod vault;
+pub mod playbook;
+pub mod merge;
+
 pub async fn get_ansible_inventory(
     State(state): State<AppState>,
     Json(inv): Json<AnsibleInventoryRequest>,
-) -> impl IntoResponse {
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
     info!("Get ansible inventory: {:?}", inv);
-    let inventory: Result<Inventory, (StatusCode, Json<serde_json::Value>)> = match inv {
-        AnsibleInventoryRequest::Raw(inv) => {
-            let inventory_file_format = inv.file_path.split(".").last();
-            match inventory_file_format {
-                Some("yaml") | Some("yml") => Ok(AnsibleInventoryParserLocal::parse_yaml_file(&inv.file_path)?),
-                Some("ini") => Ok(AnsibleInventoryParserLocal::parse_ini_file(&inv.file_path)?),
-                // default to ini
-                _ => Ok(AnsibleInventoryParserLocal::parse_ini_file(&inv.file_path)?),
-            }
-        }
-        AnsibleInventoryRequest::Plan(inv) => {
-            let plan = match state.config.plans.get(&inv.plan_name) {
-                Some(plan) => plan,
-                None => {
-                    return Err((StatusCode::BAD_REQUEST, Json(json!({"error": "Plan not found"}))));
-                }
-            };
-            let file_path = match plan.ansible.clone() {
-                Some(ansible_config) => match ansible_config.backend_inventory {
-                    AnsibleBackendType::Local(local) => local.file_path,
-                    AnsibleBackendType::Gitlab(gitlab) => gitlab.file_path,
-                    AnsibleBackendType::Etcd(_etcd) => ".yml".to_string(), // TODO: add ini support for etcd
-                },
-                None => {
-                    return Err((StatusCode::BAD_REQUEST, Json(json!({"error": "No inventory file path found"}))));
-                }
-            };
-            let inventory_file_format = file_path.split(".").last();
-            match plan.ansible.clone() {
-                Some(ansible_config) => match ansible_config.backend_inventory {
-                    AnsibleBackendType::Gitlab(gitlab) => {
-                        trace!("Loading inventory for gitlab plan: {:?}", gitlab);
-                        match inventory_file_format {
-                            Some("yaml") | Some("yml") => Ok(AnsibleInventoryParserGitlab::parse_yaml(plan, &state).await?),
-                            Some("ini") => Ok(AnsibleInventoryParserGitlab::parse_ini(plan, &state).await?),
-                            // default to ini
-                            _ => Ok(AnsibleInventoryParserGitlab::parse_ini(plan, &state).await?),
-                        }
-                    }
-                    AnsibleBackendType::Local(local) => {
-                        trace!("Loading inventory for local plan: {:?} with file path: {}", &local.type_name, &local.file_path);
-                        match inventory_file_format {
-                            Some("yaml") | Some("yml") => Ok(AnsibleInventoryParserLocal::parse_yaml_file(&file_path)?),
-                            Some("ini") => Ok(AnsibleInventoryParserLocal::parse_ini_file(&file_path)?),
-                            // default to ini
-                            _ => Ok(AnsibleInventoryParserLocal::parse_ini_file(&file_path)?),
-                        }
-                    }
-                    AnsibleBackendType::Etcd(etcd) => {
-                        info!("Loading inventory from etcd: {:?}, file format: {:?}", etcd, inventory_file_format);
-                        let etcd_client = state.etcd_clients_map.get(&etcd.etcd_name)
-                            .ok_or_else(|| (StatusCode::BAD_REQUEST, Json(json!({"error": "Etcd client not found"}))))?;
-                        let etcd_data = etcd_client.clone().get(etcd.key_path.as_str(), None).await
-                            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))))?;
-                        let etcd_data = match etcd_data.kvs().first() {
-                            Some(etcd_data) => etcd_data,
-                            None => return Err((StatusCode::NOT_FOUND, Json(json!({"error": "Key not found"})))),
-                        };
-                        let content = String::from_utf8(etcd_data.value().to_vec())
-                            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))))?;
-                        match inventory_file_format {
-                            Some("yaml") | Some("yml") => Ok(AnsibleInventoryParserLocal::parse_yaml(&content)?),
-                            _ => {
-                                return Err((StatusCode::BAD_REQUEST, Json(json!({"error": "Invalid inventory file format (ini not supported)"}))));
-                            }
-                        }
-                    }
-                },
-                _ => {
-                    return Err((StatusCode::BAD_REQUEST, Json(json!({"error": "No inventory file path found"}))));
-                }
-            }
-        }
-    };
-    
+    let inventory = load_requested_inventory(&state, &inv).await;
+
     trace!("{:?} - inventory", inventory.clone());
 
     Ok((StatusCode::OK, Json(json!(inventory?))).into_response())
@@ -103,6 +50,223 @@ pub async fn get_ansible_cmd(
     };
     Ok((StatusCode::OK, Json(json!(cmd))).into_response())
 }
+
+/// Plan inventory rendered as ini, yaml or json, only for plans the user can see
+pub async fn export_ansible_inventory(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<AnsibleInventoryExportRequest>,
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    info!("Export ansible inventory of plan {} as {:?}", req.plan_name, req.format);
+    let inventory = load_available_plan_inventory(&state, claims, &req.plan_name).await?;
+    match req.format {
+        InventoryFormat::Json => Ok(Json(export::render_list_json(&inventory.flatten())).into_response()),
+        format => {
+            let content = export::render(&inventory, format)
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))))?;
+            Ok(([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], content).into_response())
+        }
+    }
+}
+
+/// Dynamic inventory `--list` answer for a plan inventory
+pub async fn get_dynamic_inventory_list(
+    Path(plan_name): Path<String>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let inventory = load_available_plan_inventory(&state, claims, &plan_name).await?;
+    Ok(Json(export::render_list_json(&inventory.flatten())))
+}
+
+/// Dynamic inventory `--host <name>` answer for a plan inventory
+pub async fn get_dynamic_inventory_host(
+    Path((plan_name, host_name)): Path<(String, String)>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let inventory = load_available_plan_inventory(&state, claims, &plan_name).await?;
+    Ok(Json(export::render_host_json(&inventory.flatten(), &host_name)))
+}
+
+/// Resolve a host pattern against the plan inventory and return the matched hosts
+pub async fn match_ansible_hosts(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<HostPatternRequest>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let inventory = load_available_plan_inventory(&state, claims, &req.plan_name).await?;
+    let pattern = req.pattern.to_string();
+    let hosts = HostPattern::parse(&pattern)
+        .and_then(|host_pattern| host_pattern.resolve(&inventory.flatten()))
+        .map_err(|e| (StatusCode::BAD_REQUEST, Json(json!({"error": e.to_string()}))))?;
+    Ok(Json(json!({"pattern": pattern, "hosts": hosts})))
+}
+
+/// Encrypt a value with the plan vault password, the result can be pasted into a yaml inventory
+pub async fn encrypt_vault_value(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<VaultEncryptRequest>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let password = load_plan_vault_password(&state, claims, &req.plan_name).await?;
+    let payload = VaultPayload::encrypt(&req.value, &password, req.vault_id.as_deref())
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))))?;
+    let yaml = serde_yaml::to_string(&payload.to_yaml_value())
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))))?;
+    Ok(Json(json!({"vault": payload.to_text(), "yaml": yaml})))
+}
+
+/// Plan inventory with every vault value decrypted, admin only
+pub async fn decrypt_ansible_inventory(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<PlanAnsibleInventory>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let password = load_plan_vault_password(&state, claims.clone(), &req.plan_name).await?;
+    let mut inventory = load_available_plan_inventory(&state, claims, &req.plan_name).await?;
+    let decrypted = inventory.decrypt_vault_values(&password)
+        .map_err(|e| (StatusCode::BAD_REQUEST, Json(json!({"error": e.to_string()}))))?;
+    info!("Decrypted {} vault values of plan {} inventory", decrypted, req.plan_name);
+    Ok(Json(json!({"decrypted": decrypted, "inventory": inventory})))
+}
+
+/// Hosts patterns, tags, roles and vars of the plan playbook, `ref` reads it from another git ref than the plan one
+pub async fn get_playbook_summary(
+    Path(plan_name): Path<String>,
+    Query(query): Query<PlaybookQuery>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let available_plans = get_available_plans(claims, state.clone()).await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": "Failed to get available plans"}))))?;
+    let plan = available_plans.get(&plan_name).ok_or_else(|| (StatusCode::NOT_FOUND, Json(json!({"error": "Plan not found"}))))?;
+    let summary = load_plan_playbook(&state, &plan_name, plan, query.ref_name.as_deref()).await?;
+    Ok(Json(json!(summary)))
+}
+
+pub async fn load_plan_playbook(state: &AppState, plan_name: &str, plan: &PlimPlan, ref_name: Option<&str>) -> Result<PlaybookSummary, (StatusCode, Json<serde_json::Value>)> {
+    let ansible = plan.ansible.as_ref().ok_or_else(|| (StatusCode::BAD_REQUEST, Json(json!({"error": "Plan has no ansible config"}))))?;
+    let content = match ansible.playbook_source.clone().unwrap_or_default() {
+        PlaybookSource::Local => fs::read_to_string(&ansible.playbook)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))))?,
+        PlaybookSource::Gitlab => get_gitlab_file_content(
+            state,
+            plan.gitlab.project_id,
+            &ansible.playbook,
+            ref_name.unwrap_or(&plan.gitlab.ref_name),
+            &plan.gitlab.token_var,
+            PlanScope::new(plan_name, plan),
+        ).await?,
+    };
+    PlaybookSummary::parse(&content).map_err(|e| (StatusCode::BAD_REQUEST, Json(json!({"error": e.to_string()}))))
+}
+
+/// Reject a limit which matches no host of the inventory, an unreachable inventory doesn't block the trigger
+pub async fn validate_limit_hosts(state: &AppState, plan: PlanScope<'_>, inventory_config: &AnsibleConfig, ansible_config: &AnsibleConfig) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+    let limit = match &ansible_config.limit_hosts {
+        Some(limit_hosts) if !limit_hosts.is_empty() => limit_hosts.join(","),
+        _ => return Ok(()),
+    };
+    if ansible_config.is_inventory_inline == Some(true) || HostPattern::is_file_reference(&limit) {
+        return Ok(());
+    }
+    let host_pattern = HostPattern::parse(&limit).map_err(|e| (StatusCode::BAD_REQUEST, Json(json!({"error": e.to_string()}))))?;
+    let inventory = match load_plan_inventory(state, plan, inventory_config).await {
+        Ok(inventory) => inventory,
+        Err((_, Json(e))) => {
+            warn!("Skipping limit validation, inventory is not available: {}", e);
+            return Ok(());
+        }
+    };
+    let hosts = host_pattern.resolve(&inventory.flatten()).map_err(|e| (StatusCode::BAD_REQUEST, Json(json!({"error": e.to_string()}))))?;
+    if hosts.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, Json(json!({"error": format!("Limit {} does not match any host in the inventory", limit)}))));
+    }
+    trace!("Limit {} matched hosts: {:?}", limit, hosts);
+    Ok(())
+}
+
+async fn load_plan_vault_password(state: &AppState, claims: Claims, plan_name: &str) -> Result<String, (StatusCode, Json<serde_json::Value>)> {
+    let available_plans = get_available_plans(claims, state.clone()).await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": "Failed to get available plans"}))))?;
+    let plan = available_plans.get(plan_name).ok_or_else(|| (StatusCode::NOT_FOUND, Json(json!({"error": "Plan not found"}))))?;
+    let password_var = plan.ansible.as_ref().and_then(|ansible| ansible.vault_password_var.clone())
+        .ok_or_else(|| (StatusCode::BAD_REQUEST, Json(json!({"error": format!("Plan {} has no vault_password_var", plan_name)}))))?;
+    state.gitlab_tokens.get_for_plan(&password_var, PlanScope::new(plan_name, plan)).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))))
+}
+
+async fn load_available_plan_inventory(state: &AppState, claims: Claims, plan_name: &str) -> Result<Inventory, (StatusCode, Json<serde_json::Value>)> {
+    let available_plans = get_available_plans(claims, state.clone()).await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": "Failed to get available plans"}))))?;
+    if !available_plans.contains_key(plan_name) {
+        return Err((StatusCode::NOT_FOUND, Json(json!({"error": "Plan not found"}))));
+    }
+    load_requested_inventory(state, &AnsibleInventoryRequest::Plan(PlanAnsibleInventory { plan_name: plan_name.to_string() })).await
+}
+
+pub async fn load_requested_inventory(state: &AppState, inv: &AnsibleInventoryRequest) -> Result<Inventory, (StatusCode, Json<serde_json::Value>)> {
+    match inv {
+        AnsibleInventoryRequest::Raw(inv) => AnsibleInventoryParserLocal::parse_file(&inv.file_path),
+        AnsibleInventoryRequest::Plan(inv) => {
+            let config = state.config.load();
+            let plan = match config.plans.get(&inv.plan_name) {
+                Some(plan) => plan,
+                None => {
+                    return Err((StatusCode::BAD_REQUEST, Json(json!({"error": "Plan not found"}))));
+                }
+            };
+            match &plan.ansible {
+                Some(ansible_config) => load_plan_inventory(state, PlanScope::new(&inv.plan_name, plan), ansible_config).await,
+                None => Err((StatusCode::BAD_REQUEST, Json(json!({"error": "No inventory file path found"})))),
+            }
+        }
+    }
+}
+
+/// Inventory of every plan backend merged with the plan conflict policy
+pub async fn load_plan_inventory(state: &AppState, plan: PlanScope<'_>, ansible_config: &AnsibleConfig) -> Result<Inventory, (StatusCode, Json<serde_json::Value>)> {
+    let mut inventories = Vec::new();
+    for backend in ansible_config.inventory_backends() {
+        inventories.push(load_backend_inventory(state, plan, backend).await?);
+    }
+    Inventory::merge_all(inventories, &ansible_config.inventory_conflict_policy.clone().unwrap_or_default())
+        .map_err(|e| (StatusCode::CONFLICT, Json(json!({"error": e.to_string()}))))
+}
+
+pub async fn load_backend_inventory(state: &AppState, plan: PlanScope<'_>, backend: &AnsibleBackendType) -> Result<Inventory, (StatusCode, Json<serde_json::Value>)> {
+    match backend {
+        AnsibleBackendType::Gitlab(gitlab) => {
+            trace!("Loading inventory for gitlab plan: {:?}", gitlab);
+            AnsibleInventoryParserGitlab::parse(gitlab, state, plan).await
+        }
+        AnsibleBackendType::Local(local) => {
+            trace!("Loading inventory for local plan: {:?} with file path: {}", &local.type_name, &local.file_path);
+            AnsibleInventoryParserLocal::parse_file(&local.file_path)
+        }
+        AnsibleBackendType::Etcd(etcd) => {
+            info!("Loading inventory from etcd: {:?}", etcd);
+            let mut etcd_client = state.etcd.client(&etcd.etcd_name).await
+                .map_err(|e| (StatusCode::SERVICE_UNAVAILABLE, Json(json!({"error": e.to_string()}))))?;
+            let etcd_data = match etcd_client.get(etcd.key_path.as_str(), None).await {
+                Ok(etcd_data) => etcd_data,
+                Err(e) => {
+                    state.etcd.report_failure(&etcd.etcd_name, &e).await;
+                    return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))));
+                }
+            };
+            let etcd_data = match etcd_data.kvs().first() {
+                Some(etcd_data) => etcd_data,
+                None => return Err((StatusCode::NOT_FOUND, Json(json!({"error": "Key not found"})))),
+            };
+            let content = String::from_utf8(etcd_data.value().to_vec())
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))))?;
+            AnsibleInventoryParserLocal::parse(&content, InventoryFormat::from_etcd_key(&etcd.key_path))
+        }
+    }
+}
+
 // Failed to deserialize the JSON body into the target type: unknown variant `path`, expected `Local` or `Gitlab` at line 1 column 8
 #[derive(Debug, Deserialize, Clone)]
 #[serde(untagged)]
@@ -121,287 +285,578 @@ pub struct RawLocalAnsibleInventory {
     pub file_path: String,
 }
 
+#[derive(Debug, Deserialize, Clone)]
+pub struct PlaybookQuery {
+    #[serde(rename = "ref")]
+    pub ref_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct VaultEncryptRequest {
+    pub plan_name: String,
+    pub value: String,
+    pub vault_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct HostPatternRequest {
+    pub plan_name: String,
+    #[serde(alias = "limit")]
+    pub pattern: AnyValue,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AnsibleInventoryExportRequest {
+    pub plan_name: String,
+    #[serde(default)]
+    pub format: InventoryFormat,
+}
+
+pub type InventoryVars = BTreeMap<String, serde_yaml::Value>;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct InventoryChildren {
+    #[serde(default, deserialize_with = "null_as_default")]
     pub hosts: HostGroup,
+    #[serde(default, skip_serializing_if = "Option::is_none", deserialize_with = "deserialize_children")]
+    pub children: Option<IndexMap<String, InventoryChildren>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vars: Option<InventoryVars>,
 }
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Inventory(HashMap<String, InventoryChildren>);
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Inventory(pub IndexMap<String, InventoryChildren>);
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct HostGroup(HashMap<String, Option<HostVars>>);
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct HostGroup(pub IndexMap<String, Option<HostVars>>);
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct HostVars {
-    pub ansible_host: Option<String>,
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct HostVars(pub InventoryVars);
+
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+// `children: {webservers: }` is a valid group reference without any content
+fn deserialize_children<'de, D>(deserializer: D) -> Result<Option<IndexMap<String, InventoryChildren>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let children = Option::<IndexMap<String, Option<InventoryChildren>>>::deserialize(deserializer)?;
+    Ok(children.map(|children| children.into_iter().map(|(name, group)| (name, group.unwrap_or_default())).collect()))
 }
 
 pub struct AnsibleInventoryParserLocal;
 
 impl AnsibleInventoryParserLocal {
-    pub fn parse_yaml(content: &str) -> Result<Inventory, (StatusCode, Json<serde_json::Value>)> {
-        let inventory: Inventory = serde_yaml::from_str(content).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))))?;
-        Ok(inventory)
+    pub fn parse(content: &str, format: InventoryFormat) -> Result<Inventory, (StatusCode, Json<serde_json::Value>)> {
+        match format {
+            InventoryFormat::Yaml | InventoryFormat::Json => AnsibleInventoryParserLocal::parse_yaml(content),
+            InventoryFormat::Ini => AnsibleInventoryParserLocal::parse_ini(content),
+        }
     }
-    pub fn parse_yaml_file(file_path: &str) -> Result<Inventory, (StatusCode, Json<serde_json::Value>)> {
+    pub fn parse_file(file_path: &str) -> Result<Inventory, (StatusCode, Json<serde_json::Value>)> {
         let content = fs::read_to_string(file_path).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))))?;
-        AnsibleInventoryParserLocal::parse_yaml(&content)
+        AnsibleInventoryParserLocal::parse(&content, InventoryFormat::from_path(file_path))
     }
-    pub fn parse_ini_file(file_path: &str) -> Result<Inventory, (StatusCode, Json<serde_json::Value>)> {
-        let mut ini = Ini::new();
-        ini.load(file_path).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))))?;
-        parse_ini_inventory(ini).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": format!("{:?}", e)}))))
+    pub fn parse_yaml(content: &str) -> Result<Inventory, (StatusCode, Json<serde_json::Value>)> {
+        let mut inventory: Inventory = serde_yaml::from_str(content).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))))?;
+        inventory.tag_vault_values();
+        Ok(inventory)
+    }
+    pub fn parse_ini(content: &str) -> Result<Inventory, (StatusCode, Json<serde_json::Value>)> {
+        ini::parse_ini_inventory(content).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))))
     }
 }
 
 
 pub struct AnsibleInventoryParserGitlab;
 
+/// Read a text file from a GitLab repository with the token `plan` reads from `token_var`
+pub async fn get_gitlab_file_content(
+    state: &AppState,
+    project_id: u64,
+    file_path: &str,
+    ref_name: &str,
+    token_var: &str,
+    plan: PlanScope<'_>,
+) -> Result<String, (StatusCode, Json<serde_json::Value>)> {
+    let token = state.gitlab_tokens.get_for_plan(token_var, plan).await;
+    let token = match token {
+        Ok(ref token) => token,
+        Err(e) => {
+            return Err((StatusCode::BAD_REQUEST, Json(json!({"error": e.to_string()}))));
+        }
+    };
+    let file_content = state
+        .gitlab_file_cache
+        .get_file(&state.gitlab_client, project_id, file_path, ref_name, token_var, token)
+        .await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))))?;
+    let content_in_base64 = file_content.content.ok_or_else(|| (StatusCode::BAD_REQUEST, Json(json!({"error": "Base64 content not found"}))))?;
+    let content = BASE64_STANDARD.decode(&content_in_base64).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))))?;
+    let result = String::from_utf8(content).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))))?;
+    Ok(result)
+}
+
 impl AnsibleInventoryParserGitlab {
     pub async fn get_file_content(
-        plan: &PlimPlan,
+        backend_inventory: &AnsibleGitlabBackend,
         state: &AppState,
+        plan: PlanScope<'_>,
     ) -> Result<String, (StatusCode, Json<serde_json::Value>)> {
-        let ansible_config = plan.ansible.clone();
-        let backend_inventory = match ansible_config {
-            Some(ansible_config) => match ansible_config.backend_inventory {
-                AnsibleBackendType::Gitlab(gitlab) => gitlab,
-                _ => {
-                    return Err((StatusCode::BAD_REQUEST, Json(json!({"error": "Invalid inventory type"}))));
-                }
-            },
-            None => {
-                return Err((StatusCode::BAD_REQUEST, Json(json!({"error": "No inventory file path found"}))));
-            }
-        };
         let token_var = match backend_inventory.token_var {
             Some(ref token_var) => token_var,
             None => {
                 return Err((StatusCode::BAD_REQUEST, Json(json!({"error": "Token variable not found"}))));
             }
         };
-        let token = state.gitlab_tokens.get(&token_var).await;
-        let token = match token {
-            Ok(ref token) => token,
-            Err(e) => {
-                return Err((StatusCode::BAD_REQUEST, Json(json!({"error": e.to_string()}))));
-            }
-        };
-        let file_content = state
-            .gitlab_client
-            .get_gitlab_file(
-                backend_inventory.project_id.ok_or_else(|| (StatusCode::BAD_REQUEST, Json(json!({"error": "Project ID not found"}))))?,
-                &backend_inventory.file_path,
-                &backend_inventory.ref_name.ok_or_else(|| (StatusCode::BAD_REQUEST, Json(json!({"error": "Ref name not found"}))))?,
-                &token,
-            )
-            .await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))))?;
-        let content_in_base64 = file_content.content.ok_or_else(|| (StatusCode::BAD_REQUEST, Json(json!({"error": "Base64 content not found"}))))?;
-        let content = BASE64_STANDARD.decode(&content_in_base64).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))))?;
-        let result = String::from_utf8(content).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))))?;
-        Ok(result)
-    }
-    pub async fn parse_yaml(
-        plan: &PlimPlan,
-        state: &AppState,
-    ) -> Result<Inventory, (StatusCode, Json<serde_json::Value>)> {
-        let content = AnsibleInventoryParserGitlab::get_file_content(plan, state).await?;
-        trace!("Content: {:?}", content);
-        let inventory: Inventory = serde_yaml::from_str(&content).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))))?;
-        Ok(inventory)
+        get_gitlab_file_content(
+            state,
+            backend_inventory.project_id.ok_or_else(|| (StatusCode::BAD_REQUEST, Json(json!({"error": "Project ID not found"}))))?,
+            &backend_inventory.file_path,
+            backend_inventory.ref_name.as_deref().ok_or_else(|| (StatusCode::BAD_REQUEST, Json(json!({"error": "Ref name not found"}))))?,
+            token_var,
+            plan,
+        ).await
     }
-    pub async fn parse_ini(
-        plan: &PlimPlan,
+    pub async fn parse(
+        backend_inventory: &AnsibleGitlabBackend,
         state: &AppState,
+        plan: PlanScope<'_>,
     ) -> Result<Inventory, (StatusCode, Json<serde_json::Value>)> {
-        let content = AnsibleInventoryParserGitlab::get_file_content(plan, state).await?;
+        let content = AnsibleInventoryParserGitlab::get_file_content(backend_inventory, state, plan).await?;
         trace!("Content: {:?}", content);
-        let mut ini = Ini::new();
-        ini.read(content).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))))?;
-        parse_ini_inventory(ini).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": format!("{:?}", e)}))))
+        AnsibleInventoryParserLocal::parse(&content, InventoryFormat::from_path(&backend_inventory.file_path))
     }
 }
 
-fn parse_ini_inventory(ini: Ini) -> Result<Inventory, (StatusCode, Json<serde_json::Value>)> {
-    let mut inventory_map = HashMap::new();
-
-    // Iterate through sections in INI file
-    for section_name in ini.sections() {
-        // Skip any section that starts with '_' as these are Ansible vars
-        if section_name.starts_with('_') {
-            continue;
-        }
-
-        let mut host_group = HostGroup(HashMap::new());
-        // Process each host in the section
-        if let Some(section_map) = ini
-            .get_map()
-            .context("Failed to get INI map")
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))))?
-            .get(&section_name)
-        {
-            for (key, value) in section_map {
-                // println!("key: {:?}, value: {:?}", key, value);
-                host_group.0.insert(
-                    key.split(" ").next().ok_or_else(|| (StatusCode::BAD_REQUEST, Json(json!({"error": "Host name not found"}))))?.to_string(),
-                    value.as_ref().map(|v| HostVars {
-                        ansible_host: Some(v.to_string()),
-                    }),
-                );
-            }
-        }
-
-        inventory_map.insert(section_name, InventoryChildren { hosts: host_group });
-    }
-    Ok(Inventory(inventory_map))
-}
 // AnsibleCommandGenerator
 #[derive(Default)]
 pub struct AnsibleGenCmd;
 const EXTRA_VARS: &str = "-e"; // --extra-vars
 
 impl AnsibleGenCmd {
+    /// Command line for the pipeline, every argument is shell quoted when needed
     pub fn gen_ansible_cmd(&self, req: &TriggerPipelineRequest) -> Result<String, Error> {
-        let config = req.ansible_data.clone().ok_or(anyhow::anyhow!("Ansible data not found"))?;
-        let req_extra_vars = req.json_data.clone();
-        let mut command = format!("ansible-playbook {}", config.playbook);
+        let command = self.gen_ansible_argv(req)?.iter()
+            .map(|arg| shell_quote(arg))
+            .collect::<Vec<String>>()
+            .join(" ");
+        trace!("Ansible command: {:?}", command);
+        Ok(command)
+    }
 
-        command += &format!(" -i {}", config.inventory);
+    /// Arguments of the `ansible-playbook` call including the program name
+    pub fn gen_ansible_argv(&self, req: &TriggerPipelineRequest) -> Result<Vec<String>, Error> {
+        let config = req.ansible_data.clone().ok_or(anyhow::anyhow!("Ansible data not found"))?;
+        let mut argv = vec!["ansible-playbook".to_string(), config.playbook];
 
-        // Connection options
-        if let Some(private_key) = config.private_key {
-            command += &format!(" --private-key {}", private_key);
-        }
-        if let Some(remote_user) = config.remote_user {
-            command += &format!(" -u {}", remote_user);
-        }
-        if let Some(connection) = config.connection {
-            command += &format!(" -c {}", connection);
-        }
-        if let Some(timeout) = config.timeout {
-            command += &format!(" -T {}", timeout);
-        }
-        if let Some(ssh_common_args) = config.ssh_common_args {
-            command += &format!(" --ssh-common-args '{}'", ssh_common_args);
+        // Inventory options
+        if !config.inventory.is_empty() {
+            push_option(&mut argv, "-i", Some(config.inventory));
         }
-        if let Some(sftp_extra_args) = config.sftp_extra_args {
-            command += &format!(" --sftp-extra-args '{}'", sftp_extra_args);
-        }
-        if let Some(scp_extra_args) = config.scp_extra_args {
-            command += &format!(" --scp-extra-args '{}'", scp_extra_args);
-        }
-        if let Some(ssh_extra_args) = config.ssh_extra_args {
-            command += &format!(" --ssh-extra-args '{}'", ssh_extra_args);
-        }
-        if let Some(ask_pass) = config.ask_pass {
-            if ask_pass {
-                command += " -k";
-            }
+        for inventory in config.inventories.unwrap_or_default() {
+            push_option(&mut argv, "-i", Some(inventory));
         }
+        push_flag(&mut argv, "--flush-cache", config.flush_cache);
 
-        if let Some(privilege_escalation) = config.privilege_escalation {
-            if privilege_escalation {
-                command += " -b";
-            }
-        }
-        if let Some(become_method) = config.become_method {
-            command += &format!(" --become-method {}", become_method);
-        }
-        if let Some(become_user) = config.become_user {
-            command += &format!(" --become-user {}", become_user);
-        }
-        if let Some(ask_become_pass) = config.ask_become_pass {
-            if ask_become_pass {
-                command += " -K";
-            }
-        }
+        // Connection options
+        push_option(&mut argv, "--private-key", config.private_key);
+        push_option(&mut argv, "-u", config.remote_user);
+        push_option(&mut argv, "-c", config.connection);
+        push_option(&mut argv, "-T", config.timeout);
+        push_option(&mut argv, "--ssh-common-args", config.ssh_common_args);
+        push_option(&mut argv, "--sftp-extra-args", config.sftp_extra_args);
+        push_option(&mut argv, "--scp-extra-args", config.scp_extra_args);
+        push_option(&mut argv, "--ssh-extra-args", config.ssh_extra_args);
+        push_flag(&mut argv, "-k", config.ask_pass);
+        push_option(&mut argv, "--connection-password-file", config.connection_password_file);
+
+        // Privilege escalation options
+        push_flag(&mut argv, "-b", config.privilege_escalation);
+        push_option(&mut argv, "--become-method", config.become_method);
+        push_option(&mut argv, "--become-user", config.become_user);
+        push_flag(&mut argv, "-K", config.ask_become_pass);
+        push_option(&mut argv, "--become-password-file", config.become_password_file);
 
         // Additional playbook options
-        if let Some(tags) = config.tags {
-            if !tags.is_empty() {
-                let tags_str = tags.join(",");
-                command += &format!(" -t {}", tags_str);
-            }
-        }
-        if let Some(skip_tags) = config.skip_tags {
-            command += &format!(" --skip-tags {}", skip_tags);
-        }
-        if let Some(forks) = config.forks {
-            command += &format!(" -f {}", forks);
-        }
-        if let Some(limit_hosts) = config.limit_hosts {
-            if !limit_hosts.is_empty() {
-                let lhosts = limit_hosts.join(",");
-                command += &format!(" -l {}", lhosts);
-                if let Some(is_inventory_inline) = config.is_inventory_inline {
-                    if is_inventory_inline {
-                        command += ",";
-                    }
-                }
+        push_option(&mut argv, "-t", config.tags.filter(|tags| !tags.is_empty()).map(|tags| tags.join(",")));
+        push_option(&mut argv, "--skip-tags", config.skip_tags);
+        push_option(&mut argv, "-f", config.forks);
+        if let Some(limit_hosts) = config.limit_hosts.filter(|limit_hosts| !limit_hosts.is_empty()) {
+            let mut limit = limit_hosts.join(",");
+            if config.is_inventory_inline == Some(true) {
+                limit += ",";
             }
+            push_option(&mut argv, "-l", Some(limit));
         }
-        if let Some(verbosity) = config.verbosity {
-            if verbosity != 0 {
-                command += &format!(" -{}", "v".repeat(verbosity as usize));
-            }
+        if let Some(verbosity) = config.verbosity.filter(|verbosity| *verbosity != 0) {
+            argv.push(format!("-{}", "v".repeat(verbosity as usize)));
         }
+        push_option(&mut argv, "-M", config.module_path);
+        push_flag(&mut argv, "--force-handlers", config.force_handlers);
 
         // Vault options
-        if let Some(vault_password_file) = config.vault_password_file {
-            command += &format!(" --vault-password-file {}", vault_password_file);
+        push_option(&mut argv, "--vault-password-file", config.vault_password_file);
+        for vault_id in config.vault_ids.unwrap_or_default() {
+            push_option(&mut argv, "--vault-id", Some(vault_id));
         }
+        push_flag(&mut argv, "-J", config.ask_vault_pass);
 
         // Other options
-        if let Some(syntax_check) = config.syntax_check {
-            if syntax_check {
-                command += " --syntax-check";
-            }
-        }
-        if let Some(diff) = config.diff {
-            if diff {
-                command += " --diff";
-            }
-        }
-        if let Some(check) = config.check {
-            if check {
-                command += " --check";
-            }
-        }
-        if let Some(list_hosts) = config.list_hosts {
-            if list_hosts {
-                command += " --list-hosts";
-            }
-        }
-        if let Some(list_tasks) = config.list_tasks {
-            if list_tasks {
-                command += " --list-tasks";
-            }
-        }
-        if let Some(list_tags) = config.list_tags {
-            if list_tags {
-                command += " --list-tags";
-            }
-        }
-        if let Some(start_at_task) = config.start_at_task {
-            command += &format!(" --start-at-task '{}'", start_at_task);
-        }
-        // Add extra vars
-        if let Some(extra_vars) = config.extra_vars {
-            for (key, value) in extra_vars {
-                command += &format!(" {EXTRA_VARS} {}='{}'", key, value);
+        push_flag(&mut argv, "--syntax-check", config.syntax_check);
+        push_flag(&mut argv, "--diff", config.diff);
+        push_flag(&mut argv, "--check", config.check);
+        push_flag(&mut argv, "--list-hosts", config.list_hosts);
+        push_flag(&mut argv, "--list-tasks", config.list_tasks);
+        push_flag(&mut argv, "--list-tags", config.list_tags);
+        push_option(&mut argv, "--start-at-task", config.start_at_task);
+        push_flag(&mut argv, "--version", config.version);
+
+        // Add extra vars, sorted to keep the command stable between calls
+        for file in config.extra_vars_files.unwrap_or_default() {
+            let file = if file.starts_with('@') { file } else { format!("@{}", file) };
+            push_option(&mut argv, EXTRA_VARS, Some(file));
+        }
+        let extra_vars: BTreeMap<String, String> = config.extra_vars.unwrap_or_default().into_iter().collect();
+        for (key, value) in extra_vars {
+            push_option(&mut argv, EXTRA_VARS, Some(format!("{}={}", key, value)));
+        }
+        let req_extra_vars: BTreeMap<String, Option<AnyValue>> = req.json_data.clone().unwrap_or_default().into_iter().collect();
+        for (key, value) in req_extra_vars {
+            match value {
+                Some(value) => push_option(&mut argv, EXTRA_VARS, Some(format!("{}={}", key, value.to_string()))),
+                None => warn!("Extra var {} is None", key),
             }
         }
-        if let Some(req_extra_vars) = req_extra_vars {
-            for (key, value) in req_extra_vars {
-                if let Some(value) = value {
-                    command += &format!(" {EXTRA_VARS} {}='{}'", key, value.to_string());
-                } else {
-                    warn!("Extra var {} is None", key);
-                }
-            }
+        Ok(argv)
+    }
+}
+
+fn push_flag(argv: &mut Vec<String>, flag: &str, enabled: Option<bool>) {
+    if enabled == Some(true) {
+        argv.push(flag.to_string());
+    }
+}
+
+fn push_option<T: ToString>(argv: &mut Vec<String>, option: &str, value: Option<T>) {
+    if let Some(value) = value {
+        argv.push(option.to_string());
+        argv.push(value.to_string());
+    }
+}
+
+/// Quote an argument for a POSIX shell, plain words are left as they are
+fn shell_quote(arg: &str) -> String {
+    let is_plain = !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "@%+=:,./-_".contains(c));
+    if is_plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn base_config() -> AnsibleConfig {
+        AnsibleConfig {
+            playbook: "site.yml".to_string(),
+            inventory: "hosts.ini".to_string(),
+            ..Default::default()
         }
-        trace!("Ansible command: {:?}", command);
-        Ok(command)
+    }
+
+    fn argv(config: AnsibleConfig) -> Vec<String> {
+        argv_with_json(config, None)
+    }
+
+    fn argv_with_json(config: AnsibleConfig, json_data: Option<HashMap<String, Option<AnyValue>>>) -> Vec<String> {
+        let req = TriggerPipelineRequest::new(json_data, Some(config), None);
+        AnsibleGenCmd.gen_ansible_argv(&req).unwrap()
+    }
+
+    /// argv of the base config followed by `extra`
+    fn expected(extra: &[&str]) -> Vec<String> {
+        ["ansible-playbook", "site.yml", "-i", "hosts.ini"].iter().chain(extra).map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn missing_ansible_data_is_an_error() {
+        let req = TriggerPipelineRequest::new(None, None, None);
+        assert!(AnsibleGenCmd.gen_ansible_argv(&req).is_err());
+    }
+
+    #[test]
+    fn base_command() {
+        assert_eq!(argv(base_config()), expected(&[]));
+    }
+
+    #[test]
+    fn empty_inventory_is_skipped() {
+        let config = AnsibleConfig { inventory: String::new(), ..base_config() };
+        assert_eq!(argv(config), vec!["ansible-playbook", "site.yml"]);
+    }
+
+    #[test]
+    fn multiple_inventories() {
+        let config = AnsibleConfig { inventories: Some(vec!["etcd.yml".to_string(), "cloud.yml".to_string()]), ..base_config() };
+        assert_eq!(argv(config), expected(&["-i", "etcd.yml", "-i", "cloud.yml"]));
+    }
+
+    #[test]
+    fn flush_cache() {
+        let config = AnsibleConfig { flush_cache: Some(true), ..base_config() };
+        assert_eq!(argv(config), expected(&["--flush-cache"]));
+    }
+
+    #[test]
+    fn private_key() {
+        let config = AnsibleConfig { private_key: Some("~/.ssh/id_rsa".to_string()), ..base_config() };
+        assert_eq!(argv(config), expected(&["--private-key", "~/.ssh/id_rsa"]));
+    }
+
+    #[test]
+    fn remote_user() {
+        let config = AnsibleConfig { remote_user: Some("deploy".to_string()), ..base_config() };
+        assert_eq!(argv(config), expected(&["-u", "deploy"]));
+    }
+
+    #[test]
+    fn connection() {
+        let config = AnsibleConfig { connection: Some("local".to_string()), ..base_config() };
+        assert_eq!(argv(config), expected(&["-c", "local"]));
+    }
+
+    #[test]
+    fn timeout() {
+        let config = AnsibleConfig { timeout: Some(30), ..base_config() };
+        assert_eq!(argv(config), expected(&["-T", "30"]));
+    }
+
+    #[test]
+    fn ssh_args() {
+        let config = AnsibleConfig {
+            ssh_common_args: Some("-o ProxyJump=bastion".to_string()),
+            sftp_extra_args: Some("-l 1000".to_string()),
+            scp_extra_args: Some("-O".to_string()),
+            ssh_extra_args: Some("-o ForwardAgent=yes".to_string()),
+            ..base_config()
+        };
+        assert_eq!(argv(config), expected(&[
+            "--ssh-common-args", "-o ProxyJump=bastion",
+            "--sftp-extra-args", "-l 1000",
+            "--scp-extra-args", "-O",
+            "--ssh-extra-args", "-o ForwardAgent=yes",
+        ]));
+    }
+
+    #[test]
+    fn ask_pass() {
+        let config = AnsibleConfig { ask_pass: Some(true), ..base_config() };
+        assert_eq!(argv(config), expected(&["-k"]));
+    }
+
+    #[test]
+    fn connection_password_file() {
+        let config = AnsibleConfig { connection_password_file: Some("conn.pass".to_string()), ..base_config() };
+        assert_eq!(argv(config), expected(&["--connection-password-file", "conn.pass"]));
+    }
+
+    #[test]
+    fn privilege_escalation() {
+        let config = AnsibleConfig {
+            privilege_escalation: Some(true),
+            become_method: Some("sudo".to_string()),
+            become_user: Some("root".to_string()),
+            ..base_config()
+        };
+        assert_eq!(argv(config), expected(&["-b", "--become-method", "sudo", "--become-user", "root"]));
+    }
+
+    #[test]
+    fn ask_become_pass() {
+        let config = AnsibleConfig { ask_become_pass: Some(true), ..base_config() };
+        assert_eq!(argv(config), expected(&["-K"]));
+    }
+
+    #[test]
+    fn become_password_file() {
+        let config = AnsibleConfig { become_password_file: Some("become.pass".to_string()), ..base_config() };
+        assert_eq!(argv(config), expected(&["--become-password-file", "become.pass"]));
+    }
+
+    #[test]
+    fn disabled_flags_are_skipped() {
+        let config = AnsibleConfig {
+            ask_pass: Some(false),
+            privilege_escalation: Some(false),
+            ask_become_pass: Some(false),
+            force_handlers: Some(false),
+            ask_vault_pass: Some(false),
+            syntax_check: Some(false),
+            diff: Some(false),
+            check: Some(false),
+            list_hosts: Some(false),
+            list_tasks: Some(false),
+            list_tags: Some(false),
+            flush_cache: Some(false),
+            version: Some(false),
+            verbosity: Some(0),
+            tags: Some(vec![]),
+            limit_hosts: Some(vec![]),
+            ..base_config()
+        };
+        assert_eq!(argv(config), expected(&[]));
+    }
+
+    #[test]
+    fn tags() {
+        let config = AnsibleConfig { tags: Some(vec!["deploy".to_string(), "config".to_string()]), ..base_config() };
+        assert_eq!(argv(config), expected(&["-t", "deploy,config"]));
+    }
+
+    #[test]
+    fn skip_tags() {
+        let config = AnsibleConfig { skip_tags: Some("slow".to_string()), ..base_config() };
+        assert_eq!(argv(config), expected(&["--skip-tags", "slow"]));
+    }
+
+    #[test]
+    fn forks() {
+        let config = AnsibleConfig { forks: Some(10), ..base_config() };
+        assert_eq!(argv(config), expected(&["-f", "10"]));
+    }
+
+    #[test]
+    fn limit() {
+        let config = AnsibleConfig { limit_hosts: Some(vec!["web".to_string(), "!web-01".to_string()]), ..base_config() };
+        assert_eq!(argv(config), expected(&["-l", "web,!web-01"]));
+    }
+
+    #[test]
+    fn limit_with_inline_inventory() {
+        let config = AnsibleConfig {
+            is_inventory_inline: Some(true),
+            limit_hosts: Some(vec!["10.0.0.1".to_string()]),
+            ..base_config()
+        };
+        assert_eq!(argv(config), expected(&["-l", "10.0.0.1,"]));
+    }
+
+    #[test]
+    fn verbosity() {
+        let config = AnsibleConfig { verbosity: Some(3), ..base_config() };
+        assert_eq!(argv(config), expected(&["-vvv"]));
+    }
+
+    #[test]
+    fn module_path() {
+        let config = AnsibleConfig { module_path: Some("./library".to_string()), ..base_config() };
+        assert_eq!(argv(config), expected(&["-M", "./library"]));
+    }
+
+    #[test]
+    fn force_handlers() {
+        let config = AnsibleConfig { force_handlers: Some(true), ..base_config() };
+        assert_eq!(argv(config), expected(&["--force-handlers"]));
+    }
+
+    #[test]
+    fn vault_password_file() {
+        let config = AnsibleConfig { vault_password_file: Some("vault.pass".to_string()), ..base_config() };
+        assert_eq!(argv(config), expected(&["--vault-password-file", "vault.pass"]));
+    }
+
+    #[test]
+    fn vault_ids() {
+        let config = AnsibleConfig { vault_ids: Some(vec!["dev@dev.pass".to_string(), "prod@prompt".to_string()]), ..base_config() };
+        assert_eq!(argv(config), expected(&["--vault-id", "dev@dev.pass", "--vault-id", "prod@prompt"]));
+    }
+
+    #[test]
+    fn ask_vault_pass() {
+        let config = AnsibleConfig { ask_vault_pass: Some(true), ..base_config() };
+        assert_eq!(argv(config), expected(&["-J"]));
+    }
+
+    #[test]
+    fn syntax_check() {
+        let config = AnsibleConfig { syntax_check: Some(true), ..base_config() };
+        assert_eq!(argv(config), expected(&["--syntax-check"]));
+    }
+
+    #[test]
+    fn diff() {
+        let config = AnsibleConfig { diff: Some(true), ..base_config() };
+        assert_eq!(argv(config), expected(&["--diff"]));
+    }
+
+    #[test]
+    fn check() {
+        let config = AnsibleConfig { check: Some(true), ..base_config() };
+        assert_eq!(argv(config), expected(&["--check"]));
+    }
+
+    #[test]
+    fn list_options() {
+        let config = AnsibleConfig { list_hosts: Some(true), list_tasks: Some(true), list_tags: Some(true), ..base_config() };
+        assert_eq!(argv(config), expected(&["--list-hosts", "--list-tasks", "--list-tags"]));
+    }
+
+    #[test]
+    fn start_at_task() {
+        let config = AnsibleConfig { start_at_task: Some("Install packages".to_string()), ..base_config() };
+        assert_eq!(argv(config), expected(&["--start-at-task", "Install packages"]));
+    }
+
+    #[test]
+    fn version() {
+        let config = AnsibleConfig { version: Some(true), ..base_config() };
+        assert_eq!(argv(config), expected(&["--version"]));
+    }
+
+    #[test]
+    fn extra_vars_files() {
+        let config = AnsibleConfig { extra_vars_files: Some(vec!["vars/prod.yml".to_string(), "@vars/common.yml".to_string()]), ..base_config() };
+        assert_eq!(argv(config), expected(&["-e", "@vars/prod.yml", "-e", "@vars/common.yml"]));
+    }
+
+    #[test]
+    fn extra_vars_are_sorted() {
+        let extra_vars = HashMap::from([("b".to_string(), "2".to_string()), ("a".to_string(), "1 2".to_string())]);
+        let config = AnsibleConfig { extra_vars: Some(extra_vars), ..base_config() };
+        assert_eq!(argv(config), expected(&["-e", "a=1 2", "-e", "b=2"]));
+    }
+
+    #[test]
+    fn request_extra_vars_follow_config_ones() {
+        let json_data = HashMap::from([
+            ("VERSION".to_string(), Some(AnyValue::String("1.2.3".to_string()))),
+            ("HOSTS".to_string(), Some(AnyValue::VecString(vec!["a".to_string(), "b".to_string()]))),
+            ("EMPTY".to_string(), None),
+        ]);
+        let config = AnsibleConfig { extra_vars: Some(HashMap::from([("env".to_string(), "prod".to_string())])), ..base_config() };
+        assert_eq!(argv_with_json(config, Some(json_data)), expected(&["-e", "env=prod", "-e", "HOSTS=a,b", "-e", "VERSION=1.2.3"]));
+    }
+
+    #[test]
+    fn command_is_shell_quoted() {
+        let config = AnsibleConfig {
+            start_at_task: Some("Don't restart".to_string()),
+            extra_vars: Some(HashMap::from([("msg".to_string(), "hello world".to_string())])),
+            ..base_config()
+        };
+        let req = TriggerPipelineRequest::new(None, Some(config), None);
+        assert_eq!(
+            AnsibleGenCmd.gen_ansible_cmd(&req).unwrap(),
+            "ansible-playbook site.yml -i hosts.ini --start-at-task 'Don'\\''t restart' -e 'msg=hello world'"
+        );
     }
 }
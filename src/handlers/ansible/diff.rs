@@ -0,0 +1,183 @@
+use std::collections::BTreeSet;
+
+use serde::Serialize;
+
+use super::inventory::FlatInventory;
+use super::{Inventory, InventoryVars};
+
+/// Changed direct members of a group, hosts inherited from children are not repeated here
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GroupMembershipChange {
+    pub group: String,
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub added_hosts: BTreeSet<String>,
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub removed_hosts: BTreeSet<String>,
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub added_children: BTreeSet<String>,
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub removed_children: BTreeSet<String>,
+}
+
+impl GroupMembershipChange {
+    fn is_empty(&self) -> bool {
+        self.added_hosts.is_empty() && self.removed_hosts.is_empty() && self.added_children.is_empty() && self.removed_children.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VarOwnerKind {
+    Host,
+    Group,
+}
+
+/// Variable added (`old` is empty), removed (`new` is empty) or changed
+#[derive(Debug, Clone, Serialize)]
+pub struct VarChange {
+    pub owner_kind: VarOwnerKind,
+    pub owner: String,
+    pub name: String,
+    pub old: Option<serde_yaml::Value>,
+    pub new: Option<serde_yaml::Value>,
+}
+
+/// Semantic difference between two inventories, formatting and ordering changes are ignored
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct InventoryDiff {
+    pub added_hosts: BTreeSet<String>,
+    pub removed_hosts: BTreeSet<String>,
+    pub added_groups: BTreeSet<String>,
+    pub removed_groups: BTreeSet<String>,
+    pub membership_changes: Vec<GroupMembershipChange>,
+    pub var_changes: Vec<VarChange>,
+}
+
+impl InventoryDiff {
+    pub fn between(old: &Inventory, new: &Inventory) -> Self {
+        InventoryDiff::between_flat(&old.flatten(), &new.flatten())
+    }
+
+    pub fn between_flat(old: &FlatInventory, new: &FlatInventory) -> Self {
+        let mut diff = InventoryDiff {
+            added_hosts: added_keys(old.hosts.keys(), new.hosts.keys()),
+            removed_hosts: added_keys(new.hosts.keys(), old.hosts.keys()),
+            added_groups: added_keys(old.groups.keys(), new.groups.keys()),
+            removed_groups: added_keys(new.groups.keys(), old.groups.keys()),
+            ..Default::default()
+        };
+
+        let group_names: BTreeSet<&String> = old.groups.keys().chain(new.groups.keys()).collect();
+        let empty_group = Default::default();
+        for name in group_names {
+            let old_group = old.groups.get(name).unwrap_or(&empty_group);
+            let new_group = new.groups.get(name).unwrap_or(&empty_group);
+            let change = GroupMembershipChange {
+                group: name.clone(),
+                added_hosts: new_group.hosts.difference(&old_group.hosts).cloned().collect(),
+                removed_hosts: old_group.hosts.difference(&new_group.hosts).cloned().collect(),
+                added_children: new_group.children.difference(&old_group.children).cloned().collect(),
+                removed_children: old_group.children.difference(&new_group.children).cloned().collect(),
+            };
+            if !change.is_empty() {
+                diff.membership_changes.push(change);
+            }
+            diff.collect_var_changes(VarOwnerKind::Group, name, &old_group.vars, &new_group.vars);
+        }
+
+        let host_names: BTreeSet<&String> = old.hosts.keys().chain(new.hosts.keys()).collect();
+        let empty_vars = InventoryVars::new();
+        for name in host_names {
+            let old_vars = old.hosts.get(name).unwrap_or(&empty_vars);
+            let new_vars = new.hosts.get(name).unwrap_or(&empty_vars);
+            diff.collect_var_changes(VarOwnerKind::Host, name, old_vars, new_vars);
+        }
+        diff
+    }
+
+    fn collect_var_changes(&mut self, owner_kind: VarOwnerKind, owner: &str, old: &InventoryVars, new: &InventoryVars) {
+        let names: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+        for name in names {
+            let (old_value, new_value) = (old.get(name), new.get(name));
+            if old_value != new_value {
+                self.var_changes.push(VarChange {
+                    owner_kind,
+                    owner: owner.to_string(),
+                    name: name.clone(),
+                    old: old_value.cloned(),
+                    new: new_value.cloned(),
+                });
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added_hosts.is_empty()
+            && self.removed_hosts.is_empty()
+            && self.added_groups.is_empty()
+            && self.removed_groups.is_empty()
+            && self.membership_changes.is_empty()
+            && self.var_changes.is_empty()
+    }
+}
+
+fn added_keys<'a>(old: impl Iterator<Item = &'a String>, new: impl Iterator<Item = &'a String>) -> BTreeSet<String> {
+    let old: BTreeSet<&String> = old.collect();
+    new.filter(|key| !old.contains(key)).cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::ansible::ini::parse_ini_inventory;
+
+    fn diff(old: &str, new: &str) -> InventoryDiff {
+        InventoryDiff::between(&parse_ini_inventory(old).unwrap(), &parse_ini_inventory(new).unwrap())
+    }
+
+    fn set(items: &[&str]) -> BTreeSet<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn reordering_is_no_change() {
+        let old = "[web]\nweb1 port=22\nweb2\n\n[db]\ndb1\n";
+        let new = "[db]\ndb1\n\n[web]\nweb2\nweb1   port=22\n";
+        assert!(diff(old, new).is_empty());
+    }
+
+    #[test]
+    fn added_and_removed_hosts_and_groups() {
+        let diff = diff("[web]\nweb1\nweb2\n", "[web]\nweb1\n\n[db]\ndb1\n");
+        assert_eq!(diff.added_hosts, set(&["db1"]));
+        assert_eq!(diff.removed_hosts, set(&["web2"]));
+        assert_eq!(diff.added_groups, set(&["db"]));
+        assert!(diff.removed_groups.is_empty());
+    }
+
+    #[test]
+    fn membership_changes() {
+        let diff = diff("[web]\nweb1\n\n[db]\nweb1\n", "[web]\nweb1\n\n[app]\nweb1\n\n[all:children]\napp\n");
+        let db = diff.membership_changes.iter().find(|change| change.group == "db").unwrap();
+        assert_eq!(db.removed_hosts, set(&["web1"]));
+        let app = diff.membership_changes.iter().find(|change| change.group == "app").unwrap();
+        assert_eq!(app.added_hosts, set(&["web1"]));
+        let all = diff.membership_changes.iter().find(|change| change.group == "all").unwrap();
+        assert_eq!(all.added_children, set(&["app"]));
+        assert!(diff.membership_changes.iter().all(|change| change.group != "web"));
+    }
+
+    #[test]
+    fn var_changes() {
+        let diff = diff("[web]\nweb1 port=22 user=root\n\n[web:vars]\nenv=prod\n", "[web]\nweb1 port=2222 key=id\n\n[web:vars]\nenv=prod\n");
+        let render = |value: &Option<serde_yaml::Value>| value.as_ref().map(|value| serde_yaml::to_string(value).unwrap().trim().to_string());
+        let changes: Vec<String> = diff.var_changes.iter()
+            .map(|change| format!("{:?} {}.{}: {:?} -> {:?}", change.owner_kind, change.owner, change.name, render(&change.old), render(&change.new)))
+            .collect();
+        assert_eq!(changes, [
+            "Host web1.key: None -> Some(\"id\")",
+            "Host web1.port: Some(\"22\") -> Some(\"2222\")",
+            "Host web1.user: Some(\"root\") -> None",
+        ]);
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use indexmap::IndexSet;
+use anyhow::{Context, Error};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use super::inventory::{FlatInventory, ALL_GROUP_NAME, UNGROUPED_GROUP_NAME};
+use super::vault::to_export_value;
+use super::{Inventory, InventoryVars};
+
+/// Output formats supported by the inventory export
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InventoryFormat {
+    Ini,
+    #[default]
+    Yaml,
+    /// `ansible-inventory --list` compatible JSON
+    Json,
+}
+
+impl InventoryFormat {
+    /// Guess inventory format by file extension, unknown extensions are treated as ini
+    pub fn from_path(path: &str) -> Self {
+        match path.rsplit('.').next() {
+            Some("yaml") | Some("yml") => InventoryFormat::Yaml,
+            Some("json") => InventoryFormat::Json,
+            _ => InventoryFormat::Ini,
+        }
+    }
+
+    /// etcd keys usually have no extension, yaml is the default there
+    pub fn from_etcd_key(key_path: &str) -> Self {
+        match InventoryFormat::from_path(key_path) {
+            InventoryFormat::Ini if !key_path.ends_with(".ini") => InventoryFormat::Yaml,
+            format => format,
+        }
+    }
+}
+
+pub fn render(inventory: &Inventory, format: InventoryFormat) -> Result<String, Error> {
+    match format {
+        InventoryFormat::Ini => Ok(render_ini(&inventory.flatten())),
+        InventoryFormat::Yaml => serde_yaml::to_string(&inventory.flatten().to_inventory()).context("Failed to render yaml inventory"),
+        InventoryFormat::Json => serde_json::to_string_pretty(&render_list_json(&inventory.flatten())).context("Failed to render json inventory"),
+    }
+}
+
+/// Same structure as `ansible-inventory --list --export` prints, also valid dynamic inventory output
+pub fn render_list_json(flat: &FlatInventory) -> Value {
+    let mut result = Map::new();
+    let hostvars: Map<String, Value> = flat.hosts.iter()
+        .map(|(host, vars)| (host.clone(), vars_to_json(vars)))
+        .collect();
+    result.insert("_meta".to_string(), json!({ "hostvars": hostvars }));
+
+    let mut all_children: Vec<String> = flat.top_level_groups().into_iter().collect();
+    let ungrouped = flat.ungrouped_hosts();
+    if !ungrouped.is_empty() && !all_children.iter().any(|name| name == UNGROUPED_GROUP_NAME) {
+        all_children.push(UNGROUPED_GROUP_NAME.to_string());
+    }
+    let mut all = json!({ "children": all_children });
+    if let Some(vars) = flat.groups.get(ALL_GROUP_NAME).map(|g| &g.vars).filter(|v| !v.is_empty()) {
+        all["vars"] = vars_to_json(vars);
+    }
+    result.insert(ALL_GROUP_NAME.to_string(), all);
+
+    for (name, group) in flat.groups.iter().filter(|(name, _)| name.as_str() != ALL_GROUP_NAME) {
+        let mut hosts = group.hosts.clone();
+        if name == UNGROUPED_GROUP_NAME {
+            hosts.extend(ungrouped.iter().cloned());
+        }
+        result.insert(name.clone(), render_group_json(&hosts, &group.children, &group.vars));
+    }
+    if !ungrouped.is_empty() && !flat.groups.contains_key(UNGROUPED_GROUP_NAME) {
+        result.insert(UNGROUPED_GROUP_NAME.to_string(), render_group_json(&ungrouped, &BTreeSet::new(), &InventoryVars::new()));
+    }
+    Value::Object(result)
+}
+
+fn render_group_json(hosts: &IndexSet<String>, children: &BTreeSet<String>, vars: &InventoryVars) -> Value {
+    let mut group = Map::new();
+    if !hosts.is_empty() {
+        group.insert("hosts".to_string(), json!(hosts));
+    }
+    if !children.is_empty() {
+        group.insert("children".to_string(), json!(children));
+    }
+    if !vars.is_empty() {
+        group.insert("vars".to_string(), vars_to_json(vars));
+    }
+    Value::Object(group)
+}
+
+/// Host variables answer for `--host <name>`, unknown hosts get an empty object as ansible expects
+pub fn render_host_json(flat: &FlatInventory, host_name: &str) -> Value {
+    flat.hosts.get(host_name).map(vars_to_json).unwrap_or_else(|| json!({}))
+}
+
+fn vars_to_json(vars: &InventoryVars) -> Value {
+    let vars: InventoryVars = vars.iter().map(|(key, value)| (key.clone(), to_export_value(value))).collect();
+    serde_json::to_value(vars).unwrap_or_else(|_| json!({}))
+}
+
+pub fn render_ini(flat: &FlatInventory) -> String {
+    let mut out = String::new();
+    let mut hosts_with_vars_written = BTreeSet::new();
+    let mut write_host = |out: &mut String, host: &str| {
+        let vars = flat.hosts.get(host).filter(|vars| !vars.is_empty() && !hosts_with_vars_written.contains(host));
+        match vars {
+            Some(vars) => {
+                hosts_with_vars_written.insert(host.to_string());
+                let _ = writeln!(out, "{} {}", host, render_ini_vars(vars, " "));
+            }
+            None => {
+                let _ = writeln!(out, "{}", host);
+            }
+        }
+    };
+
+    let ungrouped = flat.ungrouped_hosts();
+    for host in &ungrouped {
+        write_host(&mut out, host);
+    }
+    for (name, group) in &flat.groups {
+        let hosts: Vec<&String> = group.hosts.iter()
+            .filter(|host| !(ungrouped.contains(*host) && (name == ALL_GROUP_NAME || name == UNGROUPED_GROUP_NAME)))
+            .collect();
+        if !hosts.is_empty() && name != ALL_GROUP_NAME {
+            let _ = writeln!(out, "\n[{}]", name);
+            for host in hosts {
+                write_host(&mut out, host);
+            }
+        }
+        if !group.children.is_empty() {
+            let _ = writeln!(out, "\n[{}:children]", name);
+            for child in &group.children {
+                let _ = writeln!(out, "{}", child);
+            }
+        }
+        if !group.vars.is_empty() {
+            let _ = writeln!(out, "\n[{}:vars]", name);
+            let _ = writeln!(out, "{}", render_ini_vars(&group.vars, "\n"));
+        }
+    }
+    // groups without hosts, children and vars still have to be declared
+    for (name, group) in &flat.groups {
+        if name != ALL_GROUP_NAME && group.hosts.is_empty() && group.children.is_empty() && group.vars.is_empty() {
+            let _ = writeln!(out, "\n[{}]", name);
+        }
+    }
+    out.trim_start().to_string()
+}
+
+fn render_ini_vars(vars: &InventoryVars, separator: &str) -> String {
+    vars.iter()
+        .map(|(key, value)| format!("{}={}", key, render_ini_value(value)))
+        .collect::<Vec<String>>()
+        .join(separator)
+}
+
+fn render_ini_value(value: &serde_yaml::Value) -> String {
+    match value {
+        serde_yaml::Value::String(s) => {
+            // quote strings that would be read back as another type or split on whitespace
+            let needs_quotes = s.is_empty()
+                || s.contains(|c: char| c.is_whitespace() || c == '#' || c == ';' || c == '"' || c == '\'')
+                || !matches!(super::ini::parse_ini_value(s), serde_yaml::Value::String(_));
+            if needs_quotes && s.contains('"') && !s.contains('\'') {
+                format!("'{}'", s)
+            } else if needs_quotes {
+                format!("\"{}\"", s.replace('"', "'"))
+            } else {
+                s.clone()
+            }
+        }
+        serde_yaml::Value::Null => String::new(),
+        serde_yaml::Value::Bool(b) => b.to_string(),
+        serde_yaml::Value::Number(n) => n.to_string(),
+        other => serde_json::to_string(&to_export_value(other)).unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indexmap::IndexMap;
+    use std::collections::BTreeMap;
+    use crate::handlers::ansible::ini::parse_ini_inventory;
+    use crate::handlers::ansible::inventory::FlatGroup;
+    use crate::handlers::ansible::AnsibleInventoryParserLocal;
+
+    const INVENTORY: &str = "\
+bastion ansible_host=192.0.2.1
+
+[web]
+web1 ansible_host=10.0.0.1 http_port=8080
+web2 motd=\"hello world\"
+web3 password='p#ss;word' quote='say \"hi\"'
+
+[db]
+db1 replica=false
+db2
+
+[prod:children]
+web
+db
+
+[prod:vars]
+env=production
+timeout=2.5
+port=\"22\"
+
+[all:vars]
+ntp=pool.ntp.org
+";
+
+    /// What ansible sees of an inventory, `all` and `ungrouped` membership differs between formats
+    #[derive(Debug, PartialEq)]
+    struct InventoryView {
+        groups: BTreeMap<String, FlatGroup>,
+        hosts: IndexMap<String, InventoryVars>,
+        ungrouped: IndexSet<String>,
+        all_vars: InventoryVars,
+    }
+
+    fn view(flat: &FlatInventory) -> InventoryView {
+        InventoryView {
+            groups: flat.groups.iter()
+                .filter(|(name, _)| name.as_str() != ALL_GROUP_NAME && name.as_str() != UNGROUPED_GROUP_NAME)
+                .map(|(name, group)| (name.clone(), group.clone()))
+                .collect(),
+            hosts: flat.hosts.clone(),
+            ungrouped: flat.ungrouped_hosts(),
+            all_vars: flat.groups.get(ALL_GROUP_NAME).map(|group| group.vars.clone()).unwrap_or_default(),
+        }
+    }
+
+    fn from_list_json(list: &Value) -> FlatInventory {
+        let mut flat = FlatInventory::default();
+        for (host, vars) in list["_meta"]["hostvars"].as_object().unwrap() {
+            flat.hosts.insert(host.clone(), serde_json::from_value(vars.clone()).unwrap());
+        }
+        for (name, group) in list.as_object().unwrap().iter().filter(|(name, _)| name.as_str() != "_meta") {
+            flat.groups.insert(name.clone(), FlatGroup {
+                hosts: serde_json::from_value(group["hosts"].clone()).unwrap_or_default(),
+                children: serde_json::from_value(group["children"].clone()).unwrap_or_default(),
+                vars: serde_json::from_value(group["vars"].clone()).unwrap_or_default(),
+            });
+        }
+        flat
+    }
+
+    fn inventory() -> Inventory {
+        parse_ini_inventory(INVENTORY).unwrap()
+    }
+
+    #[test]
+    fn ini_round_trip() {
+        let rendered = render(&inventory(), InventoryFormat::Ini).unwrap();
+        let parsed = parse_ini_inventory(&rendered).unwrap();
+        assert_eq!(view(&parsed.flatten()), view(&inventory().flatten()), "{}", rendered);
+    }
+
+    #[test]
+    fn yaml_round_trip() {
+        let rendered = render(&inventory(), InventoryFormat::Yaml).unwrap();
+        let parsed = AnsibleInventoryParserLocal::parse(&rendered, InventoryFormat::Yaml).unwrap();
+        assert_eq!(view(&parsed.flatten()), view(&inventory().flatten()), "{}", rendered);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let rendered = render(&inventory(), InventoryFormat::Json).unwrap();
+        let parsed = from_list_json(&serde_json::from_str(&rendered).unwrap());
+        assert_eq!(view(&parsed), view(&inventory().flatten()), "{}", rendered);
+    }
+
+    #[test]
+    fn ini_quotes_values_that_would_change_type() {
+        let rendered = render(&inventory(), InventoryFormat::Ini).unwrap();
+        assert!(rendered.starts_with("bastion ansible_host=192.0.2.1\n"), "{}", rendered);
+        assert!(rendered.contains("\n[prod:children]\ndb\nweb\n"), "{}", rendered);
+        assert!(rendered.contains("\nport=\"22\"\ntimeout=2.5\n"), "{}", rendered);
+        assert!(rendered.contains("\nweb3 password=\"p#ss;word\" quote='say \"hi\"'\n"), "{}", rendered);
+    }
+
+    #[test]
+    fn empty_groups_are_declared() {
+        let rendered = render(&parse_ini_inventory("[web]\nweb1\n\n[db]\n").unwrap(), InventoryFormat::Ini).unwrap();
+        assert_eq!(rendered, "[web]\nweb1\n\n[db]\n");
+        assert!(parse_ini_inventory(&rendered).unwrap().0.contains_key("db"));
+    }
+
+    #[test]
+    fn list_json() {
+        let list = render_list_json(&inventory().flatten());
+        assert_eq!(list["all"]["children"], json!(["prod", "ungrouped"]));
+        assert_eq!(list["ungrouped"]["hosts"], json!(["bastion"]));
+        assert_eq!(list["prod"]["children"], json!(["db", "web"]));
+        assert_eq!(list["_meta"]["hostvars"]["db2"], json!({}));
+    }
+
+    #[test]
+    fn host_json() {
+        let flat = inventory().flatten();
+        assert_eq!(render_host_json(&flat, "web1"), json!({"ansible_host": "10.0.0.1", "http_port": 8080}));
+        assert_eq!(render_host_json(&flat, "missing"), json!({}));
+    }
+
+    #[test]
+    fn format_from_path() {
+        assert_eq!(InventoryFormat::from_path("inventory/hosts.yml"), InventoryFormat::Yaml);
+        assert_eq!(InventoryFormat::from_path("hosts"), InventoryFormat::Ini);
+        assert_eq!(InventoryFormat::from_etcd_key("/inventories/web"), InventoryFormat::Yaml);
+        assert_eq!(InventoryFormat::from_etcd_key("/inventories/web.ini"), InventoryFormat::Ini);
+    }
+}
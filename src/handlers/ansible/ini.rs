@@ -0,0 +1,302 @@
+use indexmap::IndexMap;
+
+use serde_yaml::Value;
+use thiserror::Error;
+
+use super::inventory::UNGROUPED_GROUP_NAME;
+use super::{HostVars, Inventory, InventoryChildren, InventoryVars};
+
+#[derive(Error, Debug)]
+pub enum IniInventoryError {
+    #[error("line {line}: invalid section header: {content}")]
+    InvalidSection { line: usize, content: String },
+    #[error("line {line}: unknown section type: {section_type}")]
+    UnknownSectionType { line: usize, section_type: String },
+    #[error("line {line}: invalid variable definition: {content}")]
+    InvalidVariable { line: usize, content: String },
+    #[error("line {line}: unterminated quote")]
+    UnterminatedQuote { line: usize },
+}
+
+impl IniInventoryError {
+    pub fn line(&self) -> usize {
+        match self {
+            IniInventoryError::InvalidSection { line, .. }
+            | IniInventoryError::UnknownSectionType { line, .. }
+            | IniInventoryError::InvalidVariable { line, .. }
+            | IniInventoryError::UnterminatedQuote { line } => *line,
+        }
+    }
+}
+
+/// Section kinds of an INI inventory: `[group]`, `[group:vars]` and `[group:children]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IniSectionKind {
+    Hosts,
+    Vars,
+    Children,
+}
+
+/// Parse a section header like `[webservers:vars]` into group name and section kind
+pub fn parse_section_header(line: &str, line_number: usize) -> Result<(String, IniSectionKind), IniInventoryError> {
+    let inner = line
+        .strip_prefix('[')
+        .and_then(|l| l.strip_suffix(']'))
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .ok_or_else(|| IniInventoryError::InvalidSection { line: line_number, content: line.to_string() })?;
+    match inner.split_once(':') {
+        Some((group, "vars")) => Ok((group.to_string(), IniSectionKind::Vars)),
+        Some((group, "children")) => Ok((group.to_string(), IniSectionKind::Children)),
+        Some((_, section_type)) => Err(IniInventoryError::UnknownSectionType {
+            line: line_number,
+            section_type: section_type.to_string(),
+        }),
+        None => Ok((inner.to_string(), IniSectionKind::Hosts)),
+    }
+}
+
+/// Split a host line into shell-like tokens, keeping quoted parts together
+pub fn split_tokens(line: &str, line_number: usize) -> Result<Vec<String>, IniInventoryError> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    for c in line.chars() {
+        match quote {
+            Some(q) if c == q => {
+                quote = None;
+                current.push(c);
+            }
+            Some(_) => current.push(c),
+            None if c == '"' || c == '\'' => {
+                quote = Some(c);
+                current.push(c);
+            }
+            None if c.is_whitespace() => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            None => current.push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err(IniInventoryError::UnterminatedQuote { line: line_number });
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    Ok(tokens)
+}
+
+/// Convert an INI value into a typed YAML value the same way Ansible does for simple literals
+pub fn parse_ini_value(raw: &str) -> Value {
+    let raw = raw.trim();
+    for q in ['"', '\''] {
+        if raw.len() >= 2 && raw.starts_with(q) && raw.ends_with(q) {
+            return Value::String(raw[1..raw.len() - 1].to_string());
+        }
+    }
+    if let Ok(int) = raw.parse::<i64>() {
+        return Value::Number(int.into());
+    }
+    if let Ok(float) = raw.parse::<f64>() {
+        return Value::Number(float.into());
+    }
+    match raw {
+        "True" | "true" => Value::Bool(true),
+        "False" | "false" => Value::Bool(false),
+        _ => Value::String(raw.to_string()),
+    }
+}
+
+/// Parse a `key=value` pair, used both for inline host vars and `[group:vars]` lines
+pub fn parse_variable(token: &str, line_number: usize) -> Result<(String, Value), IniInventoryError> {
+    match token.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => Ok((key.trim().to_string(), parse_ini_value(value))),
+        _ => Err(IniInventoryError::InvalidVariable { line: line_number, content: token.to_string() }),
+    }
+}
+
+/// Strip `;` and `#` comments that are not part of a quoted value
+pub fn strip_comment(line: &str) -> &str {
+    let mut quote: Option<char> = None;
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if (c == '#' || c == ';') && (i == 0 || line[..i].ends_with(char::is_whitespace)) => return &line[..i],
+            None => {}
+        }
+    }
+    line
+}
+
+fn group_entry<'a>(groups: &'a mut IndexMap<String, InventoryChildren>, name: &str) -> &'a mut InventoryChildren {
+    groups.entry(name.to_string()).or_default()
+}
+
+/// Parse an INI formatted ansible inventory, supporting host vars, `:vars` and `:children` sections
+pub fn parse_ini_inventory(content: &str) -> Result<Inventory, IniInventoryError> {
+    let mut groups: IndexMap<String, InventoryChildren> = IndexMap::new();
+    let mut section = (UNGROUPED_GROUP_NAME.to_string(), IniSectionKind::Hosts);
+
+    for (index, raw_line) in content.lines().enumerate() {
+        let line_number = index + 1;
+        let line = strip_comment(raw_line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') {
+            section = parse_section_header(line, line_number)?;
+            group_entry(&mut groups, &section.0);
+            continue;
+        }
+        let (group_name, kind) = &section;
+        match kind {
+            IniSectionKind::Hosts => {
+                let mut tokens = split_tokens(line, line_number)?.into_iter();
+                let host_name = match tokens.next() {
+                    Some(host_name) => host_name,
+                    None => continue,
+                };
+                let mut vars = InventoryVars::new();
+                for token in tokens {
+                    let (key, value) = parse_variable(&token, line_number)?;
+                    vars.insert(key, value);
+                }
+                let group = group_entry(&mut groups, group_name);
+                let host_vars = (!vars.is_empty()).then_some(HostVars(vars));
+                match group.hosts.0.get_mut(&host_name) {
+                    Some(Some(existing)) => existing.0.extend(host_vars.map(|v| v.0).unwrap_or_default()),
+                    _ => {
+                        group.hosts.0.insert(host_name, host_vars);
+                    }
+                }
+            }
+            IniSectionKind::Vars => {
+                let (key, value) = parse_variable(line, line_number)?;
+                group_entry(&mut groups, group_name).vars.get_or_insert_with(InventoryVars::new).insert(key, value);
+            }
+            IniSectionKind::Children => {
+                let group = group_entry(&mut groups, group_name);
+                group.children.get_or_insert_with(IndexMap::new).entry(line.to_string()).or_default();
+            }
+        }
+    }
+    // implicit "ungrouped" section is only kept when something was put into it
+    if groups.get(UNGROUPED_GROUP_NAME).is_some_and(|g| g.hosts.0.is_empty() && g.children.is_none() && g.vars.is_none()) {
+        groups.shift_remove(UNGROUPED_GROUP_NAME);
+    }
+    Ok(Inventory(groups))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INVENTORY: &str = "\
+# hosts before the first section are ungrouped
+bastion ansible_host=192.0.2.1
+
+[web]
+web1 ansible_host=10.0.0.1 http_port=8080
+web2 motd=\"hello world\" ; trailing comment
+web3 password='p#ss;word' # the quoted value keeps its comment characters
+
+[db]
+db1 replica=false
+
+[prod:children]
+web
+db
+
+[prod:vars]
+; comment in a vars section
+env=production
+timeout=2.5
+owner=\"ops team\"
+";
+
+    fn host_vars<'a>(inventory: &'a Inventory, group: &str, host: &str) -> &'a InventoryVars {
+        &inventory.0[group].hosts.0[host].as_ref().unwrap().0
+    }
+
+    #[test]
+    fn sections_keep_inventory_order() {
+        let inventory = parse_ini_inventory(INVENTORY).unwrap();
+        assert_eq!(inventory.0.keys().collect::<Vec<_>>(), [UNGROUPED_GROUP_NAME, "web", "db", "prod"]);
+        assert_eq!(inventory.0["web"].hosts.0.keys().collect::<Vec<_>>(), ["web1", "web2", "web3"]);
+    }
+
+    #[test]
+    fn children_and_vars_sections() {
+        let inventory = parse_ini_inventory(INVENTORY).unwrap();
+        let prod = &inventory.0["prod"];
+        assert_eq!(prod.children.as_ref().unwrap().keys().collect::<Vec<_>>(), ["web", "db"]);
+        let vars = prod.vars.as_ref().unwrap();
+        assert_eq!(vars["env"], Value::String("production".to_string()));
+        assert_eq!(vars["timeout"], Value::Number(2.5.into()));
+        assert_eq!(vars["owner"], Value::String("ops team".to_string()));
+        assert!(prod.hosts.0.is_empty());
+    }
+
+    #[test]
+    fn inline_host_vars() {
+        let inventory = parse_ini_inventory(INVENTORY).unwrap();
+        let web1 = host_vars(&inventory, "web", "web1");
+        assert_eq!(web1["ansible_host"], Value::String("10.0.0.1".to_string()));
+        assert_eq!(web1["http_port"], Value::Number(8080.into()));
+        assert_eq!(host_vars(&inventory, "web", "web2")["motd"], Value::String("hello world".to_string()));
+        assert_eq!(host_vars(&inventory, "db", "db1")["replica"], Value::Bool(false));
+        assert_eq!(host_vars(&inventory, UNGROUPED_GROUP_NAME, "bastion")["ansible_host"], Value::String("192.0.2.1".to_string()));
+    }
+
+    #[test]
+    fn comments() {
+        let inventory = parse_ini_inventory(INVENTORY).unwrap();
+        assert_eq!(host_vars(&inventory, "web", "web2").len(), 1);
+        assert_eq!(host_vars(&inventory, "web", "web3")["password"], Value::String("p#ss;word".to_string()));
+        assert_eq!(strip_comment("# whole line"), "");
+        assert_eq!(strip_comment("web1 ; note"), "web1 ");
+        assert_eq!(strip_comment("web1 url=http://host/#anchor"), "web1 url=http://host/#anchor");
+        assert_eq!(strip_comment("web1 motd='a # b' # note"), "web1 motd='a # b' ");
+    }
+
+    #[test]
+    fn tokens_keep_quoted_whitespace() {
+        assert_eq!(split_tokens("web1  a=\"x y\" b='z'", 1).unwrap(), ["web1", "a=\"x y\"", "b='z'"]);
+        assert!(matches!(split_tokens("web1 a=\"x", 3), Err(IniInventoryError::UnterminatedQuote { line: 3 })));
+    }
+
+    #[test]
+    fn values_are_typed_like_ansible() {
+        assert_eq!(parse_ini_value("42"), Value::Number(42.into()));
+        assert_eq!(parse_ini_value("-1.5"), Value::Number((-1.5).into()));
+        assert_eq!(parse_ini_value("True"), Value::Bool(true));
+        assert_eq!(parse_ini_value("\"42\""), Value::String("42".to_string()));
+        assert_eq!(parse_ini_value("'true'"), Value::String("true".to_string()));
+        assert_eq!(parse_ini_value("yes"), Value::String("yes".to_string()));
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let error = parse_ini_inventory("[web]\nweb1\n[web:hosts]\n").unwrap_err();
+        assert!(matches!(error, IniInventoryError::UnknownSectionType { line: 3, .. }));
+        assert_eq!(parse_ini_inventory("[web:vars]\nenv\n").unwrap_err().line(), 2);
+        assert_eq!(parse_ini_inventory("[]\n").unwrap_err().line(), 1);
+    }
+
+    #[test]
+    fn empty_ungrouped_section_is_dropped() {
+        let inventory = parse_ini_inventory("# only groups\n[web]\nweb1\n").unwrap();
+        assert!(!inventory.0.contains_key(UNGROUPED_GROUP_NAME));
+    }
+
+    #[test]
+    fn repeated_host_merges_vars() {
+        let inventory = parse_ini_inventory("[web]\nweb1 a=1\nweb1 b=2\n").unwrap();
+        assert_eq!(host_vars(&inventory, "web", "web1").keys().collect::<Vec<_>>(), ["a", "b"]);
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use indexmap::{IndexMap, IndexSet};
+
+use super::{HostGroup, HostVars, Inventory, InventoryChildren, InventoryVars};
+
+pub const ALL_GROUP_NAME: &str = "all";
+pub const UNGROUPED_GROUP_NAME: &str = "ungrouped";
+
+/// Group of a flattened inventory, children and hosts are referenced by name
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FlatGroup {
+    pub hosts: IndexSet<String>, // in inventory order, ansible subscripts like `webservers[0]` depend on it
+    pub children: BTreeSet<String>,
+    pub vars: InventoryVars,
+}
+
+/// Inventory with every group definition merged by name, the way ansible sees it after loading
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FlatInventory {
+    pub groups: BTreeMap<String, FlatGroup>,
+    pub hosts: IndexMap<String, InventoryVars>,
+}
+
+impl Inventory {
+    pub fn flatten(&self) -> FlatInventory {
+        let mut flat = FlatInventory::default();
+        for (name, group) in &self.0 {
+            flat.collect_group(name, group);
+        }
+        flat
+    }
+}
+
+impl FlatInventory {
+    fn collect_group(&mut self, name: &str, group: &InventoryChildren) {
+        let mut child_names = Vec::new();
+        {
+            let flat_group = self.groups.entry(name.to_string()).or_default();
+            flat_group.hosts.extend(group.hosts.0.keys().cloned());
+            if let Some(vars) = &group.vars {
+                flat_group.vars.extend(vars.clone());
+            }
+            if let Some(children) = &group.children {
+                flat_group.children.extend(children.keys().cloned());
+                child_names.extend(children.iter());
+            }
+        }
+        for (host_name, host_vars) in &group.hosts.0 {
+            let vars = self.hosts.entry(host_name.clone()).or_default();
+            if let Some(host_vars) = host_vars {
+                vars.extend(host_vars.0.clone());
+            }
+        }
+        for (child_name, child) in child_names {
+            self.collect_group(child_name, child);
+        }
+    }
+
+    /// Groups which are not a child of any other group, `all` excluded
+    pub fn top_level_groups(&self) -> BTreeSet<String> {
+        let nested: BTreeSet<&String> = self.groups.iter()
+            .filter(|(name, _)| name.as_str() != ALL_GROUP_NAME)
+            .flat_map(|(_, group)| group.children.iter())
+            .collect();
+        self.groups.keys()
+            .filter(|name| name.as_str() != ALL_GROUP_NAME && !nested.contains(name))
+            .cloned()
+            .collect()
+    }
+
+    /// All hosts of a group including the ones inherited from child groups, in inventory order
+    pub fn group_hosts(&self, group_name: &str) -> IndexSet<String> {
+        if group_name == ALL_GROUP_NAME {
+            return self.hosts.keys().cloned().collect();
+        }
+        let mut hosts = IndexSet::new();
+        let mut visited = BTreeSet::new();
+        let mut queue = VecDeque::from([group_name.to_string()]);
+        while let Some(name) = queue.pop_front() {
+            if !visited.insert(name.clone()) {
+                continue;
+            }
+            if let Some(group) = self.groups.get(&name) {
+                hosts.extend(group.hosts.iter().cloned());
+                queue.extend(group.children.iter().cloned());
+            }
+        }
+        hosts
+    }
+
+    /// Hosts that are not a member of any group besides `all` and `ungrouped`
+    pub fn ungrouped_hosts(&self) -> IndexSet<String> {
+        let grouped: BTreeSet<&String> = self.groups.iter()
+            .filter(|(name, _)| name.as_str() != ALL_GROUP_NAME && name.as_str() != UNGROUPED_GROUP_NAME)
+            .flat_map(|(_, group)| group.hosts.iter())
+            .collect();
+        self.hosts.keys().filter(|host| !grouped.contains(host)).cloned().collect()
+    }
+
+    /// Build a canonical nested inventory rooted in `all`, host vars are kept on `all.hosts`
+    pub fn to_inventory(&self) -> Inventory {
+        let ungrouped = self.ungrouped_hosts();
+        let mut all = InventoryChildren {
+            hosts: HostGroup(self.hosts.iter()
+                .filter(|(name, vars)| !vars.is_empty() || ungrouped.contains(*name))
+                .map(|(name, vars)| (name.clone(), (!vars.is_empty()).then(|| HostVars(vars.clone()))))
+                .collect()),
+            children: None,
+            vars: self.groups.get(ALL_GROUP_NAME).map(|g| g.vars.clone()).filter(|v| !v.is_empty()),
+        };
+        let mut rendered = BTreeSet::new();
+        let children: IndexMap<String, InventoryChildren> = self.top_level_groups().into_iter()
+            .filter(|name| name != UNGROUPED_GROUP_NAME || !self.groups[name].vars.is_empty())
+            .map(|name| {
+                let group = self.nested_group(&name, &mut rendered);
+                (name, group)
+            })
+            .collect();
+        if !children.is_empty() {
+            all.children = Some(children);
+        }
+        Inventory(IndexMap::from([(ALL_GROUP_NAME.to_string(), all)]))
+    }
+
+    fn nested_group(&self, name: &str, rendered: &mut BTreeSet<String>) -> InventoryChildren {
+        // a group reachable from several parents is defined once and referenced elsewhere
+        if !rendered.insert(name.to_string()) {
+            return InventoryChildren::default();
+        }
+        let group = match self.groups.get(name) {
+            Some(group) => group,
+            None => return InventoryChildren::default(),
+        };
+        let children: IndexMap<String, InventoryChildren> = group.children.iter()
+            .map(|child| (child.clone(), self.nested_group(child, rendered)))
+            .collect();
+        InventoryChildren {
+            hosts: HostGroup(group.hosts.iter().map(|host| (host.clone(), None)).collect()),
+            children: (!children.is_empty()).then_some(children),
+            vars: (!group.vars.is_empty()).then(|| group.vars.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::ansible::ini::parse_ini_inventory;
+
+    const INVENTORY: &str = "\
+bastion
+
+[web]
+web1 port=80
+web2
+
+[db]
+db1
+
+[prod:children]
+web
+db
+
+[monitored:children]
+web
+
+[web:vars]
+tier=front
+";
+
+    fn flat() -> FlatInventory {
+        parse_ini_inventory(INVENTORY).unwrap().flatten()
+    }
+
+    #[test]
+    fn group_hosts_include_children() {
+        assert_eq!(flat().group_hosts("prod").into_iter().collect::<BTreeSet<_>>(), BTreeSet::from(["web1".to_string(), "web2".to_string(), "db1".to_string()]));
+        assert_eq!(flat().group_hosts(ALL_GROUP_NAME).into_iter().collect::<Vec<_>>(), ["bastion", "web1", "web2", "db1"]);
+        assert!(flat().group_hosts("missing").is_empty());
+    }
+
+    #[test]
+    fn top_level_and_ungrouped() {
+        let flat = flat();
+        assert_eq!(flat.top_level_groups().into_iter().collect::<Vec<_>>(), ["monitored", "prod", UNGROUPED_GROUP_NAME]);
+        assert_eq!(flat.ungrouped_hosts().into_iter().collect::<Vec<_>>(), ["bastion"]);
+    }
+
+    #[test]
+    fn to_inventory_nests_groups_under_all() {
+        let inventory = flat().to_inventory();
+        assert_eq!(inventory.0.keys().collect::<Vec<_>>(), [ALL_GROUP_NAME]);
+        let all = &inventory.0[ALL_GROUP_NAME];
+        // host vars and ungrouped hosts live on `all`, groups only reference their hosts
+        assert_eq!(all.hosts.0.keys().collect::<Vec<_>>(), ["bastion", "web1"]);
+        assert!(all.hosts.0["bastion"].is_none());
+        let children = all.children.as_ref().unwrap();
+        assert_eq!(children.keys().collect::<Vec<_>>(), ["monitored", "prod"]);
+        let web = &children["monitored"].children.as_ref().unwrap()["web"];
+        assert_eq!(web.hosts.0.keys().collect::<Vec<_>>(), ["web1", "web2"]);
+        assert!(web.hosts.0.values().all(Option::is_none));
+        assert_eq!(web.vars.as_ref().unwrap()["tier"], serde_yaml::Value::String("front".to_string()));
+    }
+
+    #[test]
+    fn shared_group_is_defined_once() {
+        let inventory = flat().to_inventory();
+        let prod = &inventory.0[ALL_GROUP_NAME].children.as_ref().unwrap()["prod"];
+        let web = &prod.children.as_ref().unwrap()["web"];
+        assert!(web.hosts.0.is_empty() && web.vars.is_none());
+        let (flat, reloaded) = (flat(), inventory.flatten());
+        assert_eq!(reloaded.hosts, flat.hosts);
+        for name in ["web", "db", "prod", "monitored"] {
+            assert_eq!(reloaded.groups[name], flat.groups[name], "{}", name);
+        }
+    }
+}
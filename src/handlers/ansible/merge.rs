@@ -0,0 +1,122 @@
+use thiserror::Error;
+
+use crate::config::InventoryConflictPolicy;
+
+use super::inventory::FlatInventory;
+use super::{Inventory, InventoryVars};
+
+#[derive(Error, Debug)]
+pub enum InventoryMergeError {
+    #[error("Inventory backends disagree on {owner} var {name}: {old} != {new}")]
+    Conflict { owner: String, name: String, old: String, new: String },
+}
+
+impl FlatInventory {
+    /// Add hosts, groups and memberships of `other`, `policy` decides which value a var defined on both sides keeps
+    pub fn merge(&mut self, other: FlatInventory, policy: &InventoryConflictPolicy) -> Result<(), InventoryMergeError> {
+        for (name, group) in other.groups {
+            let merged = self.groups.entry(name.clone()).or_default();
+            merged.hosts.extend(group.hosts);
+            merged.children.extend(group.children);
+            merge_vars(&format!("group {}", name), &mut merged.vars, group.vars, policy)?;
+        }
+        for (name, vars) in other.hosts {
+            merge_vars(&format!("host {}", name), self.hosts.entry(name.clone()).or_default(), vars, policy)?;
+        }
+        Ok(())
+    }
+}
+
+fn merge_vars(owner: &str, vars: &mut InventoryVars, other: InventoryVars, policy: &InventoryConflictPolicy) -> Result<(), InventoryMergeError> {
+    for (name, value) in other {
+        match vars.get(&name) {
+            None => {
+                vars.insert(name, value);
+            }
+            Some(current) if *current == value => {}
+            Some(current) => match policy {
+                InventoryConflictPolicy::LastWins => {
+                    vars.insert(name, value);
+                }
+                InventoryConflictPolicy::FirstWins => {}
+                InventoryConflictPolicy::Error => {
+                    let render = |value: &serde_yaml::Value| serde_json::to_string(value).unwrap_or_default();
+                    return Err(InventoryMergeError::Conflict { owner: owner.to_string(), name, old: render(current), new: render(&value) });
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+impl Inventory {
+    /// One inventory out of several backends, a single one is returned untouched
+    pub fn merge_all(mut inventories: Vec<Inventory>, policy: &InventoryConflictPolicy) -> Result<Inventory, InventoryMergeError> {
+        if inventories.len() <= 1 {
+            return Ok(inventories.pop().unwrap_or_default());
+        }
+        let mut merged = FlatInventory::default();
+        for inventory in inventories {
+            merged.merge(inventory.flatten(), policy)?;
+        }
+        Ok(merged.to_inventory())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::ansible::ini::parse_ini_inventory;
+
+    const FIRST: &str = "[web]\nweb1 port=22\n\n[web:vars]\nenv=prod\n";
+    const SECOND: &str = "[web]\nweb1 port=2222\nweb2\n\n[db]\ndb1\n";
+
+    fn merge(policy: InventoryConflictPolicy) -> Result<FlatInventory, InventoryMergeError> {
+        let inventories = vec![parse_ini_inventory(FIRST).unwrap(), parse_ini_inventory(SECOND).unwrap()];
+        Inventory::merge_all(inventories, &policy).map(|inventory| inventory.flatten())
+    }
+
+    fn host_var(inventory: &FlatInventory, host: &str, name: &str) -> String {
+        serde_yaml::to_string(&inventory.hosts[host][name]).unwrap().trim().to_string()
+    }
+
+    #[test]
+    fn hosts_groups_and_vars_of_every_backend_are_kept() {
+        let merged = merge(InventoryConflictPolicy::LastWins).unwrap();
+        assert_eq!(merged.group_hosts("web").into_iter().collect::<Vec<_>>(), ["web1", "web2"]);
+        assert_eq!(merged.group_hosts("db").into_iter().collect::<Vec<_>>(), ["db1"]);
+        assert_eq!(serde_yaml::to_string(&merged.groups["web"].vars["env"]).unwrap().trim(), "prod");
+    }
+
+    #[test]
+    fn last_wins() {
+        assert_eq!(host_var(&merge(InventoryConflictPolicy::LastWins).unwrap(), "web1", "port"), "2222");
+    }
+
+    #[test]
+    fn first_wins() {
+        assert_eq!(host_var(&merge(InventoryConflictPolicy::FirstWins).unwrap(), "web1", "port"), "22");
+    }
+
+    #[test]
+    fn error_names_the_conflicting_var() {
+        match merge(InventoryConflictPolicy::Error) {
+            Err(InventoryMergeError::Conflict { owner, name, old, new }) => {
+                assert_eq!((owner.as_str(), name.as_str(), old.as_str(), new.as_str()), ("host web1", "port", "22", "2222"));
+            }
+            other => panic!("expected a conflict, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn equal_values_are_no_conflict() {
+        let inventories = vec![parse_ini_inventory(FIRST).unwrap(), parse_ini_inventory(FIRST).unwrap()];
+        assert!(Inventory::merge_all(inventories, &InventoryConflictPolicy::Error).is_ok());
+    }
+
+    #[test]
+    fn single_inventory_is_untouched() {
+        let merged = Inventory::merge_all(vec![parse_ini_inventory(SECOND).unwrap()], &InventoryConflictPolicy::Error).unwrap();
+        assert!(merged.0.contains_key("web") && merged.0.contains_key("db"));
+    }
+}
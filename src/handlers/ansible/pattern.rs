@@ -0,0 +1,316 @@
+use indexmap::IndexSet;
+use regex::Regex;
+use thiserror::Error;
+
+use super::inventory::{FlatInventory, ALL_GROUP_NAME};
+
+#[derive(Error, Debug)]
+pub enum HostPatternError {
+    #[error("Host pattern is empty")]
+    Empty,
+    #[error("Invalid regex in host pattern {pattern}: {source}")]
+    InvalidRegex { pattern: String, source: regex::Error },
+    #[error("Invalid subscript in host pattern {0}")]
+    InvalidSubscript(String),
+}
+
+/// How a single term is combined with the terms before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PatternOperation {
+    Union,
+    Intersection,
+    Exclusion,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatternSubscript {
+    Index(i64),
+    /// inclusive on both ends like in ansible `webservers[0:2]`, an open end runs to the last host
+    Range(i64, Option<i64>),
+}
+
+#[derive(Debug, Clone)]
+pub struct PatternTerm {
+    pub operation: PatternOperation,
+    pub expression: String,
+    pub subscript: Option<PatternSubscript>,
+}
+
+/// Host pattern as accepted by `ansible-playbook --limit` and the `hosts:` keyword
+#[derive(Debug, Clone)]
+pub struct HostPattern {
+    pub terms: Vec<PatternTerm>,
+}
+
+impl HostPattern {
+    pub fn parse(pattern: &str) -> Result<Self, HostPatternError> {
+        let mut terms = Vec::new();
+        for raw_term in split_pattern(pattern) {
+            let raw_term = raw_term.trim();
+            if raw_term.is_empty() {
+                continue;
+            }
+            let (operation, term) = match raw_term.chars().next() {
+                Some('&') => (PatternOperation::Intersection, &raw_term[1..]),
+                Some('!') => (PatternOperation::Exclusion, &raw_term[1..]),
+                _ => (PatternOperation::Union, raw_term),
+            };
+            let (expression, subscript) = parse_subscript(term)?;
+            terms.push(PatternTerm { operation, expression, subscript });
+        }
+        if terms.is_empty() {
+            return Err(HostPatternError::Empty);
+        }
+        Ok(Self { terms })
+    }
+
+    /// Patterns reading hosts from a file (`@retry_file`) can't be checked against the inventory
+    pub fn is_file_reference(pattern: &str) -> bool {
+        split_pattern(pattern).iter().any(|term| term.trim().starts_with('@'))
+    }
+
+    /// Resolve pattern the way ansible does: unions first, then intersections, then exclusions.
+    /// Hosts keep the inventory order so subscripts pick the same hosts as ansible
+    pub fn resolve(&self, inventory: &FlatInventory) -> Result<IndexSet<String>, HostPatternError> {
+        let mut terms: Vec<&PatternTerm> = self.terms.iter().collect();
+        terms.sort_by_key(|term| term.operation);
+        let only_restrictions = terms.iter().all(|term| term.operation != PatternOperation::Union);
+        // a pattern without unions like `!db` means everything except db
+        let mut hosts = if only_restrictions {
+            inventory.group_hosts(ALL_GROUP_NAME)
+        } else {
+            IndexSet::new()
+        };
+        for term in terms {
+            let matched = resolve_term(term, inventory)?;
+            match term.operation {
+                PatternOperation::Union => hosts.extend(matched),
+                PatternOperation::Intersection => hosts.retain(|host| matched.contains(host)),
+                PatternOperation::Exclusion => hosts.retain(|host| !matched.contains(host)),
+            }
+        }
+        Ok(hosts)
+    }
+}
+
+/// Split by commas, or by colons for the legacy syntax, keeping subscripts like `[0:2]` intact
+fn split_pattern(pattern: &str) -> Vec<String> {
+    let separator = if pattern.contains(',') { ',' } else { ':' };
+    let mut terms = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    for c in pattern.chars() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            _ => {}
+        }
+        if c == separator && depth == 0 {
+            terms.push(std::mem::take(&mut current));
+        } else {
+            current.push(c);
+        }
+    }
+    terms.push(current);
+    // regex terms may contain colons themselves, so they are never split in legacy mode
+    if separator == ':' && pattern.trim_start_matches(['&', '!']).starts_with('~') {
+        return vec![pattern.to_string()];
+    }
+    terms
+}
+
+fn parse_subscript(term: &str) -> Result<(String, Option<PatternSubscript>), HostPatternError> {
+    if term.starts_with('~') || !term.ends_with(']') {
+        return Ok((term.to_string(), None));
+    }
+    let open = match term.rfind('[') {
+        Some(open) if open > 0 => open,
+        // `[abc]*` style globs are not subscripts
+        _ => return Ok((term.to_string(), None)),
+    };
+    let inner = &term[open + 1..term.len() - 1];
+    // ansible only reads digits, `-` and `:` as a subscript, `web[!3]` is a glob
+    if !inner.chars().all(|c| c.is_ascii_digit() || c == '-' || c == ':') {
+        return Ok((term.to_string(), None));
+    }
+    let invalid = || HostPatternError::InvalidSubscript(term.to_string());
+    let is_number = |value: &str| !value.is_empty() && value.chars().all(|c| c.is_ascii_digit());
+    // same forms as ansible's subscript regex: `[-1]`, `[0:2]`, `[0-2]`, `[1:]` and `[1-]`,
+    // range starts can't be negative
+    let subscript = match inner.find([':', '-']).filter(|&separator| separator > 0) {
+        Some(separator) => {
+            let (start, end) = (&inner[..separator], &inner[separator + 1..]);
+            if !is_number(start) || !(end.is_empty() || is_number(end)) {
+                return Err(invalid());
+            }
+            let start = start.parse::<i64>().map_err(|_| invalid())?;
+            let end = match end {
+                "" => None,
+                end => Some(end.parse::<i64>().map_err(|_| invalid())?),
+            };
+            PatternSubscript::Range(start, end)
+        }
+        None => PatternSubscript::Index(inner.parse::<i64>().map_err(|_| invalid())?),
+    };
+    Ok((term[..open].to_string(), Some(subscript)))
+}
+
+fn resolve_term(term: &PatternTerm, inventory: &FlatInventory) -> Result<IndexSet<String>, HostPatternError> {
+    let matched = resolve_expression(&term.expression, inventory)?;
+    match &term.subscript {
+        None => Ok(matched),
+        Some(subscript) => Ok(apply_subscript(matched.into_iter().collect(), subscript)),
+    }
+}
+
+fn resolve_expression(expression: &str, inventory: &FlatInventory) -> Result<IndexSet<String>, HostPatternError> {
+    if expression == ALL_GROUP_NAME || expression == "*" {
+        return Ok(inventory.group_hosts(ALL_GROUP_NAME));
+    }
+    let regex = if let Some(regex) = expression.strip_prefix('~') {
+        format!("^(?:{})", regex)
+    } else if expression.contains(['*', '?', '[']) {
+        glob_to_regex(expression)
+    } else if inventory.groups.contains_key(expression) {
+        return Ok(inventory.group_hosts(expression));
+    } else if inventory.hosts.contains_key(expression) {
+        return Ok(IndexSet::from([expression.to_string()]));
+    } else {
+        return Ok(IndexSet::new());
+    };
+    let regex = Regex::new(&regex).map_err(|source| HostPatternError::InvalidRegex {
+        pattern: expression.to_string(),
+        source,
+    })?;
+    let mut hosts: IndexSet<String> = inventory.hosts.keys().filter(|host| regex.is_match(host)).cloned().collect();
+    for group in inventory.groups.keys().filter(|group| regex.is_match(group)) {
+        hosts.extend(inventory.group_hosts(group));
+    }
+    Ok(hosts)
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut in_class = false;
+    for c in glob.chars() {
+        match c {
+            '*' if !in_class => regex.push_str(".*"),
+            '?' if !in_class => regex.push('.'),
+            '[' if !in_class => {
+                in_class = true;
+                regex.push('[');
+            }
+            ']' if in_class => {
+                in_class = false;
+                regex.push(']');
+            }
+            '!' if in_class && regex.ends_with('[') => regex.push('^'),
+            c if in_class => regex.push(c),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    if in_class {
+        // unbalanced bracket is a literal like fnmatch treats it
+        return format!("^{}$", regex::escape(glob));
+    }
+    regex.push('$');
+    regex
+}
+
+fn apply_subscript(hosts: Vec<String>, subscript: &PatternSubscript) -> IndexSet<String> {
+    let len = hosts.len() as i64;
+    let normalize = |index: i64| if index < 0 { len + index } else { index };
+    let (start, end) = match subscript {
+        PatternSubscript::Index(index) => (normalize(*index), normalize(*index)),
+        PatternSubscript::Range(start, end) => (*start, end.map(normalize).unwrap_or(len - 1)),
+    };
+    if start < 0 || start >= len || end < start {
+        return IndexSet::new();
+    }
+    hosts[start as usize..=end.min(len - 1) as usize].iter().cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::ansible::ini::parse_ini_inventory;
+
+    const INVENTORY: &str = "\
+[web]
+web3
+web1
+web2
+
+[db]
+db2
+db1
+
+[staging]
+web1
+db1
+";
+
+    fn resolve(pattern: &str) -> Vec<String> {
+        let inventory = parse_ini_inventory(INVENTORY).unwrap().flatten();
+        HostPattern::parse(pattern).unwrap().resolve(&inventory).unwrap().into_iter().collect()
+    }
+
+    #[test]
+    fn union() {
+        assert_eq!(resolve("web,db"), ["web3", "web1", "web2", "db2", "db1"]);
+        assert_eq!(resolve("web:db1"), ["web3", "web1", "web2", "db1"]);
+    }
+
+    #[test]
+    fn intersection() {
+        assert_eq!(resolve("web,&staging"), ["web1"]);
+    }
+
+    #[test]
+    fn exclusion() {
+        assert_eq!(resolve("all,!web"), ["db2", "db1"]);
+        assert_eq!(resolve("!staging"), ["web3", "web2", "db2"]);
+    }
+
+    #[test]
+    fn regex() {
+        assert_eq!(resolve("~web[12]"), ["web1", "web2"]);
+        assert_eq!(resolve("~(web|db)1"), ["web1", "db1"]);
+    }
+
+    #[test]
+    fn glob() {
+        assert_eq!(resolve("db*"), ["db2", "db1"]);
+        assert_eq!(resolve("web?"), ["web3", "web1", "web2"]);
+        assert_eq!(resolve("web[!3]"), ["web1", "web2"]);
+    }
+
+    #[test]
+    fn subscripts_use_inventory_order() {
+        assert_eq!(resolve("web[0]"), ["web3"]);
+        assert_eq!(resolve("web[0:1]"), ["web3", "web1"]);
+        assert_eq!(resolve("web[1:]"), ["web1", "web2"]);
+    }
+
+    #[test]
+    fn dash_subscripts() {
+        assert_eq!(resolve("web[0-1]"), ["web3", "web1"]);
+        assert_eq!(resolve("web[1-]"), ["web1", "web2"]);
+        assert_eq!(resolve("web[0-1],db[1]"), ["web3", "web1", "db1"]);
+    }
+
+    #[test]
+    fn negative_subscripts() {
+        assert_eq!(resolve("web[-1]"), ["web2"]);
+        assert!(resolve("web[-4]").is_empty());
+    }
+
+    #[test]
+    fn invalid_subscript_is_an_error() {
+        assert!(matches!(HostPattern::parse("web[]"), Err(HostPatternError::InvalidSubscript(_))));
+        assert!(matches!(HostPattern::parse("web[-2:]"), Err(HostPatternError::InvalidSubscript(_))));
+        assert!(matches!(HostPattern::parse("web[1:-1]"), Err(HostPatternError::InvalidSubscript(_))));
+        assert!(matches!(HostPattern::parse("web[1:2:3]"), Err(HostPatternError::InvalidSubscript(_))));
+        assert!(matches!(HostPattern::parse(" , "), Err(HostPatternError::Empty)));
+    }
+}
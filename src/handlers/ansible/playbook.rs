@@ -0,0 +1,258 @@
+use std::collections::BTreeSet;
+
+use serde::Serialize;
+use serde_yaml::Value;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum PlaybookError {
+    #[error("Failed to parse playbook: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("Playbook must be a list of plays")]
+    NotAList,
+}
+
+/// Task lists of a play, handlers included since they can carry tags too
+const TASK_SECTIONS: [&str; 4] = ["pre_tasks", "tasks", "post_tasks", "handlers"];
+const BLOCK_SECTIONS: [&str; 3] = ["block", "rescue", "always"];
+const ROLE_TASK_MODULES: [&str; 4] = ["include_role", "import_role", "ansible.builtin.include_role", "ansible.builtin.import_role"];
+const IMPORT_PLAYBOOK_MODULES: [&str; 2] = ["import_playbook", "ansible.builtin.import_playbook"];
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct PlaySummary {
+    pub name: Option<String>,
+    pub hosts: String,
+    /// play level tags together with every task and role tag inside the play
+    pub tags: BTreeSet<String>,
+    pub roles: BTreeSet<String>,
+    pub vars: BTreeSet<String>,
+    pub vars_prompt: BTreeSet<String>,
+}
+
+/// What a playbook offers to the one running it, merged over all plays
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct PlaybookSummary {
+    pub plays: Vec<PlaySummary>,
+    pub hosts: BTreeSet<String>,
+    pub tags: BTreeSet<String>,
+    pub roles: BTreeSet<String>,
+    pub vars: BTreeSet<String>,
+    pub vars_prompt: BTreeSet<String>,
+    /// playbooks pulled in with `import_playbook`, they are not fetched
+    pub imported_playbooks: Vec<String>,
+}
+
+impl PlaybookSummary {
+    pub fn parse(content: &str) -> Result<Self, PlaybookError> {
+        let plays = match serde_yaml::from_str::<Value>(content)? {
+            Value::Sequence(plays) => plays,
+            Value::Null => Vec::new(),
+            _ => return Err(PlaybookError::NotAList),
+        };
+        let mut summary = PlaybookSummary::default();
+        for play in &plays {
+            if let Some(imported) = IMPORT_PLAYBOOK_MODULES.iter().find_map(|module| play.get(module)).and_then(Value::as_str) {
+                summary.imported_playbooks.push(imported.to_string());
+                continue;
+            }
+            let play = parse_play(play);
+            summary.hosts.insert(play.hosts.clone());
+            summary.tags.extend(play.tags.iter().cloned());
+            summary.roles.extend(play.roles.iter().cloned());
+            summary.vars.extend(play.vars.iter().cloned());
+            summary.vars_prompt.extend(play.vars_prompt.iter().cloned());
+            summary.plays.push(play);
+        }
+        Ok(summary)
+    }
+}
+
+fn parse_play(play: &Value) -> PlaySummary {
+    let mut summary = PlaySummary {
+        name: play.get("name").and_then(Value::as_str).map(str::to_string),
+        hosts: match play.get("hosts") {
+            Some(Value::Sequence(hosts)) => hosts.iter().filter_map(Value::as_str).collect::<Vec<&str>>().join(","),
+            Some(hosts) => hosts.as_str().unwrap_or_default().to_string(),
+            None => String::new(),
+        },
+        tags: tags_of(play),
+        ..Default::default()
+    };
+    if let Some(Value::Mapping(vars)) = play.get("vars") {
+        summary.vars.extend(vars.keys().filter_map(Value::as_str).map(str::to_string));
+    }
+    if let Some(Value::Sequence(prompts)) = play.get("vars_prompt") {
+        summary.vars_prompt.extend(prompts.iter().filter_map(|prompt| prompt.get("name")).filter_map(Value::as_str).map(str::to_string));
+    }
+    if let Some(Value::Sequence(roles)) = play.get("roles") {
+        for role in roles {
+            let name = match role {
+                Value::String(name) => Some(name.as_str()),
+                role => role.get("role").or_else(|| role.get("name")).and_then(Value::as_str),
+            };
+            summary.roles.extend(name.map(str::to_string));
+            summary.tags.extend(tags_of(role));
+        }
+    }
+    for section in TASK_SECTIONS {
+        if let Some(Value::Sequence(tasks)) = play.get(section) {
+            collect_tasks(tasks, &mut summary);
+        }
+    }
+    summary
+}
+
+fn collect_tasks(tasks: &[Value], summary: &mut PlaySummary) {
+    for task in tasks {
+        summary.tags.extend(tags_of(task));
+        if let Some(role) = ROLE_TASK_MODULES.iter().find_map(|module| task.get(module)) {
+            summary.roles.extend(role.get("name").and_then(Value::as_str).map(str::to_string));
+            if let Some(apply) = role.get("apply") {
+                summary.tags.extend(tags_of(apply));
+            }
+        }
+        for section in BLOCK_SECTIONS {
+            if let Some(Value::Sequence(block)) = task.get(section) {
+                collect_tasks(block, summary);
+            }
+        }
+    }
+}
+
+/// `tags` may be a list, a single tag or a comma separated string
+fn tags_of(value: &Value) -> BTreeSet<String> {
+    let split = |tags: &str| tags.split(',').map(str::trim).filter(|tag| !tag.is_empty()).map(str::to_string).collect::<Vec<String>>();
+    match value.get("tags") {
+        Some(Value::Sequence(tags)) => tags.iter().filter_map(Value::as_str).flat_map(split).collect(),
+        Some(Value::String(tags)) => split(tags).into_iter().collect(),
+        Some(Value::Number(tag)) => BTreeSet::from([tag.to_string()]),
+        _ => BTreeSet::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAYBOOK: &str = r#"
+- import_playbook: common.yml
+
+- name: Web servers
+  hosts: [web, "&staging"]
+  tags: [web, deploy]
+  vars:
+    http_port: 80
+  vars_prompt:
+    - name: release
+      prompt: Release to deploy
+  roles:
+    - nginx
+    - role: certs
+      tags: tls
+  pre_tasks:
+    - name: Check disk
+      command: df -h
+      tags: checks
+  tasks:
+    - name: Deploy
+      block:
+        - name: Copy release
+          copy: {src: app, dest: /srv/app}
+          tags: "deploy, copy"
+      rescue:
+        - name: Roll back
+          command: rollback
+          tags: [rollback]
+      always:
+        - name: Cleanup
+          file: {path: /tmp/app, state: absent}
+          tags: cleanup
+    - include_role:
+        name: monitoring
+        apply:
+          tags: [monitoring]
+    - ansible.builtin.import_role:
+        name: backup
+      tags: backup
+  handlers:
+    - name: Restart nginx
+      service: {name: nginx, state: restarted}
+      tags: 2024
+
+- hosts: db
+  tags: deploy
+  roles:
+    - {name: postgres}
+"#;
+
+    fn summary() -> PlaybookSummary {
+        PlaybookSummary::parse(PLAYBOOK).unwrap()
+    }
+
+    fn names(set: &BTreeSet<String>) -> Vec<&str> {
+        set.iter().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn plays_and_hosts() {
+        let summary = summary();
+        assert_eq!(summary.plays.len(), 2);
+        assert_eq!(summary.plays[0].name.as_deref(), Some("Web servers"));
+        assert_eq!(summary.plays[0].hosts, "web,&staging");
+        assert_eq!(names(&summary.hosts), ["db", "web,&staging"]);
+    }
+
+    #[test]
+    fn play_task_and_role_tags() {
+        let summary = summary();
+        assert_eq!(names(&summary.plays[1].tags), ["deploy"]);
+        let web = &summary.plays[0].tags;
+        // play, pre_tasks, handlers and role tags
+        for tag in ["web", "checks", "2024", "tls"] {
+            assert!(web.contains(tag), "{} missing from {:?}", tag, web);
+        }
+    }
+
+    #[test]
+    fn block_rescue_and_always_tasks() {
+        let web = &summary().plays[0].tags;
+        for tag in ["copy", "rollback", "cleanup"] {
+            assert!(web.contains(tag), "{} missing from {:?}", tag, web);
+        }
+    }
+
+    #[test]
+    fn include_and_import_role() {
+        let summary = summary();
+        assert_eq!(names(&summary.plays[0].roles), ["backup", "certs", "monitoring", "nginx"]);
+        assert_eq!(names(&summary.plays[1].roles), ["postgres"]);
+        assert!(summary.tags.contains("monitoring") && summary.tags.contains("backup"));
+    }
+
+    #[test]
+    fn imported_playbooks_are_listed() {
+        assert_eq!(summary().imported_playbooks, ["common.yml"]);
+    }
+
+    #[test]
+    fn vars_and_vars_prompt() {
+        let summary = summary();
+        assert_eq!(names(&summary.vars), ["http_port"]);
+        assert_eq!(names(&summary.vars_prompt), ["release"]);
+    }
+
+    #[test]
+    fn tags_are_unique_and_sorted() {
+        assert_eq!(
+            names(&summary().tags),
+            ["2024", "backup", "checks", "cleanup", "copy", "deploy", "monitoring", "rollback", "tls", "web"],
+        );
+    }
+
+    #[test]
+    fn empty_and_invalid_playbooks() {
+        assert!(PlaybookSummary::parse("").unwrap().plays.is_empty());
+        assert!(matches!(PlaybookSummary::parse("hosts: all"), Err(PlaybookError::NotAList)));
+        assert!(matches!(PlaybookSummary::parse("- [unclosed"), Err(PlaybookError::Yaml(_))));
+    }
+}
@@ -0,0 +1,574 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::Serialize;
+use yaml_rust2::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust2::scanner::{Marker, TScalarStyle};
+
+use super::export::InventoryFormat;
+use super::ini::{parse_ini_inventory, parse_section_header, split_tokens, strip_comment, IniSectionKind};
+use super::inventory::{ALL_GROUP_NAME, UNGROUPED_GROUP_NAME};
+use super::vault::{VaultPayload, VAULT_TAG};
+use super::Inventory;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiagnosticSeverity {
+    Error,
+    Warning,
+}
+
+/// Single problem found in an inventory, line and column are 1-indexed
+#[derive(Debug, Clone, Serialize)]
+pub struct InventoryDiagnostic {
+    pub line: usize,
+    pub column: usize,
+    pub severity: DiagnosticSeverity,
+    pub message: String,
+}
+
+impl InventoryDiagnostic {
+    fn error(line: usize, column: usize, message: impl Into<String>) -> Self {
+        Self { line, column, severity: DiagnosticSeverity::Error, message: message.into() }
+    }
+    fn warning(line: usize, column: usize, message: impl Into<String>) -> Self {
+        Self { line, column, severity: DiagnosticSeverity::Warning, message: message.into() }
+    }
+}
+
+/// Validate inventory content and report every problem found, sorted by position
+pub fn validate_inventory(content: &str, format: InventoryFormat) -> Vec<InventoryDiagnostic> {
+    let mut diagnostics = match format {
+        InventoryFormat::Yaml | InventoryFormat::Json => validate_yaml(content),
+        InventoryFormat::Ini => validate_ini(content),
+    };
+    diagnostics.sort_by_key(|d| (d.line, d.column));
+    diagnostics
+}
+
+pub fn has_errors(diagnostics: &[InventoryDiagnostic]) -> bool {
+    diagnostics.iter().any(|d| d.severity == DiagnosticSeverity::Error)
+}
+
+/// Ansible variable names follow python identifier rules
+fn is_valid_var_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Ansible replaces other characters in group names with underscores and warns about it
+fn is_valid_group_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// `parent -> [(child, line, column)]` edges of the group hierarchy
+type ChildEdges = BTreeMap<String, Vec<(String, usize, usize)>>;
+
+fn check_cycles(edges: &ChildEdges, diagnostics: &mut Vec<InventoryDiagnostic>) {
+    fn visit(group: &str, edges: &ChildEdges, path: &mut Vec<String>, done: &mut BTreeSet<String>, diagnostics: &mut Vec<InventoryDiagnostic>) {
+        if done.contains(group) {
+            return;
+        }
+        path.push(group.to_string());
+        for (child, line, column) in edges.get(group).into_iter().flatten() {
+            if let Some(start) = path.iter().position(|name| name == child) {
+                let mut cycle = path[start..].to_vec();
+                cycle.push(child.clone());
+                diagnostics.push(InventoryDiagnostic::error(*line, *column, format!("Cyclic children: {}", cycle.join(" -> "))));
+            } else {
+                visit(child, edges, path, done, diagnostics);
+            }
+        }
+        path.pop();
+        done.insert(group.to_string());
+    }
+    let mut done = BTreeSet::new();
+    for group in edges.keys() {
+        visit(group, edges, &mut Vec::new(), &mut done, diagnostics);
+    }
+}
+
+// YAML
+
+/// Minimal YAML node tree keeping source positions, serde_yaml drops them after parsing
+#[derive(Debug)]
+enum YamlNode {
+    Scalar { value: String, style: TScalarStyle, tag: Option<String>, mark: Marker },
+    Sequence { mark: Marker },
+    Mapping { entries: Vec<(YamlNode, YamlNode)>, mark: Marker },
+    Alias { mark: Marker },
+}
+
+impl YamlNode {
+    fn mark(&self) -> Marker {
+        match self {
+            YamlNode::Scalar { mark, .. } | YamlNode::Sequence { mark } | YamlNode::Mapping { mark, .. } | YamlNode::Alias { mark } => *mark,
+        }
+    }
+    fn line(&self) -> usize {
+        self.mark().line()
+    }
+    fn column(&self) -> usize {
+        self.mark().col() + 1
+    }
+    fn is_null(&self) -> bool {
+        matches!(self, YamlNode::Scalar { value, style: TScalarStyle::Plain, tag: None, .. }
+            if value.is_empty() || value == "~" || value == "null" || value == "Null" || value == "NULL")
+    }
+    fn as_key(&self) -> String {
+        match self {
+            YamlNode::Scalar { value, .. } => value.clone(),
+            _ => String::new(),
+        }
+    }
+}
+
+enum YamlFrame {
+    Sequence(Marker),
+    Mapping(Marker, Vec<YamlNode>),
+}
+
+#[derive(Default)]
+struct YamlTreeBuilder {
+    stack: Vec<YamlFrame>,
+    documents: Vec<YamlNode>,
+}
+
+impl YamlTreeBuilder {
+    fn push_node(&mut self, node: YamlNode) {
+        match self.stack.last_mut() {
+            Some(YamlFrame::Mapping(_, items)) => items.push(node),
+            // sequence items are not inspected, only the position of the sequence is kept
+            Some(YamlFrame::Sequence(_)) => {}
+            None => self.documents.push(node),
+        }
+    }
+}
+
+impl MarkedEventReceiver for YamlTreeBuilder {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::Scalar(value, style, _, tag) => self.push_node(YamlNode::Scalar { value, style, tag: tag.map(|tag| tag.suffix), mark }),
+            Event::Alias(_) => self.push_node(YamlNode::Alias { mark }),
+            Event::SequenceStart(..) => self.stack.push(YamlFrame::Sequence(mark)),
+            Event::MappingStart(..) => self.stack.push(YamlFrame::Mapping(mark, Vec::new())),
+            Event::SequenceEnd => {
+                if let Some(YamlFrame::Sequence(start)) = self.stack.pop() {
+                    self.push_node(YamlNode::Sequence { mark: start });
+                }
+            }
+            Event::MappingEnd => {
+                if let Some(YamlFrame::Mapping(start, items)) = self.stack.pop() {
+                    let mut items = items.into_iter();
+                    let mut entries = Vec::new();
+                    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                        entries.push((key, value));
+                    }
+                    // the parser marks a block mapping after its first key, the key position reads better
+                    let mark = entries.first().map(|(key, _)| key.mark()).unwrap_or(start);
+                    self.push_node(YamlNode::Mapping { entries, mark });
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Default)]
+struct YamlValidator {
+    diagnostics: Vec<InventoryDiagnostic>,
+    edges: ChildEdges,
+    /// groups that got hosts, children or vars somewhere in the file
+    populated_groups: BTreeSet<String>,
+    /// children declared with an empty body, checked against populated groups in the end
+    empty_children: Vec<(String, usize, usize)>,
+}
+
+impl YamlValidator {
+    /// Report duplicate keys of a mapping, the message is built by the caller
+    fn check_duplicates(&mut self, entries: &[(YamlNode, YamlNode)], message: impl Fn(&str, usize) -> String) {
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        for (key, _) in entries {
+            let name = key.as_key();
+            match seen.get(&name) {
+                Some(first_line) => self.diagnostics.push(InventoryDiagnostic::error(key.line(), key.column(), message(&name, *first_line))),
+                None => {
+                    seen.insert(name, key.line());
+                }
+            }
+        }
+    }
+
+    fn check_group(&mut self, name: &str, key: &YamlNode, group: &YamlNode) {
+        if !is_valid_group_name(name) {
+            self.diagnostics.push(InventoryDiagnostic::warning(key.line(), key.column(),
+                format!("Group name '{}' contains characters ansible will replace with '_'", name)));
+        }
+        if group.is_null() {
+            return;
+        }
+        let entries = match group {
+            YamlNode::Mapping { entries, .. } => entries,
+            other => {
+                self.diagnostics.push(InventoryDiagnostic::error(other.line(), other.column(), format!("Group '{}' must be a mapping", name)));
+                return;
+            }
+        };
+        self.check_duplicates(entries, |key, first_line| format!("Duplicate key '{}' in group '{}', first defined at line {}", key, name, first_line));
+        for (section_key, section) in entries {
+            let section_name = section_key.as_key();
+            match section_name.as_str() {
+                "hosts" => self.check_hosts(name, section),
+                "children" => self.check_children(name, section),
+                "vars" => {
+                    self.check_vars(&format!("group '{}'", name), section);
+                }
+                other => {
+                    self.diagnostics.push(InventoryDiagnostic::error(section_key.line(), section_key.column(),
+                        format!("Unexpected key '{}' in group '{}', expected hosts, children or vars", other, name)));
+                    continue;
+                }
+            }
+            if !section.is_null() {
+                self.populated_groups.insert(name.to_string());
+            }
+        }
+    }
+
+    fn check_hosts(&mut self, group_name: &str, hosts: &YamlNode) {
+        if hosts.is_null() {
+            return;
+        }
+        let entries = match hosts {
+            YamlNode::Mapping { entries, .. } => entries,
+            other => {
+                self.diagnostics.push(InventoryDiagnostic::error(other.line(), other.column(),
+                    format!("Hosts of group '{}' must be a mapping of host names", group_name)));
+                return;
+            }
+        };
+        self.check_duplicates(entries, |host, first_line| format!("Duplicate host '{}' in group '{}', first defined at line {}", host, group_name, first_line));
+        for (host, vars) in entries {
+            if host.as_key().trim().is_empty() {
+                self.diagnostics.push(InventoryDiagnostic::error(host.line(), host.column(), format!("Empty host name in group '{}'", group_name)));
+            }
+            if !vars.is_null() {
+                self.check_vars(&format!("host '{}'", host.as_key()), vars);
+            }
+        }
+    }
+
+    fn check_children(&mut self, group_name: &str, children: &YamlNode) {
+        if children.is_null() {
+            return;
+        }
+        let entries = match children {
+            YamlNode::Mapping { entries, .. } => entries,
+            other => {
+                self.diagnostics.push(InventoryDiagnostic::error(other.line(), other.column(),
+                    format!("Children of group '{}' must be a mapping of group names", group_name)));
+                return;
+            }
+        };
+        self.check_duplicates(entries, |child, first_line| format!("Duplicate child group '{}' in group '{}', first defined at line {}", child, group_name, first_line));
+        for (child_key, child) in entries {
+            let child_name = child_key.as_key();
+            self.edges.entry(group_name.to_string()).or_default().push((child_name.clone(), child_key.line(), child_key.column()));
+            if child.is_null() {
+                self.empty_children.push((child_name.clone(), child_key.line(), child_key.column()));
+            }
+            self.check_group(&child_name, child_key, child);
+        }
+    }
+
+    fn check_vars(&mut self, owner: &str, vars: &YamlNode) {
+        if vars.is_null() {
+            return;
+        }
+        let entries = match vars {
+            YamlNode::Mapping { entries, .. } => entries,
+            other => {
+                self.diagnostics.push(InventoryDiagnostic::error(other.line(), other.column(), format!("Variables of {} must be a mapping", owner)));
+                return;
+            }
+        };
+        self.check_duplicates(entries, |var, first_line| format!("Duplicate variable '{}' in {}, first defined at line {}", var, owner, first_line));
+        for (key, value) in entries {
+            let name = key.as_key();
+            if !is_valid_var_name(&name) {
+                self.diagnostics.push(InventoryDiagnostic::error(key.line(), key.column(), format!("Invalid variable name '{}' in {}", name, owner)));
+            }
+            match value {
+                YamlNode::Scalar { value: text, tag: Some(tag), .. } if tag == VAULT_TAG => {
+                    if let Err(e) = VaultPayload::parse(text) {
+                        self.diagnostics.push(InventoryDiagnostic::error(value.line(), value.column(), format!("Variable '{}' in {}: {}", name, owner, e)));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<InventoryDiagnostic> {
+        for (child, line, column) in std::mem::take(&mut self.empty_children) {
+            if !self.populated_groups.contains(&child) {
+                self.diagnostics.push(InventoryDiagnostic::warning(line, column,
+                    format!("Child group '{}' has no hosts, children or vars anywhere in the inventory", child)));
+            }
+        }
+        check_cycles(&self.edges, &mut self.diagnostics);
+        self.diagnostics
+    }
+}
+
+fn validate_yaml(content: &str) -> Vec<InventoryDiagnostic> {
+    let mut builder = YamlTreeBuilder::default();
+    let mut parser = Parser::new_from_str(content);
+    if let Err(e) = parser.load(&mut builder, true) {
+        return vec![InventoryDiagnostic::error(e.marker().line(), e.marker().col() + 1, format!("Invalid yaml: {}", e.info()))];
+    }
+    let mut documents = builder.documents.into_iter();
+    let root = match documents.next() {
+        Some(root) if !root.is_null() => root,
+        _ => return vec![InventoryDiagnostic::warning(1, 1, "Inventory is empty")],
+    };
+    let mut validator = YamlValidator::default();
+    if let Some(extra) = documents.next() {
+        validator.diagnostics.push(InventoryDiagnostic::error(extra.line(), extra.column(), "Inventory must be a single yaml document"));
+    }
+    match &root {
+        YamlNode::Mapping { entries, .. } => {
+            validator.check_duplicates(entries, |group, first_line| format!("Duplicate group '{}', first defined at line {}", group, first_line));
+            for (key, group) in entries {
+                validator.check_group(&key.as_key(), key, group);
+            }
+        }
+        other => validator.diagnostics.push(InventoryDiagnostic::error(other.line(), other.column(), "Inventory must be a mapping of group names")),
+    }
+    let mut diagnostics = validator.finish();
+    // anything the structural checks missed still has to load with the real parser
+    if let (false, Err(e)) = (has_errors(&diagnostics), serde_yaml::from_str::<Inventory>(content)) {
+        let (line, column) = e.location().map(|l| (l.line(), l.column())).unwrap_or((1, 1));
+        diagnostics.push(InventoryDiagnostic::error(line, column, e.to_string()));
+    }
+    diagnostics
+}
+
+// INI
+
+/// Column of `token` in `raw_line` searching from byte offset `from`, advances `from` past it
+fn token_column(raw_line: &str, token: &str, from: &mut usize) -> usize {
+    match raw_line[*from..].find(token) {
+        Some(offset) => {
+            let start = *from + offset;
+            *from = start + token.len();
+            raw_line[..start].chars().count() + 1
+        }
+        None => 1,
+    }
+}
+
+fn check_ini_value(value: &str, line: usize, column: usize, diagnostics: &mut Vec<InventoryDiagnostic>) {
+    let value = value.trim();
+    for q in ['"', '\''] {
+        if value.starts_with(q) && (value.len() < 2 || !value.ends_with(q)) {
+            diagnostics.push(InventoryDiagnostic::error(line, column, format!("Unterminated quote in value {}", value)));
+            return;
+        }
+    }
+}
+
+fn check_ini_variable(token: &str, owner: &str, line: usize, column: usize, diagnostics: &mut Vec<InventoryDiagnostic>) -> Option<String> {
+    match token.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            let key = key.trim();
+            if !is_valid_var_name(key) {
+                diagnostics.push(InventoryDiagnostic::error(line, column, format!("Invalid variable name '{}' in {}", key, owner)));
+            }
+            check_ini_value(value, line, column + token.find('=').unwrap_or(0) + 1, diagnostics);
+            Some(key.to_string())
+        }
+        _ => {
+            diagnostics.push(InventoryDiagnostic::error(line, column, format!("Invalid variable definition '{}' in {}, expected key=value", token, owner)));
+            None
+        }
+    }
+}
+
+fn validate_ini(content: &str) -> Vec<InventoryDiagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut edges = ChildEdges::new();
+    let mut defined_groups: BTreeSet<String> = BTreeSet::from([ALL_GROUP_NAME.to_string(), UNGROUPED_GROUP_NAME.to_string()]);
+    let mut hosts_seen: HashMap<(String, String), usize> = HashMap::new();
+    let mut vars_seen: HashMap<(String, String), usize> = HashMap::new();
+    let mut section = Some((UNGROUPED_GROUP_NAME.to_string(), IniSectionKind::Hosts));
+
+    for (index, raw_line) in content.lines().enumerate() {
+        let line_number = index + 1;
+        let line = strip_comment(raw_line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let indent = raw_line.chars().take_while(|c| c.is_whitespace()).count() + 1;
+        if line.starts_with('[') {
+            section = match parse_section_header(line, line_number) {
+                Ok((group_name, kind)) => {
+                    if !is_valid_group_name(&group_name) {
+                        diagnostics.push(InventoryDiagnostic::warning(line_number, indent + 1,
+                            format!("Group name '{}' contains characters ansible will replace with '_'", group_name)));
+                    }
+                    defined_groups.insert(group_name.clone());
+                    Some((group_name, kind))
+                }
+                Err(e) => {
+                    diagnostics.push(InventoryDiagnostic::error(line_number, indent, e.to_string()));
+                    None
+                }
+            };
+            continue;
+        }
+        // lines of a broken section header are skipped, the header is already reported
+        let (group_name, kind) = match &section {
+            Some(section) => section,
+            None => continue,
+        };
+        match kind {
+            IniSectionKind::Hosts => {
+                // on unbalanced quotes fall back to plain words, the broken value gets reported below
+                let tokens = split_tokens(line, line_number)
+                    .unwrap_or_else(|_| line.split_whitespace().map(str::to_string).collect());
+                let mut offset = 0;
+                let mut tokens = tokens.iter();
+                let host_name = match tokens.next() {
+                    Some(host_name) => host_name,
+                    None => continue,
+                };
+                let host_column = token_column(raw_line, host_name, &mut offset);
+                match hosts_seen.get(&(group_name.clone(), host_name.clone())) {
+                    Some(first_line) => diagnostics.push(InventoryDiagnostic::error(line_number, host_column,
+                        format!("Duplicate host '{}' in group '{}', first defined at line {}", host_name, group_name, first_line))),
+                    None => {
+                        hosts_seen.insert((group_name.clone(), host_name.clone()), line_number);
+                    }
+                }
+                let owner = format!("host '{}'", host_name);
+                for token in tokens {
+                    let column = token_column(raw_line, token, &mut offset);
+                    check_ini_variable(token, &owner, line_number, column, &mut diagnostics);
+                }
+            }
+            IniSectionKind::Vars => {
+                let owner = format!("group '{}'", group_name);
+                if let Some(key) = check_ini_variable(line, &owner, line_number, indent, &mut diagnostics) {
+                    match vars_seen.get(&(group_name.clone(), key.clone())) {
+                        Some(first_line) => diagnostics.push(InventoryDiagnostic::warning(line_number, indent,
+                            format!("Variable '{}' of {} is redefined, first defined at line {}", key, owner, first_line))),
+                        None => {
+                            vars_seen.insert((group_name.clone(), key), line_number);
+                        }
+                    }
+                }
+            }
+            IniSectionKind::Children => {
+                if line.contains(char::is_whitespace) {
+                    diagnostics.push(InventoryDiagnostic::error(line_number, indent,
+                        format!("Invalid child group '{}' of group '{}', expected a single group name", line, group_name)));
+                    continue;
+                }
+                let children = edges.entry(group_name.clone()).or_default();
+                if children.iter().any(|(child, _, _)| child == line) {
+                    diagnostics.push(InventoryDiagnostic::warning(line_number, indent,
+                        format!("Duplicate child group '{}' in group '{}'", line, group_name)));
+                    continue;
+                }
+                children.push((line.to_string(), line_number, indent));
+            }
+        }
+    }
+    for (group_name, children) in &edges {
+        for (child, line, column) in children {
+            if !defined_groups.contains(child) {
+                diagnostics.push(InventoryDiagnostic::error(*line, *column,
+                    format!("Child group '{}' of group '{}' is not defined", child, group_name)));
+            }
+        }
+    }
+    check_cycles(&edges, &mut diagnostics);
+    if let (false, Err(e)) = (has_errors(&diagnostics), parse_ini_inventory(content)) {
+        diagnostics.push(InventoryDiagnostic::error(e.line(), 1, e.to_string()));
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `(line, column, severity)` of every diagnostic
+    fn positions(content: &str, format: InventoryFormat) -> Vec<(usize, usize, DiagnosticSeverity)> {
+        validate_inventory(content, format).iter().map(|d| (d.line, d.column, d.severity)).collect()
+    }
+
+    fn messages(content: &str, format: InventoryFormat) -> Vec<String> {
+        validate_inventory(content, format).into_iter().map(|d| d.message).collect()
+    }
+
+    #[test]
+    fn valid_inventories_have_no_diagnostics() {
+        let yaml = "all:\n  children:\n    web:\n      hosts:\n        web1:\n          port: 22\n";
+        assert!(validate_inventory(yaml, InventoryFormat::Yaml).is_empty());
+        let ini = "[web]\nweb1 port=22\n\n[all:children]\nweb\n";
+        assert!(validate_inventory(ini, InventoryFormat::Ini).is_empty());
+    }
+
+    #[test]
+    fn yaml_duplicate_host() {
+        let yaml = "web:\n  hosts:\n    web1:\n    web2:\n    web1:\n";
+        assert_eq!(positions(yaml, InventoryFormat::Yaml), [(5, 5, DiagnosticSeverity::Error)]);
+        assert_eq!(messages(yaml, InventoryFormat::Yaml), ["Duplicate host 'web1' in group 'web', first defined at line 3"]);
+    }
+
+    #[test]
+    fn yaml_duplicate_variable() {
+        let yaml = "web:\n  vars:\n    port: 22\n    port: 23\n";
+        assert_eq!(positions(yaml, InventoryFormat::Yaml), [(4, 5, DiagnosticSeverity::Error)]);
+    }
+
+    #[test]
+    fn yaml_cycle() {
+        let yaml = "a:\n  children:\n    b:\n      children:\n        a:\n          hosts:\n            h1:\n";
+        let diagnostics = validate_inventory(yaml, InventoryFormat::Yaml);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (5, 9));
+        assert_eq!(diagnostics[0].message, "Cyclic children: a -> b -> a");
+    }
+
+    #[test]
+    fn ini_duplicate_host() {
+        let ini = "[web]\nweb1\nweb2\n  web1 port=22\n";
+        assert_eq!(positions(ini, InventoryFormat::Ini), [(4, 3, DiagnosticSeverity::Error)]);
+        assert_eq!(messages(ini, InventoryFormat::Ini), ["Duplicate host 'web1' in group 'web', first defined at line 2"]);
+    }
+
+    #[test]
+    fn ini_cycle() {
+        let ini = "[a:children]\nb\n\n[b:children]\na\n";
+        let diagnostics = validate_inventory(ini, InventoryFormat::Ini);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, 5);
+        assert_eq!(diagnostics[0].message, "Cyclic children: a -> b -> a");
+    }
+
+    #[test]
+    fn ini_unterminated_quote() {
+        let ini = "[web]\nweb1 user=\"deploy\n";
+        assert_eq!(positions(ini, InventoryFormat::Ini), [(2, 11, DiagnosticSeverity::Error)]);
+        assert_eq!(messages(ini, InventoryFormat::Ini), ["Unterminated quote in value \"deploy"]);
+    }
+
+    #[test]
+    fn ini_undefined_child() {
+        let ini = "[web:children]\napp\n";
+        assert_eq!(messages(ini, InventoryFormat::Ini), ["Child group 'app' of group 'web' is not defined"]);
+    }
+}
@@ -0,0 +1,339 @@
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use openssl::symm::{self, Cipher};
+use serde_yaml::value::{Tag, TaggedValue};
+use serde_yaml::{Mapping, Value};
+use thiserror::Error;
+
+use super::{HostGroup, Inventory, InventoryChildren, InventoryVars};
+
+pub const VAULT_HEADER: &str = "$ANSIBLE_VAULT";
+pub const VAULT_TAG: &str = "vault";
+/// Key used for vault values in JSON inventories, same as `ansible-inventory --list` prints
+pub const VAULT_JSON_KEY: &str = "__ansible_vault";
+
+const VAULT_CIPHER: &str = "AES256";
+const SALT_LENGTH: usize = 32;
+const KEY_LENGTH: usize = 32;
+const IV_LENGTH: usize = 16;
+const PBKDF2_ITERATIONS: usize = 10000;
+const AES_BLOCK_SIZE: usize = 16;
+const LINE_WIDTH: usize = 80;
+
+#[derive(Error, Debug)]
+pub enum VaultError {
+    #[error("Invalid vault payload: {0}")]
+    InvalidFormat(String),
+    #[error("Unsupported vault cipher {0}, only AES256 is supported")]
+    UnsupportedCipher(String),
+    #[error("Vault HMAC mismatch, the vault password is wrong or the payload is corrupted")]
+    InvalidHmac,
+    #[error("Decrypted vault value is not valid utf-8")]
+    InvalidUtf8,
+    #[error("Vault crypto error: {0}")]
+    Crypto(#[from] ErrorStack),
+}
+
+/// Parsed `$ANSIBLE_VAULT;1.1;AES256` payload
+#[derive(Debug, Clone)]
+pub struct VaultPayload {
+    pub version: String,
+    pub cipher: String,
+    pub vault_id: Option<String>,
+    salt: Vec<u8>,
+    hmac: Vec<u8>,
+    ciphertext: Vec<u8>,
+}
+
+impl VaultPayload {
+    pub fn is_vault_text(text: &str) -> bool {
+        text.trim_start().starts_with(VAULT_HEADER)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, VaultError> {
+        let mut lines = text.trim().lines().map(str::trim);
+        let header = lines.next().unwrap_or_default();
+        let fields: Vec<&str> = header.split(';').collect();
+        if fields.len() < 3 || fields[0] != VAULT_HEADER {
+            return Err(VaultError::InvalidFormat(format!("invalid header {}", header)));
+        }
+        if fields[2] != VAULT_CIPHER {
+            return Err(VaultError::UnsupportedCipher(fields[2].to_string()));
+        }
+        let body: String = lines.collect();
+        // the body is hex of three newline separated hex strings: salt, hmac and ciphertext
+        let body = hex::decode(body).map_err(|e| VaultError::InvalidFormat(e.to_string()))?;
+        let body = String::from_utf8(body).map_err(|_| VaultError::InvalidFormat("body is not hex encoded".to_string()))?;
+        let parts: Vec<&str> = body.split('\n').collect();
+        if parts.len() != 3 {
+            return Err(VaultError::InvalidFormat("body must contain salt, hmac and ciphertext".to_string()));
+        }
+        let decode = |part: &str| hex::decode(part.trim()).map_err(|e| VaultError::InvalidFormat(e.to_string()));
+        Ok(Self {
+            version: fields[1].to_string(),
+            cipher: fields[2].to_string(),
+            vault_id: fields.get(3).map(|id| id.to_string()),
+            salt: decode(parts[0])?,
+            hmac: decode(parts[1])?,
+            ciphertext: decode(parts[2])?,
+        })
+    }
+
+    pub fn decrypt(&self, password: &str) -> Result<String, VaultError> {
+        let keys = DerivedKeys::new(password, &self.salt)?;
+        let expected = hmac_sha256(&keys.hmac_key, &self.ciphertext)?;
+        if expected.len() != self.hmac.len() || !openssl::memcmp::eq(&expected, &self.hmac) {
+            return Err(VaultError::InvalidHmac);
+        }
+        let mut plaintext = symm::decrypt(Cipher::aes_256_ctr(), &keys.cipher_key, Some(&keys.iv), &self.ciphertext)?;
+        let padding = plaintext.last().copied().unwrap_or_default() as usize;
+        if padding == 0 || padding > AES_BLOCK_SIZE || padding > plaintext.len() {
+            return Err(VaultError::InvalidFormat("invalid padding".to_string()));
+        }
+        plaintext.truncate(plaintext.len() - padding);
+        String::from_utf8(plaintext).map_err(|_| VaultError::InvalidUtf8)
+    }
+
+    pub fn encrypt(plaintext: &str, password: &str, vault_id: Option<&str>) -> Result<Self, VaultError> {
+        let mut salt = vec![0u8; SALT_LENGTH];
+        openssl::rand::rand_bytes(&mut salt)?;
+        let keys = DerivedKeys::new(password, &salt)?;
+        // ansible pads with PKCS7 even though CTR mode doesn't need it
+        let mut padded = plaintext.as_bytes().to_vec();
+        let padding = AES_BLOCK_SIZE - padded.len() % AES_BLOCK_SIZE;
+        padded.resize(padded.len() + padding, padding as u8);
+        let ciphertext = symm::encrypt(Cipher::aes_256_ctr(), &keys.cipher_key, Some(&keys.iv), &padded)?;
+        let hmac = hmac_sha256(&keys.hmac_key, &ciphertext)?;
+        Ok(Self {
+            version: if vault_id.is_some() { "1.2" } else { "1.1" }.to_string(),
+            cipher: VAULT_CIPHER.to_string(),
+            vault_id: vault_id.map(str::to_string),
+            salt,
+            hmac,
+            ciphertext,
+        })
+    }
+
+    /// Text form as written by `ansible-vault encrypt_string`, without the `!vault |` prefix
+    pub fn to_text(&self) -> String {
+        let mut header = format!("{};{};{}", VAULT_HEADER, self.version, self.cipher);
+        if let Some(vault_id) = &self.vault_id {
+            header = format!("{};{}", header, vault_id);
+        }
+        let body = format!("{}\n{}\n{}", hex::encode(&self.salt), hex::encode(&self.hmac), hex::encode(&self.ciphertext));
+        let body = hex::encode(body);
+        let lines: Vec<&str> = body.as_bytes().chunks(LINE_WIDTH).map(|chunk| std::str::from_utf8(chunk).unwrap_or_default()).collect();
+        format!("{}\n{}\n", header, lines.join("\n"))
+    }
+
+    pub fn to_yaml_value(&self) -> Value {
+        Value::Tagged(Box::new(TaggedValue { tag: Tag::new(VAULT_TAG), value: Value::String(self.to_text()) }))
+    }
+}
+
+struct DerivedKeys {
+    cipher_key: Vec<u8>,
+    hmac_key: Vec<u8>,
+    iv: Vec<u8>,
+}
+
+impl DerivedKeys {
+    fn new(password: &str, salt: &[u8]) -> Result<Self, VaultError> {
+        let mut derived = vec![0u8; 2 * KEY_LENGTH + IV_LENGTH];
+        openssl::pkcs5::pbkdf2_hmac(password.as_bytes(), salt, PBKDF2_ITERATIONS, MessageDigest::sha256(), &mut derived)?;
+        Ok(Self {
+            cipher_key: derived[..KEY_LENGTH].to_vec(),
+            hmac_key: derived[KEY_LENGTH..2 * KEY_LENGTH].to_vec(),
+            iv: derived[2 * KEY_LENGTH..].to_vec(),
+        })
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Result<Vec<u8>, VaultError> {
+    let key = PKey::hmac(key)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(data)?;
+    Ok(signer.sign_to_vec()?)
+}
+
+/// Vault text of a value in any of the supported forms: `!vault` tag, `__ansible_vault` mapping or a plain string
+pub fn vault_text(value: &Value) -> Option<&str> {
+    match value {
+        Value::Tagged(tagged) if tagged.tag == VAULT_TAG => tagged.value.as_str(),
+        Value::Mapping(mapping) if mapping.len() == 1 => mapping.get(VAULT_JSON_KEY).and_then(Value::as_str),
+        Value::String(text) if VaultPayload::is_vault_text(text) => Some(text),
+        _ => None,
+    }
+}
+
+/// Bring vault values to the `!vault` tagged form a yaml inventory is parsed into
+fn tag_vault_value(value: &mut Value) {
+    if let Some(text) = vault_text(value) {
+        let text = text.to_string();
+        *value = Value::Tagged(Box::new(TaggedValue { tag: Tag::new(VAULT_TAG), value: Value::String(text) }));
+        return;
+    }
+    match value {
+        Value::Mapping(mapping) => mapping.iter_mut().for_each(|(_, v)| tag_vault_value(v)),
+        Value::Sequence(sequence) => sequence.iter_mut().for_each(tag_vault_value),
+        _ => {}
+    }
+}
+
+/// Value suitable for JSON output, vault values become `{"__ansible_vault": ...}`
+pub fn to_export_value(value: &Value) -> Value {
+    if let Some(text) = vault_text(value) {
+        let mut mapping = Mapping::new();
+        mapping.insert(Value::String(VAULT_JSON_KEY.to_string()), Value::String(text.to_string()));
+        return Value::Mapping(mapping);
+    }
+    match value {
+        Value::Mapping(mapping) => Value::Mapping(mapping.iter().map(|(k, v)| (k.clone(), to_export_value(v))).collect()),
+        Value::Sequence(sequence) => Value::Sequence(sequence.iter().map(to_export_value).collect()),
+        Value::Tagged(tagged) => to_export_value(&tagged.value),
+        other => other.clone(),
+    }
+}
+
+fn decrypt_value(value: &mut Value, password: &str) -> Result<usize, VaultError> {
+    if let Some(text) = vault_text(value) {
+        *value = Value::String(VaultPayload::parse(text)?.decrypt(password)?);
+        return Ok(1);
+    }
+    let mut count = 0;
+    match value {
+        Value::Mapping(mapping) => {
+            for (_, v) in mapping.iter_mut() {
+                count += decrypt_value(v, password)?;
+            }
+        }
+        Value::Sequence(sequence) => {
+            for v in sequence.iter_mut() {
+                count += decrypt_value(v, password)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(count)
+}
+
+fn vars_mut(group: &mut InventoryChildren) -> Vec<&mut InventoryVars> {
+    let InventoryChildren { hosts: HostGroup(hosts), children, vars } = group;
+    let mut all_vars: Vec<&mut InventoryVars> = hosts.values_mut().flatten().map(|host_vars| &mut host_vars.0).collect();
+    all_vars.extend(vars.as_mut());
+    for child in children.iter_mut().flat_map(|children| children.values_mut()) {
+        all_vars.extend(vars_mut(child));
+    }
+    all_vars
+}
+
+impl Inventory {
+    /// Recognise vault payloads coming from json inventories or written as plain strings
+    pub fn tag_vault_values(&mut self) {
+        for vars in self.0.values_mut().flat_map(vars_mut) {
+            vars.values_mut().for_each(tag_vault_value);
+        }
+    }
+
+    /// Replace every vault value with its plaintext, returns how many values were decrypted
+    pub fn decrypt_vault_values(&mut self, password: &str) -> Result<usize, VaultError> {
+        let mut count = 0;
+        for vars in self.0.values_mut().flat_map(vars_mut) {
+            for value in vars.values_mut() {
+                count += decrypt_value(value, password)?;
+            }
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWORD: &str = "plim-test-password";
+    /// `s3cret value` encrypted with PASSWORD and the salt 00..1f. It was made with the python
+    /// `cryptography` package following ansible's VaultAES256, separately from the code above
+    const KNOWN_VAULT: &str = "\
+$ANSIBLE_VAULT;1.1;AES256
+30303031303230333034303530363037303830393061306230633064306530663130313131323133
+3134313531363137313831393161316231633164316531660a356139356533366333356635396530
+63383462373739363566616639643737643133306638616634666339643062323431393631656335
+3435653738633634660a383633396332633831366164353866633931636465633832303639333432
+3839
+";
+
+    #[test]
+    fn decrypts_known_vector() {
+        let payload = VaultPayload::parse(KNOWN_VAULT).unwrap();
+        assert_eq!(payload.version, "1.1");
+        assert_eq!(payload.vault_id, None);
+        assert_eq!(payload.decrypt(PASSWORD).unwrap(), "s3cret value");
+        // the text form is byte for byte what ansible writes
+        assert_eq!(payload.to_text(), KNOWN_VAULT);
+    }
+
+    #[test]
+    fn round_trip() {
+        // empty, exactly one block and multi-byte values exercise the padding
+        for plaintext in ["", "0123456789abcdef", "päss wörd\nwith a newline", &"x".repeat(100)] {
+            let text = VaultPayload::encrypt(plaintext, PASSWORD, None).unwrap().to_text();
+            assert!(VaultPayload::is_vault_text(&text));
+            assert_eq!(VaultPayload::parse(&text).unwrap().decrypt(PASSWORD).unwrap(), plaintext);
+        }
+    }
+
+    #[test]
+    fn round_trip_with_vault_id() {
+        let text = VaultPayload::encrypt("value", PASSWORD, Some("prod")).unwrap().to_text();
+        assert!(text.starts_with("$ANSIBLE_VAULT;1.2;AES256;prod\n"));
+        let payload = VaultPayload::parse(&text).unwrap();
+        assert_eq!(payload.vault_id.as_deref(), Some("prod"));
+        assert_eq!(payload.decrypt(PASSWORD).unwrap(), "value");
+    }
+
+    #[test]
+    fn encryption_is_salted() {
+        let first = VaultPayload::encrypt("value", PASSWORD, None).unwrap().to_text();
+        let second = VaultPayload::encrypt("value", PASSWORD, None).unwrap().to_text();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn wrong_password_is_an_hmac_mismatch() {
+        let payload = VaultPayload::parse(KNOWN_VAULT).unwrap();
+        assert!(matches!(payload.decrypt("wrong"), Err(VaultError::InvalidHmac)));
+    }
+
+    #[test]
+    fn tampered_ciphertext_is_an_hmac_mismatch() {
+        let mut payload = VaultPayload::parse(KNOWN_VAULT).unwrap();
+        payload.ciphertext[0] ^= 1;
+        assert!(matches!(payload.decrypt(PASSWORD), Err(VaultError::InvalidHmac)));
+        let mut payload = VaultPayload::parse(KNOWN_VAULT).unwrap();
+        payload.hmac.truncate(16);
+        assert!(matches!(payload.decrypt(PASSWORD), Err(VaultError::InvalidHmac)));
+    }
+
+    #[test]
+    fn invalid_payloads() {
+        assert!(matches!(VaultPayload::parse("not a vault"), Err(VaultError::InvalidFormat(_))));
+        assert!(matches!(VaultPayload::parse("$ANSIBLE_VAULT;1.1;AES\n3030"), Err(VaultError::UnsupportedCipher(_))));
+        assert!(matches!(VaultPayload::parse("$ANSIBLE_VAULT;1.1;AES256\nzz"), Err(VaultError::InvalidFormat(_))));
+        // valid hex, but not the three hex lines of salt, hmac and ciphertext
+        assert!(matches!(VaultPayload::parse("$ANSIBLE_VAULT;1.1;AES256\n3030"), Err(VaultError::InvalidFormat(_))));
+    }
+
+    #[test]
+    fn decrypts_inventory_values() {
+        let mut inventory: Inventory = serde_yaml::from_str(&format!(
+            "web:\n  hosts:\n    web1:\n      password: !vault |\n{}\n  vars:\n    plain: value\n",
+            KNOWN_VAULT.lines().map(|line| format!("        {}", line)).collect::<Vec<_>>().join("\n"),
+        )).unwrap();
+        assert_eq!(inventory.decrypt_vault_values(PASSWORD).unwrap(), 1);
+        let password = &inventory.0["web"].hosts.0["web1"].as_ref().unwrap().0["password"];
+        assert_eq!(password, &Value::String("s3cret value".to_string()));
+    }
+}
@@ -49,12 +49,13 @@ pub async fn login(
         }).into_response()
     } else {
         
-        if !state.config.check_user_password_is_valid(&payload.username, &payload.password) {
+        let config = state.config.load();
+        if !config.check_user_password_is_valid(&payload.username, &payload.password) {
             return AuthenticationResponse::Error(TokenResponseError {
                 detail: "Invalid username or password".to_string(),
             }).into_response()
         }
-        let user = match state.config.get_user(&payload.username) {
+        let user = match config.get_user(&payload.username) {
             Ok(user) => user,
             _ => return AuthenticationResponse::Error(TokenResponseError {
                 detail: "Error getting user".to_string(),
@@ -1,14 +1,15 @@
 use std::collections::HashMap;
 
-use crate::{config::{AnsibleBackendType, AnsibleEtcdBackend, DataSource, DataSourceType, PlimPlan, PlimPlanViewType}, jwt::Claims, state::AppState};
+use crate::{config::{AnsibleBackendType, AnsibleEtcdBackend, DataSource, EtcdConfig, DataSourceType, PlimPlan, PlimPlanViewType}, jwt::Claims, secret_registry::PlanScope, state::AppState};
 use axum::{extract::State, response::IntoResponse, Extension, Json};
 use base64::{prelude::BASE64_STANDARD, Engine};
-use log::trace;
+use etcd_client::{Compare, CompareOp, GetOptions, Txn, TxnOp};
+use log::{trace, warn};
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
-use super::{ ansible::{AnsibleInventoryParserLocal, Inventory}, PlimApiError, PlimErrorKind};
+use super::{ ansible::{diff::InventoryDiff, export::{render, InventoryFormat}, load_plan_inventory, validate::{has_errors, validate_inventory}, AnsibleInventoryParserLocal, Inventory}, PlimApiError, PlimErrorKind};
 
 const ADMIN_ROLE_NAME: &str = "admin";
 
@@ -26,20 +27,24 @@ pub async fn update_etcd_key<T: Serialize, F>(state: AppState, etcd_name: String
 where
     F: Fn(T) -> Result<String, PlimErrorKind>,
 {
-    let mut etcd_client = match state.etcd_clients_map.get(&etcd_name) {
-        Some(client) => client.clone(),
-        None => return Err(PlimErrorKind::not_found("Etcd client not found").into())
-    };
+    let mut etcd_client = state.etcd.client(&etcd_name).await?;
     match etcd_client.put(key_path, serialize_fn(key_value)?, None).await {
         Ok(_) => Ok(()),
-        Err(e) => Err(PlimErrorKind::internal_server_error(e.to_string()).into())
+        Err(e) => Err(etcd_request_error(&state, &etcd_name, e).await)
+    }
+}
+/// Put `value` only while `compare` holds, false when another write got in between
+pub async fn update_etcd_key_when(state: &AppState, etcd_name: &str, key_path: &str, value: String, compare: Compare) -> Result<bool, PlimApiError> {
+    let mut etcd_client = state.etcd.client(etcd_name).await?;
+    let txn = Txn::new().when([compare]).and_then([TxnOp::put(key_path, value, None)]);
+    match etcd_client.txn(txn).await {
+        Ok(response) => Ok(response.succeeded()),
+        Err(e) => Err(etcd_request_error(state, etcd_name, e).await)
     }
 }
+
 pub async fn read_etcd_key(state: AppState, etcd_name: String, key_path: String) -> Result<String, PlimApiError> {
-    let mut etcd_client = match state.etcd_clients_map.get(&etcd_name) {
-        Some(client) => client.clone(),
-        None => return Err(PlimErrorKind::not_found("Etcd client not found").into())
-    };
+    let mut etcd_client = state.etcd.client(&etcd_name).await?;
     match etcd_client.get(key_path.clone(), None).await {
         Ok(resp) => {
             let kv = resp.kvs().first().ok_or_else(|| PlimErrorKind::not_found(format!("Key {} not found", key_path)))?;
@@ -47,7 +52,7 @@ pub async fn read_etcd_key(state: AppState, etcd_name: String, key_path: String)
             trace!("read_etcd_key: {:?}", value);
             Ok(value)
         },
-        Err(e) => Err(PlimErrorKind::internal_server_error(e.to_string()).into())
+        Err(e) => Err(etcd_request_error(&state, &etcd_name, e).await)
     }
 }
 
@@ -71,8 +76,8 @@ pub async fn get_view_etcd_key(Extension(claims): Extension<Claims>, State(state
 pub async fn get_etcd_inventory_data_source_list(claims: Claims, state: AppState) -> Result<Vec<AnsibleEtcdBackend>, PlimApiError> {
     let available_plans = get_available_plans(claims, state).await?;
     let etcd_backend_inventory_list: Vec<AnsibleEtcdBackend> = available_plans.values().
-    flat_map(|plan| plan.ansible.clone()).filter_map(|ansible| match ansible.backend_inventory {
-        AnsibleBackendType::Etcd(etcd) => Some(etcd),
+    flat_map(|plan| plan.ansible.as_ref()).flat_map(|ansible| ansible.inventory_backends()).filter_map(|backend| match backend {
+        AnsibleBackendType::Etcd(etcd) => Some(etcd.clone()),
         _ => None,
     }).collect();
     Ok(etcd_backend_inventory_list)
@@ -80,18 +85,74 @@ pub async fn get_etcd_inventory_data_source_list(claims: Claims, state: AppState
 
 pub async fn set_inventory_etcd_key(Extension(claims): Extension<Claims>, State(state): State<AppState>, Json(request): Json<EtcdSetInventoryRequest> ) -> Result<impl IntoResponse, PlimApiError> {
     if check_etcd_key_value_is_available_in_inventories(claims, state.clone(), request.etcd_name.clone(), request.key_path.clone()).await? {
-        let decoded_bytes = BASE64_STANDARD.decode(request.key_value.clone()).map_err(|e| PlimErrorKind::internal_server_error(e.to_string()))?;
-        let key_value = String::from_utf8(decoded_bytes).map_err(|e| PlimErrorKind::internal_server_error(e.to_string()))?;
-        let inventory = AnsibleInventoryParserLocal::parse_yaml(&key_value).map_err(|e| PlimErrorKind::internal_server_error("Failed to parse yaml inventory"))?;
+        let key_value = decode_inventory_key_value(&request.key_value)?;
+        let format = InventoryFormat::from_etcd_key(&request.key_path);
+        let diagnostics = validate_inventory(&key_value, format);
+        if has_errors(&diagnostics) {
+            return Err(PlimApiError::from(PlimErrorKind::validation(format!("Inventory {} is not valid", request.key_path)))
+                .with_details(json!({ "diagnostics": diagnostics })));
+        }
+        let inventory = AnsibleInventoryParserLocal::parse(&key_value, format).map_err(|_| PlimErrorKind::validation("Failed to parse inventory"))?;
         trace!("inventory: {:?}", inventory);
-        let to_yaml = |x: Inventory| serde_yaml::to_string(&x).map_err(|e| PlimErrorKind::internal_server_error(e.to_string()));
-        update_etcd_key(state, request.etcd_name.clone(), request.key_path.clone(), inventory.clone(), to_yaml).await?;
-        Ok(Json(json!(inventory)))
+        // a broken stored value should not block fixing it, the diff then shows everything as added.
+        // The put only goes through while the key is still the version the diff is made against
+        let (previous, unchanged) = match read_etcd_inventory(&state, &request.etcd_name, &request.key_path, InventoryRevision::Latest).await? {
+            Some((content, mod_revision)) => (
+                parse_etcd_inventory(&content, &request.key_path).unwrap_or_else(|_| {
+                    warn!("Stored inventory {} can't be parsed, diff is made against an empty inventory", request.key_path);
+                    Inventory::default()
+                }),
+                Compare::mod_revision(request.key_path.as_str(), CompareOp::Equal, mod_revision),
+            ),
+            None => (Inventory::default(), Compare::version(request.key_path.as_str(), CompareOp::Equal, 0)),
+        };
+        let diff = InventoryDiff::between(&previous, &inventory);
+        let value = match format {
+            InventoryFormat::Ini => render(&inventory, format).map_err(|e| PlimErrorKind::internal_server_error(e.to_string()))?,
+            _ => serde_yaml::to_string(&inventory).map_err(|e| PlimErrorKind::internal_server_error(e.to_string()))?,
+        };
+        if !update_etcd_key_when(&state, &request.etcd_name, &request.key_path, value, unchanged).await? {
+            return Err(PlimErrorKind::conflict(format!("Inventory {} was changed by another request, try again", request.key_path)).into());
+        }
+        Ok(Json(json!({ "inventory": inventory, "diff": diff })))
     } else {
         Err(PlimErrorKind::not_found(format!("Etcd key value is not available for key {} and path {}", request.etcd_name, request.key_path)).into())
     }
 }
 
+/// Dry run of `set_inventory_etcd_key`, reports diagnostics without writing anything
+pub async fn validate_inventory_etcd_key(Extension(claims): Extension<Claims>, State(state): State<AppState>, Json(request): Json<EtcdSetInventoryRequest> ) -> Result<impl IntoResponse, PlimApiError> {
+    if check_etcd_key_value_is_available_in_inventories(claims, state.clone(), request.etcd_name.clone(), request.key_path.clone()).await? {
+        let key_value = decode_inventory_key_value(&request.key_value)?;
+        let diagnostics = validate_inventory(&key_value, InventoryFormat::from_etcd_key(&request.key_path));
+        Ok(Json(json!({ "valid": !has_errors(&diagnostics), "diagnostics": diagnostics })))
+    } else {
+        Err(PlimErrorKind::not_found(format!("Etcd key value is not available for key {} and path {}", request.etcd_name, request.key_path)).into())
+    }
+}
+
+/// Semantic diff of an etcd inventory (or a draft of it) against another version
+pub async fn diff_inventory_etcd_key(Extension(claims): Extension<Claims>, State(state): State<AppState>, Json(request): Json<EtcdInventoryDiffRequest> ) -> Result<impl IntoResponse, PlimApiError> {
+    if !check_etcd_key_value_is_available_in_inventories(claims.clone(), state.clone(), request.etcd_name.clone(), request.key_path.clone()).await? {
+        return Err(PlimErrorKind::not_found(format!("Etcd key value is not available for key {} and path {}", request.etcd_name, request.key_path)).into());
+    }
+    let inventory = match &request.draft {
+        Some(draft) => parse_etcd_inventory(&decode_inventory_key_value(draft)?, &request.key_path)?,
+        None => match read_etcd_inventory(&state, &request.etcd_name, &request.key_path, InventoryRevision::Latest).await? {
+            Some((content, _)) => parse_etcd_inventory(&content, &request.key_path)?,
+            None => return Err(PlimErrorKind::not_found(format!("Key {} not found", request.key_path)).into()),
+        },
+    };
+    // a draft is compared with what is stored now, a stored value with what it replaced
+    let base = request.base.clone().unwrap_or(match request.draft {
+        Some(_) => InventoryDiffBase::Current,
+        None => InventoryDiffBase::Previous,
+    });
+    let base_inventory = load_inventory_diff_base(&state, claims, &request.etcd_name, &request.key_path, &base).await?;
+    let diff = InventoryDiff::between(&base_inventory, &inventory);
+    Ok(Json(json!({ "changed": !diff.is_empty(), "diff": diff })))
+}
+
 pub async fn get_inventory_etcd_key(Extension(claims): Extension<Claims>, State(state): State<AppState>, Json(request): Json<EtcdGetViewRequest> ) -> Result<impl IntoResponse, PlimApiError> {
     if check_etcd_key_value_is_available_in_inventories(claims, state.clone(), request.etcd_name.clone(), request.key_path.clone()).await? {
         let value = read_etcd_key(state, request.etcd_name.clone(), request.key_path.clone()).await?;
@@ -104,6 +165,117 @@ pub async fn get_inventory_etcd_key(Extension(claims): Extension<Claims>, State(
 
 // not http api methods below
 
+/// Client of the etcd a plans or users prefix lives on
+pub async fn etcd_config_client(state: &AppState, etcd_config: &EtcdConfig) -> Result<etcd_client::Client, PlimApiError> {
+    Ok(state.etcd.client(&etcd_config.etcd_name).await?)
+}
+
+/// Await an etcd request, a failure drops the client like `etcd_request_error`
+pub async fn etcd_request<T>(state: &AppState, etcd_name: &str, request: impl Future<Output = Result<T, etcd_client::Error>>) -> Result<T, PlimApiError> {
+    match request.await {
+        Ok(response) => Ok(response),
+        Err(e) => Err(etcd_request_error(state, etcd_name, e).await),
+    }
+}
+
+/// A failed request makes the next one reconnect
+pub async fn etcd_request_error(state: &AppState, etcd_name: &str, error: etcd_client::Error) -> PlimApiError {
+    state.etcd.report_failure(etcd_name, &error).await;
+    PlimErrorKind::internal_server_error(error.to_string()).into()
+}
+
+/// Full etcd key of `key` below the prefix of `etcd_config`
+pub fn etcd_config_key(etcd_config: &EtcdConfig, key: &str) -> Result<String, PlimApiError> {
+    let key = key.trim_matches('/');
+    if key.is_empty() {
+        return Err(PlimErrorKind::validation("Key is empty").into());
+    }
+    Ok(format!("{}{}", etcd_config.key_prefix(), key))
+}
+
+fn decode_inventory_key_value(key_value: &str) -> Result<String, PlimApiError> {
+    let decoded_bytes = BASE64_STANDARD.decode(key_value).map_err(|e| PlimErrorKind::validation(e.to_string()))?;
+    Ok(String::from_utf8(decoded_bytes).map_err(|e| PlimErrorKind::validation(e.to_string()))?)
+}
+
+fn parse_etcd_inventory(content: &str, key_path: &str) -> Result<Inventory, PlimApiError> {
+    AnsibleInventoryParserLocal::parse(content, InventoryFormat::from_etcd_key(key_path)).map_err(inventory_load_error)
+}
+
+fn inventory_load_error((status, Json(body)): (StatusCode, Json<serde_json::Value>)) -> PlimApiError {
+    let message = body["error"].as_str().unwrap_or("Failed to load inventory").to_string();
+    PlimApiError::new(anyhow::Error::msg(message), status)
+}
+
+/// Revision of an inventory key to read, older ones are gone once etcd compacts them
+#[derive(Debug, Clone, Copy)]
+enum InventoryRevision {
+    Latest,
+    /// the revision before the one the key was last changed at
+    Previous(i64),
+    At(i64),
+}
+
+impl InventoryRevision {
+    fn number(self) -> Option<i64> {
+        match self {
+            InventoryRevision::Latest => None,
+            InventoryRevision::Previous(revision) | InventoryRevision::At(revision) => Some(revision),
+        }
+    }
+
+    fn compacted_error(self, key_path: &str) -> PlimApiError {
+        let message = match self {
+            InventoryRevision::Previous(revision) => format!("Previous revision {} of {} is compacted", revision, key_path),
+            _ => format!("Revision {} of {} is compacted", self.number().unwrap_or_default(), key_path),
+        };
+        PlimApiError::new(anyhow::Error::msg(message), StatusCode::GONE)
+    }
+}
+
+/// etcd answers a read below the compacted revision with this message
+const ETCD_COMPACTED_MESSAGE: &str = "required revision has been compacted";
+
+fn is_revision_compacted(error: &etcd_client::Error) -> bool {
+    matches!(error, etcd_client::Error::GRpcStatus(status) if status.message().contains(ETCD_COMPACTED_MESSAGE))
+}
+
+/// Read an inventory key, optionally at an older revision, returns the content with its mod revision
+async fn read_etcd_inventory(state: &AppState, etcd_name: &str, key_path: &str, revision: InventoryRevision) -> Result<Option<(String, i64)>, PlimApiError> {
+    let mut etcd_client = state.etcd.client(etcd_name).await?;
+    let options = revision.number().map(|revision| GetOptions::new().with_revision(revision));
+    let resp = match etcd_client.get(key_path, options).await {
+        Ok(resp) => resp,
+        // a compacted revision is a bad request, not a failing etcd
+        Err(e) if is_revision_compacted(&e) => return Err(revision.compacted_error(key_path)),
+        Err(e) => return Err(etcd_request_error(state, etcd_name, e).await),
+    };
+    Ok(resp.kvs().first().map(|kv| (String::from_utf8_lossy(kv.value()).to_string(), kv.mod_revision())))
+}
+
+async fn load_inventory_diff_base(state: &AppState, claims: Claims, etcd_name: &str, key_path: &str, base: &InventoryDiffBase) -> Result<Inventory, PlimApiError> {
+    let content = match base {
+        InventoryDiffBase::Current => read_etcd_inventory(state, etcd_name, key_path, InventoryRevision::Latest).await?,
+        InventoryDiffBase::Previous => match read_etcd_inventory(state, etcd_name, key_path, InventoryRevision::Latest).await? {
+            // the key did not exist before its first write, so that version is empty
+            Some((_, mod_revision)) => read_etcd_inventory(state, etcd_name, key_path, InventoryRevision::Previous(mod_revision - 1)).await?,
+            None => None,
+        },
+        InventoryDiffBase::Revision(revision) => Some(read_etcd_inventory(state, etcd_name, key_path, InventoryRevision::At(*revision)).await?
+            .ok_or_else(|| PlimErrorKind::not_found(format!("Key {} not found at revision {}", key_path, revision)))?),
+        InventoryDiffBase::Plan(plan_name) => {
+            let available_plans = get_available_plans(claims, state.clone()).await?;
+            let plan = available_plans.get(plan_name).ok_or_else(|| PlimErrorKind::not_found(format!("Plan {} not found", plan_name)))?;
+            let ansible = plan.ansible.as_ref().ok_or_else(|| PlimErrorKind::validation(format!("Plan {} has no ansible inventory", plan_name)))?;
+            return load_plan_inventory(state, PlanScope::new(plan_name, plan), ansible).await.map_err(inventory_load_error);
+        }
+    };
+    match content {
+        Some((content, _)) => parse_etcd_inventory(&content, key_path),
+        None => Ok(Inventory::default()),
+    }
+}
+
 pub async fn get_etcd_view_data_source_list(claims: Claims, state: AppState) -> Result<Vec<DataSource>, PlimApiError> {
     let available_plans = get_available_plans(claims, state).await?;
     let data_source_is_exist = PlimPlanViewType::data_source_is_exist(
@@ -124,9 +296,9 @@ pub async fn get_etcd_view_data_source_list(claims: Claims, state: AppState) ->
 
 pub async fn get_available_plans(claims: Claims, state: AppState) -> Result<HashMap<String, PlimPlan>, PlimApiError> {
     let available_plans = if claims.roles.contains(&ADMIN_ROLE_NAME.into()) {
-        state.config.plans.clone()
+        state.config.load().plans.clone()
     } else {
-        state.config.filter_plans_by_groups(&claims.roles)
+        state.config.load().filter_plans_by_groups(&claims.roles)
     };
     Ok(available_plans)
 }
@@ -157,6 +329,28 @@ pub struct EtcdSetInventoryRequest {
     pub key_path: String,
     pub key_value: String,
 }
+/// Version of an etcd inventory to compare with
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum InventoryDiffBase {
+    /// value stored now
+    Current,
+    /// value the current one replaced
+    Previous,
+    Revision(i64),
+    /// inventory backend of a plan, e.g. the GitLab copy of the same inventory
+    Plan(String),
+}
+
+#[derive(Deserialize)]
+pub struct EtcdInventoryDiffRequest {
+    pub etcd_name: String,
+    pub key_path: String,
+    pub base: Option<InventoryDiffBase>,
+    /// base64 encoded inventory compared instead of the stored value
+    pub draft: Option<String>,
+}
+
 #[derive(Deserialize)]
 pub struct EtcdGetInventoryRequest {
     pub etcd_name: String,
@@ -4,7 +4,9 @@ use axum::{http::HeaderMap, response::IntoResponse, Json};
 use derive_merge_struct::Merge;
 use log::trace;
 use super::handlers::*;
+use super::ansible::validate_limit_hosts;
 use crate::{config::{AnsibleConfig, AnyValue, ExecuteApiType, GetPlanViewData, PlanType, PlimPlanViewType, WebhookType}, http_client::gitlab::responses::GitLabBranchesArgs};
+use crate::secret_registry::PlanScope;
 
 const TOKEN_HEADER_NAME: &str = "TOKEN";
 
@@ -14,12 +16,14 @@ pub async fn trigger_gitlab_pipeline_by_webhook(
     State(state): State<AppState>,
     Json(wh_request_data): Json<WebhookPipelineRequest>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-    let plan = match state.config.plans.get(&plan_name) {
+    let plan = match state.config.load().plans.get(&plan_name) {
         Some(plan) => plan.clone(),
         None => {
             return Ok((StatusCode::NOT_FOUND, Json(json!({"error": "Plan not found"}))));
         }
     };
+    // the webhook handler moves parts of the plan, so the scope borrows only its groups
+    let scope = PlanScope { name: &plan_name, groups: &plan.groups };
     let wh = match plan.webhooks {
         Some(wh) => wh,
         None => {
@@ -40,9 +44,9 @@ pub async fn trigger_gitlab_pipeline_by_webhook(
         },
         None => return Ok((StatusCode::FORBIDDEN, Json(json!({"error": "Webhook token is missing"})))),
     };
-    match state.gitlab_tokens.get(&webhook.trigger_token).await {
+    match state.gitlab_tokens.get_for_plan(&webhook.trigger_token, scope).await {
         Ok(token) => {
-            if token != header_token || state.config.plim.webhook_token_length != header_token.len() as u8 {
+            if token != header_token || state.config.load().plim.webhook_token_length != header_token.len() as u8 {
                 return Ok((StatusCode::FORBIDDEN, Json(json!({"error": "Webhook token is not valid or short"}))));
             }
         }
@@ -50,13 +54,9 @@ pub async fn trigger_gitlab_pipeline_by_webhook(
             return Ok((StatusCode::NOT_FOUND, Json(json!({"error": "Webhook token is missing"}))));
         }
     };
-    let gitlab_token = match state.gitlab_tokens.get(&plan.gitlab.token_var).await {
+    let gitlab_token = match state.gitlab_tokens.get_for_plan(&plan.gitlab.token_var, scope).await {
         Ok(token) => token,
-        Err(_) => {
-            let error_message = format!("Your token {} is missing", &plan.gitlab.token_var);
-            error!("{}", error_message);
-            return Ok((StatusCode::NOT_FOUND, Json(json!({"error": error_message}))));
-        }
+        Err(e) => return Ok((StatusCode::NOT_FOUND, Json(json!({"error": e.to_string()})))),
     };
 
     let wh_ansible = webhook.ansible.clone();
@@ -96,6 +96,9 @@ pub async fn trigger_gitlab_pipeline_by_webhook(
 
     trace!("Views data: {:?}", views_data);
     trace!("Ansible data: {:?}", ansible_data);
+    if let Some(ansible_data) = &ansible_data {
+        validate_limit_hosts(&state, scope, ansible_data, ansible_data).await?;
+    }
 
     let default_pipeline_data = TriggerPipelineRequest::new(
         Some(views_data),
@@ -186,19 +189,15 @@ pub async fn trigger_gitlab_pipeline(
     State(state): State<AppState>,
     Json(pipeline_data): Json<TriggerPipelineRequest>,
 ) -> Result<(StatusCode, Json<serde_json::Value>), (StatusCode, Json<serde_json::Value>)> {
-    let plan = match state.config.plans.get(&plan_name) {
+    let plan = match state.config.load().plans.get(&plan_name) {
         Some(plan) => plan.clone(),
         None => {
             return Ok((StatusCode::NOT_FOUND, Json(json!({"error": "Plan not found"}))));
         }
     };
-    let gitlab_token = match state.gitlab_tokens.get(&plan.gitlab.token_var).await {
+    let gitlab_token = match state.gitlab_tokens.get_for_plan(&plan.gitlab.token_var, PlanScope::new(&plan_name, &plan)).await {
         Ok(token) => token,
-        Err(_) => {
-            let error_message = format!("Your token {} is missing", &plan.gitlab.token_var);
-            error!("{}", error_message);
-            return Ok((StatusCode::NOT_FOUND, Json(json!({"error": error_message}))));
-        }
+        Err(e) => return Ok((StatusCode::NOT_FOUND, Json(json!({"error": e.to_string()})))),
     };
 
     let json_data = match pipeline_data.json_data {
@@ -218,6 +217,9 @@ pub async fn trigger_gitlab_pipeline(
             }
         }
     };
+    if let Some(plan_ansible) = &plan.ansible {
+        validate_limit_hosts(&state, PlanScope::new(&plan_name, &plan), plan_ansible, &ansible_data).await?;
+    }
     let trigger_pipeline_payload = match plan.type_name {
         PlanType::GitlabAnsibleBase64 => {
             let json_data_key = match plan.gitlab.json_data_key {
@@ -417,11 +419,18 @@ fn make_native_payload_for_create_pipeline_api(
     })
 }
 
+/// Drop every cached gitlab repository file, admin only
+pub async fn flush_gitlab_file_cache(State(state): State<AppState>) -> impl IntoResponse {
+    let flushed = state.gitlab_file_cache.flush().await;
+    info!("Flushed {} cached gitlab files", flushed);
+    (StatusCode::OK, Json(json!({"flushed": flushed})))
+}
+
 pub async fn get_gitlab_refs(
     State(state): State<AppState>,
     Path(plan_name): Path<String>,
 ) -> impl IntoResponse {
-    let plan = match state.config.plans.get(&plan_name) {
+    let plan = match state.config.load().plans.get(&plan_name) {
         Some(plan) => plan.clone(),
         None => {
             return (
@@ -437,7 +446,7 @@ pub async fn get_gitlab_refs(
         );
     }
     let mut refs = Vec::new();
-    match state.gitlab_tokens.get(&plan.gitlab.token_var).await {
+    match state.gitlab_tokens.get_for_plan(&plan.gitlab.token_var, PlanScope::new(&plan_name, &plan)).await {
         Ok(gitlab_token) => {
             if plan.gitlab.ref_select.branch_enabled {
                 let branches = state
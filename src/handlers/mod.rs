@@ -5,6 +5,7 @@ use serde::Deserialize;
 use serde::Serialize;
 use base64::prelude::*;
 use crate::AppState;
+use crate::etcd_manager::EtcdManagerError;
 use crate::handlers;
 use serde_json::{json, Value};
 use log::{error, info};
@@ -20,6 +21,8 @@ pub mod ansible;
 pub mod authentication;
 pub mod admin_tools;
 pub mod etcd;
+pub mod plan_admin;
+pub mod user_admin;
 
 
 #[derive(Debug, Error)]
@@ -30,6 +33,8 @@ pub enum PlimErrorKind {
     Unauthorized(String),
     #[error("Validation error: {0}")]
     Validation(String),
+    #[error("Conflict: {0}")]
+    Conflict(String),
     #[error("Internal server error: {0}")]
     InternalServerError(String),
 }
@@ -44,6 +49,9 @@ impl PlimErrorKind {
     pub fn validation(msg: impl Into<String>) -> PlimErrorKind {
         PlimErrorKind::Validation(msg.into())
     }
+    pub fn conflict(msg: impl Into<String>) -> PlimErrorKind {
+        PlimErrorKind::Conflict(msg.into())
+    }
     pub fn internal_server_error(msg: impl Into<String>) -> PlimErrorKind {
         PlimErrorKind::InternalServerError(msg.into())
     }
@@ -56,14 +64,26 @@ impl From<PlimErrorKind> for PlimApiError {
             PlimErrorKind::NotFound(_) => PlimApiError::new(kind, StatusCode::NOT_FOUND),
             PlimErrorKind::Unauthorized(_) => PlimApiError::new(kind, StatusCode::UNAUTHORIZED),
             PlimErrorKind::Validation(_) => PlimApiError::new(kind, StatusCode::BAD_REQUEST),
+            PlimErrorKind::Conflict(_) => PlimApiError::new(kind, StatusCode::CONFLICT),
         }
     }
 }
 
+impl From<EtcdManagerError> for PlimApiError {
+    fn from(error: EtcdManagerError) -> Self {
+        let status = match error {
+            EtcdManagerError::UnknownCluster(_) => StatusCode::NOT_FOUND,
+            EtcdManagerError::Unavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        PlimApiError::new(error, status)
+    }
+}
+
 #[derive(Debug)]
 pub struct PlimApiError {
     inner: Error,
     status: StatusCode,
+    details: Option<Value>,
 }
 
 impl PlimApiError {
@@ -71,18 +91,25 @@ impl PlimApiError {
         Self {
             inner: error.into(),
             status,
+            details: None,
         }
     }
+    /// Attach structured data to the error response, returned as `details`
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
 }
 
 impl IntoResponse for PlimApiError {
     fn into_response(self) -> Response {
-        (
-            self.status,
-            Json(json!({
-                "error": format!("{}", self.inner),
-            })),
-        ).into_response()
+        let mut body = json!({
+            "error": format!("{}", self.inner),
+        });
+        if let Some(details) = self.details {
+            body["details"] = details;
+        }
+        (self.status, Json(body)).into_response()
     }
 }
 
@@ -0,0 +1,114 @@
+use axum::{extract::{Path, State}, response::IntoResponse, Json};
+use etcd_client::{Client, Compare, CompareOp, Txn, TxnOp};
+use log::info;
+use reqwest::StatusCode;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{config::{etcd_config_name, get_etcd_prefix, EtcdConfig, EtcdConfigLoader, PlimPlan}, config_sources::ConfigSource, config_validation::{has_errors, validate_plan, ConfigIssue}, state::AppState};
+
+use super::{etcd::{etcd_config_client, etcd_config_key, etcd_request}, PlimApiError, PlimErrorKind};
+
+/// Plan stored under the `etcd_configs.plans` prefix, `plan` is the stored yaml as json
+#[derive(Debug, Serialize)]
+struct EtcdPlanEntry {
+    key: String,
+    name: String,
+    plan: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+pub async fn list_etcd_plans(State(state): State<AppState>) -> Result<impl IntoResponse, PlimApiError> {
+    let (etcd_config, mut client) = plans_etcd(&state).await?;
+    let prefix = etcd_config.key_prefix();
+    let (kvs, _) = etcd_request(&state, &etcd_config.etcd_name, get_etcd_prefix(&mut client, &prefix)).await?;
+    let plans: Vec<EtcdPlanEntry> = kvs.iter().map(|kv| {
+        let full_key = String::from_utf8_lossy(kv.key()).to_string();
+        let (plan, error) = match serde_yaml::from_slice::<Value>(kv.value()) {
+            Ok(plan) => (Some(plan), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        EtcdPlanEntry {
+            key: full_key.strip_prefix(&prefix).unwrap_or(&full_key).to_string(),
+            name: etcd_config_name(&etcd_config.etcd_name, &etcd_config.key_prefix_path, &full_key),
+            plan,
+            error,
+        }
+    }).collect();
+    Ok(Json(json!({ "plans": plans })))
+}
+
+pub async fn get_etcd_plan(State(state): State<AppState>, Path(key): Path<String>) -> Result<impl IntoResponse, PlimApiError> {
+    let (etcd_config, mut client) = plans_etcd(&state).await?;
+    let full_key = etcd_config_key(&etcd_config, &key)?;
+    let response = etcd_request(&state, &etcd_config.etcd_name, client.get(full_key.as_str(), None)).await?;
+    let kv = response.kvs().first().ok_or_else(|| PlimErrorKind::not_found(format!("Plan key {} not found", key)))?;
+    let plan: Value = serde_yaml::from_slice(kv.value()).map_err(|e| PlimErrorKind::internal_server_error(e.to_string()))?;
+    let name = etcd_config_name(&etcd_config.etcd_name, &etcd_config.key_prefix_path, &full_key);
+    Ok(Json(json!(EtcdPlanEntry { key, name, plan: Some(plan), error: None })))
+}
+
+/// Store a new plan, 409 when the key already exists
+pub async fn create_etcd_plan(State(state): State<AppState>, Path(key): Path<String>, Json(plan): Json<Value>) -> Result<impl IntoResponse, PlimApiError> {
+    let (name, issues) = write_etcd_plan(&state, &key, plan, CompareOp::Equal).await?;
+    Ok((StatusCode::CREATED, Json(json!({ "key": key, "name": name, "issues": issues }))))
+}
+
+/// Replace a stored plan, 404 when the key doesn't exist
+pub async fn update_etcd_plan(State(state): State<AppState>, Path(key): Path<String>, Json(plan): Json<Value>) -> Result<impl IntoResponse, PlimApiError> {
+    let (name, issues) = write_etcd_plan(&state, &key, plan, CompareOp::Greater).await?;
+    Ok((StatusCode::OK, Json(json!({ "key": key, "name": name, "issues": issues }))))
+}
+
+pub async fn delete_etcd_plan(State(state): State<AppState>, Path(key): Path<String>) -> Result<impl IntoResponse, PlimApiError> {
+    let (etcd_config, mut client) = plans_etcd(&state).await?;
+    let full_key = etcd_config_key(&etcd_config, &key)?;
+    let response = etcd_request(&state, &etcd_config.etcd_name, client.delete(full_key.as_str(), None)).await?;
+    if response.deleted() == 0 {
+        return Err(PlimErrorKind::not_found(format!("Plan key {} not found", key)).into());
+    }
+    let name = etcd_config_name(&etcd_config.etcd_name, &etcd_config.key_prefix_path, &full_key);
+    state.config.update(|conf| PlimPlan::remove_from_config(conf, &name)).await;
+    info!("Etcd plan {} deleted", name);
+    Ok(Json(json!({ "key": key, "name": name, "deleted": true })))
+}
+
+// not http api methods below
+
+async fn plans_etcd(state: &AppState) -> Result<(EtcdConfig, Client), PlimApiError> {
+    let etcd_config = state.config.load().etcd_configs.plans.clone();
+    let client = etcd_config_client(state, &etcd_config).await?;
+    Ok((etcd_config, client))
+}
+
+/// Validate, store as yaml and apply to the running config, `version_op` against 0 tells create from update
+async fn write_etcd_plan(state: &AppState, key: &str, plan: Value, version_op: CompareOp) -> Result<(String, Vec<ConfigIssue>), PlimApiError> {
+    let (etcd_config, mut client) = plans_etcd(state).await?;
+    let full_key = etcd_config_key(&etcd_config, key)?;
+    let name = etcd_config_name(&etcd_config.etcd_name, &etcd_config.key_prefix_path, &full_key);
+    // the yaml keeps only what the admin sent, the parsed plan fills every default
+    let yaml_value = serde_yaml::to_value(&plan).map_err(|e| PlimErrorKind::validation(e.to_string()))?;
+    let (parsed, issues) = validate_plan(&state.config.load(), &name, yaml_value.clone(), &state.gitlab_tokens);
+    let parsed = match parsed {
+        Some(parsed) if !has_errors(&issues) => parsed,
+        _ => return Err(PlimApiError::from(PlimErrorKind::validation(format!("Plan {} is not valid", name)))
+            .with_details(json!({ "issues": issues }))),
+    };
+    let yaml = serde_yaml::to_string(&yaml_value).map_err(|e| PlimErrorKind::internal_server_error(e.to_string()))?;
+    let txn = Txn::new()
+        .when([Compare::version(full_key.as_str(), version_op, 0)])
+        .and_then([TxnOp::put(full_key.as_str(), yaml, None)]);
+    let response = etcd_request(state, &etcd_config.etcd_name, client.txn(txn)).await?;
+    if !response.succeeded() {
+        return Err(match version_op {
+            CompareOp::Equal => PlimErrorKind::conflict(format!("Plan key {} already exists", key)),
+            _ => PlimErrorKind::not_found(format!("Plan key {} not found", key)),
+        }.into());
+    }
+    // the etcd watch applies the same change, this makes it visible before the event arrives
+    let source = ConfigSource::Etcd { etcd_name: etcd_config.etcd_name.clone(), key: full_key, revision: response.header().map(|header| header.revision()).unwrap_or_default() };
+    state.config.update(|conf| parsed.load_from_etcd(conf, name.clone(), source.clone())).await;
+    info!("Etcd plan {} stored", name);
+    Ok((name, issues))
+}
@@ -1,6 +1,7 @@
 use anyhow::Error;
 use log::trace;
-use crate::{config::{AnyValue, DataSource, DataSourceType, GetPlanViewData, PlimPlanViewType}, jwt::Claims};
+use crate::{config::{config_schema, AnyValue, DataSource, SchemaKind, DataSourceType, GetPlanViewData, PlimPlan, PlimPlanViewType}, jwt::Claims};
+use super::ansible::load_plan_playbook;
 
 const ADMIN_ROLE_NAME: &str = "admin";
 
@@ -11,10 +12,10 @@ pub async fn get_all_plans(
         return (StatusCode::FORBIDDEN, Json(json!({"error": "Forbidden"}))).into_response();
     }
     if claims.roles.contains(&ADMIN_ROLE_NAME.into()) {
-        let all_plans = state.config.plans.clone();
+        let all_plans = state.config.load().plans.clone();
         return json_response(all_plans).into_response();
     }
-    let available_plans = state.config.filter_plans_by_groups(&claims.roles);
+    let available_plans = state.config.load().filter_plans_by_groups(&claims.roles);
     trace!("Plans: {:?}", available_plans.keys());
     json_response(available_plans).into_response()
 }
@@ -25,9 +26,9 @@ pub async fn get_plan(Path(plan_name): Path<String>,
     State(state): State<AppState>,
     Extension(claims): Extension<Claims>) -> impl IntoResponse {
     let available_plans = if claims.roles.contains(&ADMIN_ROLE_NAME.into()) {
-        state.config.plans.clone()
+        state.config.load().plans.clone()
     } else {
-        state.config.filter_plans_by_groups(&claims.roles)
+        state.config.load().filter_plans_by_groups(&claims.roles)
     };
     let mut plan = match available_plans.get(&plan_name) {
         Some(plan) => plan.clone(),
@@ -45,15 +46,15 @@ pub async fn get_plan(Path(plan_name): Path<String>,
             PlimPlanViewType::Multi(mut view) => {
                 if let Some(ref data_source) = view.data_source {
                     match data_source.source_type {
-                        DataSourceType::Etcd => {
-                            let etcd_data = get_etcd_data(&state, data_source).await;
+                        DataSourceType::Etcd | DataSourceType::AnsiblePlaybookTags => {
+                            let etcd_data = get_data_source_values(&state, &plan_name, &plan, data_source).await;
                             match etcd_data {
                                 Ok(data) => {
                                     view.data = data;
                                     plan_views_with_etcd_data.push(PlimPlanViewType::Multi(view));
                                 }
                                 Err(e) => {
-                                    error!("Error getting etcd data: {}", e);
+                                    error!("Error getting data source values: {}", e);
                                 }
                             }
                         }
@@ -68,15 +69,15 @@ pub async fn get_plan(Path(plan_name): Path<String>,
             PlimPlanViewType::One(mut view) => {
                 if let Some(ref data_source) = view.data_source {
                     match data_source.source_type {
-                        DataSourceType::Etcd => {
-                            let etcd_data = get_etcd_data(&state, data_source).await;
+                        DataSourceType::Etcd | DataSourceType::AnsiblePlaybookTags => {
+                            let etcd_data = get_data_source_values(&state, &plan_name, &plan, data_source).await;
                             match etcd_data {
                                 Ok(data) => {
-                                    view.value = Some(data[0].clone());
+                                    view.value = data.first().cloned();
                                     plan_views_with_etcd_data.push(PlimPlanViewType::One(view));
                                 }
                                 Err(e) => {
-                                    error!("Error getting etcd data: {}", e);
+                                    error!("Error getting data source values: {}", e);
                                 }
                             }
                         }
@@ -89,15 +90,15 @@ pub async fn get_plan(Path(plan_name): Path<String>,
             PlimPlanViewType::CheckboxList(mut view) => {
                 if let Some(ref data_source) = view.data_source {
                     match data_source.source_type {
-                        DataSourceType::Etcd => {
-                            let etcd_data = get_etcd_data(&state, data_source).await;
+                        DataSourceType::Etcd | DataSourceType::AnsiblePlaybookTags => {
+                            let etcd_data = get_data_source_values(&state, &plan_name, &plan, data_source).await;
                             match etcd_data {
                                 Ok(data) => {
                                     view.values = data;
                                     plan_views_with_etcd_data.push(PlimPlanViewType::CheckboxList(view));
                                 }
                                 Err(e) => {
-                                    error!("Error getting etcd data: {}", e);
+                                    error!("Error getting data source values: {}", e);
                                 }
                             }
                         }
@@ -110,15 +111,15 @@ pub async fn get_plan(Path(plan_name): Path<String>,
             PlimPlanViewType::Dynamic(mut view) => {
                 if let Some(ref data_source) = view.data_source {
                     match data_source.source_type {
-                        DataSourceType::Etcd => {
-                            let etcd_data = get_etcd_data(&state, data_source).await;
+                        DataSourceType::Etcd | DataSourceType::AnsiblePlaybookTags => {
+                            let etcd_data = get_data_source_values(&state, &plan_name, &plan, data_source).await;
                             match etcd_data {
                                 Ok(data) => {
                                     view.data = data;
                                     plan_views_with_etcd_data.push(PlimPlanViewType::Dynamic(view));
                                 }
                                 Err(e) => {
-                                    error!("Error getting etcd data: {}", e);
+                                    error!("Error getting data source values: {}", e);
                                 }
                             }
                         }
@@ -135,14 +136,30 @@ pub async fn get_plan(Path(plan_name): Path<String>,
     json_response(plan)
 }
 
+/// Values of a view data source, playbook tags are read from the plan playbook on every request
+async fn get_data_source_values(state: &AppState, plan_name: &str, plan: &PlimPlan, data_source: &DataSource) -> Result<Vec<AnyValue>, Error> {
+    match data_source.source_type {
+        DataSourceType::Etcd => get_etcd_data(state, data_source).await,
+        DataSourceType::AnsiblePlaybookTags => {
+            let playbook = load_plan_playbook(state, plan_name, plan, None).await
+                .map_err(|(_, Json(e))| anyhow::anyhow!(e["error"].as_str().unwrap_or("Failed to load playbook").to_string()))?;
+            Ok(playbook.tags.into_iter().map(AnyValue::String).collect())
+        }
+        DataSourceType::StaticConfig => Ok(Vec::new()),
+    }
+}
+
 async fn get_etcd_data(state: &AppState, data_source: &DataSource) -> Result<Vec<AnyValue>, Error> {
     let etcd_name = data_source.etcd_name.clone();
-    let etcd_client = match state.etcd_clients_map.get(&etcd_name) {
-        Some(etcd_client) => etcd_client,
-        None => return Err(anyhow::anyhow!("Etcd client not found")),
-    };
+    let mut etcd_client = state.etcd.client(&etcd_name).await?;
     let key_path = data_source.key_path.clone();
-    let etcd_data = etcd_client.clone().get(key_path.clone(), None).await?;
+    let etcd_data = match etcd_client.get(key_path.clone(), None).await {
+        Ok(etcd_data) => etcd_data,
+        Err(e) => {
+            state.etcd.report_failure(&etcd_name, &e).await;
+            return Err(e.into());
+        }
+    };
     let etcd_data = match etcd_data.kvs().first() {
         Some(etcd_data) => etcd_data,
         None => return Err(anyhow::anyhow!("Key not found")),
@@ -171,3 +188,7 @@ async fn get_etcd_data(state: &AppState, data_source: &DataSource) -> Result<Vec
 //     is_exist
 // }
 
+/// JSON schema for editors and the plan editor, `kind` is config, plan, plan-file or user
+pub async fn get_config_schema(Path(kind): Path<SchemaKind>) -> impl IntoResponse {
+    Json(config_schema(kind))
+}
@@ -0,0 +1,144 @@
+use axum::{extract::{Path, State}, response::IntoResponse, Json};
+use etcd_client::{Client, Compare, CompareOp, Txn, TxnOp, TxnResponse};
+use log::info;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{config::{etcd_config_name, EtcdConfig, EtcdConfigLoader, PlimUser}, config_sources::ConfigSource, state::AppState};
+
+use super::{admin_tools::hash_password, etcd::{etcd_config_client, etcd_config_key, etcd_request}, PlimApiError, PlimErrorKind};
+
+#[derive(Deserialize, Debug)]
+pub struct CreateUserRequest {
+    full_name: String,
+    email: String,
+    groups: Vec<String>,
+    password: String,
+    #[serde(default)]
+    disabled: bool,
+}
+
+/// Fields left out keep their stored value
+#[derive(Deserialize, Debug)]
+pub struct UpdateUserRequest {
+    full_name: Option<String>,
+    email: Option<String>,
+    groups: Option<Vec<String>>,
+    disabled: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordRequest {
+    password: String,
+}
+
+/// Store a new user under the `etcd_configs.users` prefix, it can log in as `<etcd_name>_<key>`
+pub async fn create_etcd_user(State(state): State<AppState>, Path(key): Path<String>, Json(request): Json<CreateUserRequest>) -> Result<impl IntoResponse, PlimApiError> {
+    let (etcd_config, mut client) = users_etcd(&state).await?;
+    let full_key = etcd_config_key(&etcd_config, &key)?;
+    let name = etcd_config_name(&etcd_config.etcd_name, &etcd_config.key_prefix_path, &full_key);
+    if state.config.load().users.contains_key(&name) {
+        return Err(PlimErrorKind::conflict(format!("User {} already exists", name)).into());
+    }
+    let user = PlimUser {
+        full_name: request.full_name,
+        email: request.email,
+        groups: request.groups,
+        hashed_password: hash_new_password(&request.password)?,
+        disabled: request.disabled,
+    };
+    let txn = Txn::new()
+        .when([Compare::version(full_key.as_str(), CompareOp::Equal, 0)])
+        .and_then([TxnOp::put(full_key.as_str(), user_yaml(&user)?, None)]);
+    let response = etcd_request(&state, &etcd_config.etcd_name, client.txn(txn)).await?;
+    if !response.succeeded() {
+        return Err(PlimErrorKind::conflict(format!("User key {} already exists", key)).into());
+    }
+    apply_user(&state, &etcd_config, full_key, &name, &user, &response).await;
+    Ok((StatusCode::CREATED, Json(user_response(&key, &name, &user))))
+}
+
+/// Change profile, groups or the disabled flag of a user stored in etcd
+pub async fn update_etcd_user(State(state): State<AppState>, Path(key): Path<String>, Json(request): Json<UpdateUserRequest>) -> Result<impl IntoResponse, PlimApiError> {
+    let (key, name, user) = modify_etcd_user(&state, key, |user| {
+        if let Some(full_name) = request.full_name {
+            user.full_name = full_name;
+        }
+        if let Some(email) = request.email {
+            user.email = email;
+        }
+        if let Some(groups) = request.groups {
+            user.groups = groups;
+        }
+        if let Some(disabled) = request.disabled {
+            user.disabled = disabled;
+        }
+    }).await?;
+    Ok(Json(user_response(&key, &name, &user)))
+}
+
+pub async fn reset_etcd_user_password(State(state): State<AppState>, Path(key): Path<String>, Json(request): Json<ResetPasswordRequest>) -> Result<impl IntoResponse, PlimApiError> {
+    let hashed_password = hash_new_password(&request.password)?;
+    let (key, name, user) = modify_etcd_user(&state, key, |user| {
+        user.hashed_password = hashed_password;
+    }).await?;
+    Ok(Json(user_response(&key, &name, &user)))
+}
+
+// not http api methods below
+
+async fn users_etcd(state: &AppState) -> Result<(EtcdConfig, Client), PlimApiError> {
+    let etcd_config = state.config.load().etcd_configs.users.clone();
+    let client = etcd_config_client(state, &etcd_config).await?;
+    Ok((etcd_config, client))
+}
+
+fn hash_new_password(password: &str) -> Result<String, PlimApiError> {
+    if password.is_empty() {
+        return Err(PlimErrorKind::validation("Password is empty").into());
+    }
+    Ok(hash_password(password).map_err(|e| PlimErrorKind::internal_server_error(e.to_string()))?)
+}
+
+fn user_yaml(user: &PlimUser) -> Result<String, PlimApiError> {
+    Ok(serde_yaml::to_string(user).map_err(|e| PlimErrorKind::internal_server_error(e.to_string()))?)
+}
+
+/// The password hash is never sent back
+fn user_response(key: &str, name: &str, user: &PlimUser) -> Value {
+    json!({
+        "key": key,
+        "name": name,
+        "user": { "full_name": user.full_name, "email": user.email, "groups": user.groups, "disabled": user.disabled },
+    })
+}
+
+/// The etcd watch applies the same change, this makes it visible before the event arrives
+async fn apply_user(state: &AppState, etcd_config: &EtcdConfig, key: String, name: &str, user: &PlimUser, response: &TxnResponse) {
+    let source = ConfigSource::Etcd { etcd_name: etcd_config.etcd_name.clone(), key, revision: response.header().map(|header| header.revision()).unwrap_or_default() };
+    state.config.update(|conf| user.load_from_etcd(conf, name.to_string(), source.clone())).await;
+    info!("Etcd user {} stored", name);
+}
+
+/// Read, change and write back a stored user, 409 when it was changed in between
+async fn modify_etcd_user(state: &AppState, key: String, change: impl FnOnce(&mut PlimUser)) -> Result<(String, String, PlimUser), PlimApiError> {
+    let (etcd_config, mut client) = users_etcd(state).await?;
+    let full_key = etcd_config_key(&etcd_config, &key)?;
+    let name = etcd_config_name(&etcd_config.etcd_name, &etcd_config.key_prefix_path, &full_key);
+    let response = etcd_request(state, &etcd_config.etcd_name, client.get(full_key.as_str(), None)).await?;
+    // users from the config files can't be changed here
+    let kv = response.kvs().first().ok_or_else(|| PlimErrorKind::not_found(format!("User key {} not found in etcd", key)))?;
+    let mut user: PlimUser = serde_yaml::from_slice(kv.value())
+        .map_err(|e| PlimErrorKind::internal_server_error(format!("Stored user {} can't be parsed: {}", name, e)))?;
+    change(&mut user);
+    let txn = Txn::new()
+        .when([Compare::mod_revision(full_key.as_str(), CompareOp::Equal, kv.mod_revision())])
+        .and_then([TxnOp::put(full_key.as_str(), user_yaml(&user)?, None)]);
+    let response = etcd_request(state, &etcd_config.etcd_name, client.txn(txn)).await?;
+    if !response.succeeded() {
+        return Err(PlimErrorKind::conflict(format!("User {} was changed by another request, try again", name)).into());
+    }
+    apply_user(state, &etcd_config, full_key, &name, &user, &response).await;
+    Ok((key, name, user))
+}
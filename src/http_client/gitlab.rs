@@ -6,6 +6,7 @@ use reqwest::{Client, Method};
 pub mod pipeline;
 pub mod repository;
 pub mod responses;
+pub mod file_cache;
 
 /// Main Gitlab client implementation
 #[derive(Debug)]
@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use log::{trace, warn};
+use tokio::sync::RwLock;
+
+use super::GitlabClient;
+use super::repository::RepositoryError;
+use super::responses::{FileHead, FileResponse};
+
+pub const DEFAULT_FILE_CACHE_TTL_SECS: u64 = 60;
+pub const DEFAULT_FILE_CACHE_MAX_ENTRIES: usize = 512;
+
+/// Files are cached per token variable, a token that can't read a file never gets another token's copy
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct FileCacheKey {
+    project_id: u64,
+    file_path: String,
+    ref_name: String,
+    token_var: String,
+}
+
+#[derive(Debug)]
+struct CachedFile {
+    file: FileResponse,
+    checked_at: Instant,
+    used_at: Instant,
+}
+
+impl CachedFile {
+    /// Same blob means the same content, the last commit id is compared when gitlab sends no blob id
+    fn matches(&self, head: &FileHead) -> bool {
+        match (&self.file.blob_id, &head.blob_id) {
+            (Some(cached), Some(current)) => cached == current,
+            _ => self.file.last_commit_id.is_some() && self.file.last_commit_id == head.last_commit_id,
+        }
+    }
+}
+
+/// Shared cache of gitlab repository files, entries older than the ttl are revalidated with a HEAD request.
+/// Refs come from requests, so the cache holds at most `max_entries` files and drops the least recently used one
+#[derive(Debug)]
+pub struct GitlabFileCache {
+    ttl: Duration,
+    max_entries: usize,
+    files: RwLock<HashMap<FileCacheKey, CachedFile>>,
+}
+
+impl GitlabFileCache {
+    pub fn new(ttl_secs: u64, max_entries: usize) -> Self {
+        Self { ttl: Duration::from_secs(ttl_secs), max_entries, files: RwLock::new(HashMap::new()) }
+    }
+
+    /// `token_var` is the variable `token` was read from, it keeps files of different tokens apart
+    pub async fn get_file(&self, client: &GitlabClient, project_id: u64, file_path: &str, ref_name: &str, token_var: &str, token: &str) -> Result<FileResponse, RepositoryError> {
+        let key = FileCacheKey { project_id, file_path: file_path.to_string(), ref_name: ref_name.to_string(), token_var: token_var.to_string() };
+        if let Some(file) = self.revalidate(client, &key, token).await {
+            return Ok(file);
+        }
+        let file = client.get_gitlab_file(project_id, file_path, ref_name, token).await?;
+        // error answers deserialize into an empty response, they are not worth keeping
+        if file.content.is_some() && self.max_entries > 0 {
+            let mut files = self.files.write().await;
+            if files.len() >= self.max_entries && !files.contains_key(&key) {
+                let oldest = files.iter().min_by_key(|(_, cached)| cached.used_at).map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    trace!("File cache is full, dropping {:?}", oldest);
+                    files.remove(&oldest);
+                }
+            }
+            let now = Instant::now();
+            files.insert(key, CachedFile { file: file.clone(), checked_at: now, used_at: now });
+        }
+        Ok(file)
+    }
+
+    async fn revalidate(&self, client: &GitlabClient, key: &FileCacheKey, token: &str) -> Option<FileResponse> {
+        {
+            let mut files = self.files.write().await;
+            let cached = files.get_mut(key)?;
+            cached.used_at = Instant::now();
+            if cached.checked_at.elapsed() < self.ttl {
+                trace!("File cache hit for {:?}", key);
+                return Some(cached.file.clone());
+            }
+        }
+        let head = match client.get_gitlab_file_head(key.project_id, &key.file_path, &key.ref_name, token).await {
+            Ok(head) => head,
+            Err(e) => {
+                warn!("Failed to revalidate cached file {:?}: {}", key, e);
+                return None;
+            }
+        };
+        let mut files = self.files.write().await;
+        let cached = files.get_mut(key)?;
+        if !cached.matches(&head) {
+            trace!("Cached file {:?} changed upstream", key);
+            files.remove(key);
+            return None;
+        }
+        cached.checked_at = Instant::now();
+        Some(cached.file.clone())
+    }
+
+    /// Drop every cached file, returns how many were dropped
+    pub async fn flush(&self) -> usize {
+        let mut files = self.files.write().await;
+        let flushed = files.len();
+        files.clear();
+        flushed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::{Matcher, Mock, ServerGuard};
+    use serde_json::json;
+
+    use super::*;
+
+    const FILE_PATH: &str = "/projects/1/repository/files/site.yml";
+
+    async fn file_mock(server: &mut ServerGuard, blob_id: &str, content: &str, hits: usize) -> Mock {
+        server.mock("GET", FILE_PATH)
+            .match_query(Matcher::Any)
+            .with_body(json!({"blob_id": blob_id, "content": content}).to_string())
+            .expect(hits)
+            .create_async().await
+    }
+
+    async fn head_mock(server: &mut ServerGuard, blob_id: &str, hits: usize) -> Mock {
+        server.mock("HEAD", FILE_PATH)
+            .match_query(Matcher::Any)
+            .with_header("x-gitlab-blob-id", blob_id)
+            .expect(hits)
+            .create_async().await
+    }
+
+    async fn get(cache: &GitlabFileCache, client: &GitlabClient, ref_name: &str, token_var: &str) -> Option<String> {
+        cache.get_file(client, 1, "site.yml", ref_name, token_var, "token").await.unwrap().content
+    }
+
+    #[tokio::test]
+    async fn fresh_files_are_served_without_requests() {
+        let mut server = mockito::Server::new_async().await;
+        let client = GitlabClient::new(&server.url()).unwrap();
+        let file = file_mock(&mut server, "a", "v1", 1).await;
+        let head = head_mock(&mut server, "a", 0).await;
+        let cache = GitlabFileCache::new(60, 10);
+        assert_eq!(get(&cache, &client, "main", "TOKEN").await.as_deref(), Some("v1"));
+        assert_eq!(get(&cache, &client, "main", "TOKEN").await.as_deref(), Some("v1"));
+        file.assert_async().await;
+        head.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn unchanged_blob_is_revalidated() {
+        let mut server = mockito::Server::new_async().await;
+        let client = GitlabClient::new(&server.url()).unwrap();
+        let file = file_mock(&mut server, "a", "v1", 1).await;
+        let head = head_mock(&mut server, "a", 1).await;
+        let cache = GitlabFileCache::new(0, 10);
+        get(&cache, &client, "main", "TOKEN").await;
+        assert_eq!(get(&cache, &client, "main", "TOKEN").await.as_deref(), Some("v1"));
+        file.assert_async().await;
+        head.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn changed_blob_is_fetched_again() {
+        let mut server = mockito::Server::new_async().await;
+        let client = GitlabClient::new(&server.url()).unwrap();
+        let old = file_mock(&mut server, "a", "v1", 1).await;
+        let cache = GitlabFileCache::new(0, 10);
+        get(&cache, &client, "main", "TOKEN").await;
+        old.remove_async().await;
+        let new = file_mock(&mut server, "b", "v2", 1).await;
+        let head = head_mock(&mut server, "b", 1).await;
+        assert_eq!(get(&cache, &client, "main", "TOKEN").await.as_deref(), Some("v2"));
+        new.assert_async().await;
+        head.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn token_variables_do_not_share_files() {
+        let mut server = mockito::Server::new_async().await;
+        let client = GitlabClient::new(&server.url()).unwrap();
+        let file = file_mock(&mut server, "a", "v1", 2).await;
+        let cache = GitlabFileCache::new(60, 10);
+        get(&cache, &client, "main", "TOKEN").await;
+        get(&cache, &client, "main", "OTHER_TOKEN").await;
+        file.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_file_is_dropped() {
+        let mut server = mockito::Server::new_async().await;
+        let client = GitlabClient::new(&server.url()).unwrap();
+        // main is used again before feature comes in, so dev is the one dropped
+        let file = file_mock(&mut server, "a", "v1", 4).await;
+        let cache = GitlabFileCache::new(60, 2);
+        for ref_name in ["main", "dev", "main", "feature", "main", "dev"] {
+            get(&cache, &client, ref_name, "TOKEN").await;
+        }
+        file.assert_async().await;
+        assert_eq!(cache.flush().await, 2);
+    }
+}
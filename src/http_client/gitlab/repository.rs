@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 
 use super::GitlabClient;
-use super::responses::{FileResponse, GlBranch, GlTag, GitLabBranchesArgs};
+use super::responses::{FileHead, FileResponse, GlBranch, GlTag, GitLabBranchesArgs};
 use urlencoding::encode;
 use log::{info, trace};
 use thiserror::Error;
@@ -16,6 +16,8 @@ pub enum RepositoryError {
     InvalidFilePath(String),
     #[error("Invalid reference: {0}")]
     InvalidReference(String),
+    #[error("File metadata request failed with status {0}")]
+    HeadFailed(reqwest::StatusCode),
 }
 
 impl RepositoryError {
@@ -50,6 +52,25 @@ impl GitlabClient {
         Ok(file)
     }
 
+    /// Get blob and commit ids of a file without its content, used to revalidate cached files
+    pub async fn get_gitlab_file_head(&self, project_id: u64, file_path: &str, ref_name: &str, token: &str) -> Result<FileHead, RepositoryError> {
+        let file_path = encode(file_path);
+        let api_endpoint = &self.api_endpoint;
+        let url = format!("{api_endpoint}/projects/{project_id}/repository/files/{file_path}?ref={ref_name}");
+        trace!("HEAD URL: {}", url);
+        let response = self.authenticated_request(reqwest::Method::HEAD, &url, token)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(RepositoryError::HeadFailed(response.status()));
+        }
+        let header = |name: &str| response.headers().get(name).and_then(|value| value.to_str().ok()).map(str::to_string);
+        Ok(FileHead {
+            blob_id: header("x-gitlab-blob-id"),
+            last_commit_id: header("x-gitlab-last-commit-id"),
+        })
+    }
+
     /// Get all branches for a project with search
     // Attribute	Type	Required	Description
     // id	integer or string	yes	ID or URL-encoded path of the project.
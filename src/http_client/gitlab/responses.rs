@@ -72,7 +72,7 @@ pub struct User {
 }
 
 /// Repository file response
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileResponse {
     pub file_name: Option<String>,
     pub file_path: Option<String>,
@@ -87,6 +87,13 @@ pub struct FileResponse {
     pub execute_filemode: Option<bool>,
 }
 
+/// Repository file headers returned by a HEAD request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileHead {
+    pub blob_id: Option<String>,
+    pub last_commit_id: Option<String>,
+}
+
 /// Arguments for getting GitLab branches
 #[derive(Debug, Clone)]
 pub struct GitLabBranchesArgs {
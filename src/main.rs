@@ -1,14 +1,23 @@
 mod cmd;
 mod config;
+mod config_extends;
+mod config_reload;
+mod config_sources;
+mod config_validation;
+mod etcd_manager;
+mod etcd_migrations;
 mod merge_yml;
+mod secret_registry;
+mod secrets;
 mod state;
 use anyhow::{ Context, Result};
-use etcd_client::Client;
 use http_client::GitlabClient;
 use jwt::JwtKey;
 use log::warn;
+use secret_registry::DEFAULT_JWT_SECRET_VAR;
+use etcd_manager::EtcdManager;
 use state::{AppState, GitlabTokens};
-use std::{collections::HashMap, env};
+use std::env;
 use tracing_subscriber::prelude::*;
 mod handlers;
 mod http_client;
@@ -20,19 +29,30 @@ const DEFAULT_TOKEN_SECRET: &str = "mysecret";
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = cmd::parse();
+    let env_filter = match &args.log_level {
+        Some(log_level) => tracing_subscriber::EnvFilter::try_new(log_level)?,
+        None => tracing_subscriber::EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| format!("{}=trace", env!("CARGO_CRATE_NAME")).into()),
+    };
     tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| format!("{}=trace", env!("CARGO_CRATE_NAME")).into()),
-        )
+        .with(env_filter)
         .with(tracing_subscriber::fmt::layer())
         .init();
-    let conf = config::load().await?;
+    if let Some(cmd::Command::Migrate { dir, dry_run }) = &args.command {
+        let config_paths = args.config_paths();
+        let config_paths: Vec<&str> = config_paths.iter().map(String::as_str).collect();
+        let conf = config::load_local(&config_paths, &GitlabTokens::new()).await?;
+        etcd_migrations::run(&conf, dir, *dry_run).await?;
+        return Ok(());
+    }
     let gitlab_tokens = GitlabTokens::new();
-    let token_secret = if let Ok(token_secret) = gitlab_tokens.get("TOKEN_SECRET").await {
+    let conf = config::load(&args, &gitlab_tokens).await?;
+    let token_secret_var = conf.plim.jwt_secret_var.as_deref().unwrap_or(DEFAULT_JWT_SECRET_VAR);
+    let token_secret = if let Ok(token_secret) = gitlab_tokens.get(token_secret_var).await {
         token_secret
     } else {
-        warn!("TOKEN_SECRET is not set, using default value");
+        warn!("{} is not set, using default value", token_secret_var);
         DEFAULT_TOKEN_SECRET.to_string()
     };
     let jwt = JwtKey::init(&token_secret, conf.plim.jwt_token_duration_hours);
@@ -40,23 +60,22 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         Ok(gc) => gc,
         Err(e) => { Err(e.context("Failed to create GitlabClient"))? }
     };
-    let mut etcd_clients_map = HashMap::new();
-    for (key, value) in conf.etcd_data_map.iter() {
-        let etcd_client = Client::connect(value.address.clone(), None).await;
-        if let Ok(etcd_client) = etcd_client {
-            etcd_clients_map.insert(key.to_string(), etcd_client);
-        } else {
-            warn!("Failed to create EtcdClient for {}", key);
-        }
-    };
+    let etcd = EtcdManager::new(&conf.etcd_data_map, gitlab_tokens.clone());
+    let conf = config::load_etcd_configs(conf, None, &etcd).await;
     let app_state = state::AppState::new(
         jwt,
         conf.clone(),
         gc,
         gitlab_tokens,
-        etcd_clients_map
+        etcd,
+        args.config_paths(),
     );
-    let app = routes::create_router(app_state);
+    config_reload::spawn_reload_on_sighup(app_state.clone())?;
+    config_reload::spawn_etcd_config_watchers(app_state.clone());
+    if conf.plim.watch_config == Some(true) {
+        config_reload::spawn_config_watcher(app_state.clone())?;
+    }
+    let app = routes::create_router(app_state, &args.static_dir);
     let listener = tokio::net::TcpListener::bind(&conf.plim.listen_address)
         .await
         .context(format!(
@@ -1,11 +1,26 @@
-use log::{trace, info, debug, error};
+use log::{trace, info, debug, error, warn};
 use walkdir::WalkDir;
 use yaml_hash::YamlHash;
 use anyhow::{Context, Error, Result};
+use std::collections::{BTreeSet, HashMap};
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+use crate::config::{EtcdDataMap, SecretsConfig};
+use crate::config_extends::resolve_extends;
+use crate::config_sources::{describe, ConfigSources};
+use crate::state::GitlabTokens;
+
+const SECRETS_KEY: &str = "secrets";
+const ETCD_DATA_MAP_KEY: &str = "etcd_data_map";
+const PLIM_KEY: &str = "plim";
+const JWT_SECRET_VAR_KEY: &str = "jwt_secret_var";
+const TOKEN_VAR_SUFFIX: &str = "_var";
+const TRIGGER_TOKEN_KEY: &str = "trigger_token";
 
 #[derive(Debug, Clone)]
 pub struct ConfigData<'a> {
     pub merged_data: YamlHash,
+    pub sources: ConfigSources,
     config_paths_with_priority: Vec<&'a str>,
 }
 
@@ -13,6 +28,7 @@ impl<'a> ConfigData<'a> {
     pub fn new(config_paths_with_priority: Vec<&'a str>) -> Self {
         Self {
             merged_data: YamlHash::new(),
+            sources: ConfigSources::default(),
             config_paths_with_priority,
         }
     }
@@ -23,6 +39,9 @@ impl<'a> ConfigData<'a> {
             self.merge_one(path)?;
         }
         debug!("CONFIG: {:?}", self.merged_data);
+        for (path, sources) in self.sources.duplicates() {
+            warn!("{} is defined more than once and merged: {}", path, describe(sources));
+        }
         Ok(())
     }
     pub fn merge_one(&mut self, path: &str) -> Result<(), Error> {
@@ -37,6 +56,8 @@ impl<'a> ConfigData<'a> {
         for yaml_file in yaml_files {
             info!("Merging file: {:?}", yaml_file);
             self.merged_data = self.merged_data.merge_file(&yaml_file)?;
+            let content = std::fs::read_to_string(&yaml_file).context(format!("Failed to read {}", yaml_file))?;
+            self.sources.add_file(&yaml_file, &serde_yaml::from_str(&content)?);
         }
         
         Ok(())
@@ -44,7 +65,7 @@ impl<'a> ConfigData<'a> {
 }
 
 // Function to recursively get all YAML files in a folder
-fn get_yaml_files_in_folder(folder_path: &str) -> Result<Vec<String>, Error> {
+pub fn get_yaml_files_in_folder(folder_path: &str) -> Result<Vec<String>, Error> {
     let mut yaml_files = Vec::new();
     for entry in WalkDir::new(folder_path) {
         let entry = entry?;
@@ -65,3 +86,265 @@ fn get_yaml_files_in_folder(folder_path: &str) -> Result<Vec<String>, Error> {
     info!("Files to load found in folder {}: {}", folder_path, yaml_files.len());
     Ok(yaml_files)
 }
+
+impl ConfigData<'_> {
+    /// Merged YAML with `${VAR}` and `${VAR:-default}` resolved through `tokens` and plans merged over what they extend.
+    /// `merged_data` keeps the references so resolved secrets never reach the logs
+    pub fn to_config_yaml(&self, tokens: &GitlabTokens) -> Result<String, Error> {
+        let mut value: Value = serde_yaml::from_str(&self.merged_data.to_string())?;
+        let errors = interpolate_value(&mut value, String::new(), tokens);
+        if !errors.is_empty() {
+            let errors: Vec<String> = errors.iter().map(|(path, error)| format!("{} at {}", error, path)).collect();
+            return Err(anyhow::anyhow!("Failed to interpolate config variables: {}", errors.join(", ")));
+        }
+        resolve_extends(&mut value)?;
+        Ok(serde_yaml::to_string(&value)?)
+    }
+}
+
+/// Settings the secret providers need before the rest of the config can be resolved
+#[derive(Deserialize)]
+struct SecretSettings {
+    #[serde(default)]
+    secrets: SecretsConfig,
+    #[serde(default)]
+    etcd_data_map: HashMap<String, EtcdDataMap>,
+}
+
+impl ConfigData<'_> {
+    /// Tokens with the secret providers of the merged files, every provider reference in them is fetched.
+    /// `tokens` stay as they are until the caller adopts the result.
+    /// The providers' own settings are resolved with env variables only
+    pub async fn prepare_secrets(&self, tokens: &GitlabTokens) -> Result<GitlabTokens, Error> {
+        let value: Value = serde_yaml::from_str(&self.merged_data.to_string())?;
+        let mut settings = Mapping::new();
+        for key in [SECRETS_KEY, ETCD_DATA_MAP_KEY] {
+            if let Some(section) = value.get(key) {
+                settings.insert(Value::from(key), section.clone());
+            }
+        }
+        let mut settings = Value::Mapping(settings);
+        let errors = interpolate_value(&mut settings, String::new(), &GitlabTokens::new());
+        if !errors.is_empty() {
+            let errors: Vec<String> = errors.iter().map(|(path, error)| format!("{} at {}", error, path)).collect();
+            return Err(anyhow::anyhow!("Failed to interpolate env variables in secret provider settings: {}", errors.join(", ")));
+        }
+        let settings: SecretSettings = serde_yaml::from_value(settings).context("Failed to parse secret provider settings")?;
+        // the registry keeps plans away from the variable the JWTs are signed with
+        let jwt_secret_var = value.get(PLIM_KEY).and_then(|plim| plim.get(JWT_SECRET_VAR_KEY)).and_then(Value::as_str);
+        let staged = tokens.staged(&settings.secrets, &settings.etcd_data_map, jwt_secret_var)?;
+        let mut refs = BTreeSet::new();
+        collect_token_refs(&value, None, &mut refs);
+        refs.extend(staged.registry_sources());
+        staged.prefetch(refs.iter().map(String::as_str)).await;
+        Ok(staged)
+    }
+}
+
+/// Variables a config uses: values of `*_var` and `trigger_token` fields and `${VAR}` references
+fn collect_token_refs(value: &Value, field: Option<&str>, refs: &mut BTreeSet<String>) {
+    match value {
+        Value::String(s) => {
+            if field.is_some_and(|field| field.ends_with(TOKEN_VAR_SUFFIX) || field == TRIGGER_TOKEN_KEY) {
+                refs.insert(s.clone());
+            }
+            refs.extend(variable_refs(s));
+        }
+        Value::Sequence(seq) => seq.iter().for_each(|value| collect_token_refs(value, None, refs)),
+        Value::Mapping(map) => map.iter().for_each(|(key, value)| collect_token_refs(value, key.as_str(), refs)),
+        Value::Tagged(tagged) => collect_token_refs(&tagged.value, field, refs),
+        _ => {}
+    }
+}
+
+/// Names in the `${VAR}` and `${VAR:-default}` references of `s`, escaped `$${VAR}` left out
+fn variable_refs(s: &str) -> Vec<String> {
+    let mut refs = Vec::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        let escaped = rest[..start].ends_with('$');
+        let after = &rest[start + 2..];
+        let Some(end) = after.find('}') else { break };
+        if !escaped {
+            let reference = &after[..end];
+            refs.push(reference.split_once(":-").map_or(reference, |(var, _)| var).to_string());
+        }
+        rest = &after[end + 1..];
+    }
+    refs
+}
+
+/// `${VAR}` reference which could not be interpolated
+#[derive(Debug, PartialEq)]
+pub enum InterpolationError {
+    Undefined(String),
+    Refused(String), // why the variable can't be read
+}
+
+impl std::fmt::Display for InterpolationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InterpolationError::Undefined(var) => write!(f, "undefined variable ${{{}}}", var),
+            InterpolationError::Refused(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// Interpolate every string value below `value`, returns the references which failed with their config path
+pub fn interpolate_value(value: &mut Value, path: String, tokens: &GitlabTokens) -> Vec<(String, InterpolationError)> {
+    match value {
+        Value::String(s) => match interpolate_str(s, tokens) {
+            Ok(interpolated) => {
+                *value = typed_scalar(s, interpolated);
+                Vec::new()
+            }
+            Err(errors) => errors.into_iter().map(|error| (path.clone(), error)).collect(),
+        },
+        Value::Sequence(seq) => seq.iter_mut().enumerate()
+            .flat_map(|(index, value)| interpolate_value(value, format!("{}[{}]", path, index), tokens))
+            .collect(),
+        Value::Mapping(map) => map.iter_mut()
+            .flat_map(|(key, value)| {
+                let key = match key {
+                    Value::String(key) => key.clone(),
+                    key => serde_yaml::to_string(key).unwrap_or_default().trim().to_string(),
+                };
+                let path = if path.is_empty() { key } else { format!("{}.{}", path, key) };
+                interpolate_value(value, path, tokens)
+            })
+            .collect(),
+        Value::Tagged(tagged) => interpolate_value(&mut tagged.value, path, tokens),
+        _ => Vec::new(),
+    }
+}
+
+/// A value which is a single `${VAR}` reference takes the type its value has in YAML, so `port: ${PORT}` is a number.
+/// Text around the reference keeps it a string
+fn typed_scalar(original: &str, interpolated: String) -> Value {
+    let single_reference = original.strip_prefix("${").is_some_and(|reference| reference.find('}') == Some(reference.len() - 1));
+    if single_reference
+        && let Ok(value @ (Value::Bool(_) | Value::Number(_))) = serde_yaml::from_str(&interpolated) {
+        return value;
+    }
+    Value::String(interpolated)
+}
+
+/// `$${VAR}` is kept as a literal `${VAR}`. Secrets of the registry and Plim's own credentials are refused, also with a default
+fn interpolate_str(s: &str, tokens: &GitlabTokens) -> Result<String, Vec<InterpolationError>> {
+    let mut result = String::with_capacity(s.len());
+    let mut errors = Vec::new();
+    let mut rest = s;
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        if let Some(escaped) = after.strip_prefix("${") {
+            result.push_str("${");
+            rest = escaped;
+            continue;
+        }
+        let Some(reference) = after.strip_prefix('{') else {
+            result.push('$');
+            rest = after;
+            continue;
+        };
+        let Some(end) = reference.find('}') else {
+            result.push_str(&rest[start..]);
+            rest = "";
+            break;
+        };
+        let (var, default) = match reference[..end].split_once(":-") {
+            Some((var, default)) => (var, Some(default)),
+            None => (&reference[..end], None),
+        };
+        match (tokens.lookup_for_interpolation(var), default) {
+            (Ok(Some(resolved)), _) => result.push_str(&resolved),
+            (Ok(None), Some(default)) => result.push_str(default),
+            (Ok(None), None) => errors.push(InterpolationError::Undefined(var.to_string())),
+            (Err(e), _) => errors.push(InterpolationError::Refused(e.to_string())),
+        }
+        rest = &reference[end + 1..];
+    }
+    result.push_str(rest);
+    if errors.is_empty() { Ok(result) } else { Err(errors) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens() -> GitlabTokens {
+        GitlabTokens::with_vars(&[("HOST", "gitlab.example"), ("PORT", "8443"), ("ENABLED", "true"), ("EMPTY", "")])
+    }
+
+    #[test]
+    fn variables_and_defaults_are_resolved() {
+        let tokens = tokens();
+        assert_eq!(interpolate_str("https://${HOST}:${PORT}/api", &tokens).unwrap(), "https://gitlab.example:8443/api");
+        assert_eq!(interpolate_str("${MISSING:-fallback}", &tokens).unwrap(), "fallback");
+        assert_eq!(interpolate_str("${MISSING:-}", &tokens).unwrap(), "");
+        // a set variable wins over the default, even when it is empty
+        assert_eq!(interpolate_str("${HOST:-other}", &tokens).unwrap(), "gitlab.example");
+        assert_eq!(interpolate_str("[${EMPTY:-x}]", &tokens).unwrap(), "[]");
+    }
+
+    #[test]
+    fn escaped_references_stay_literal() {
+        let tokens = tokens();
+        assert_eq!(interpolate_str("$${HOST}", &tokens).unwrap(), "${HOST}");
+        assert_eq!(interpolate_str("$${MISSING} and ${HOST}", &tokens).unwrap(), "${MISSING} and gitlab.example");
+        assert_eq!(interpolate_str("costs $5 or $$", &tokens).unwrap(), "costs $5 or $$");
+    }
+
+    #[test]
+    fn unterminated_reference_is_kept() {
+        assert_eq!(interpolate_str("${HOST}/${PORT", &tokens()).unwrap(), "gitlab.example/${PORT");
+    }
+
+    #[test]
+    fn undefined_variables_are_returned() {
+        assert_eq!(interpolate_str("${MISSING}/${HOST}/${OTHER}", &tokens()),
+            Err(vec![InterpolationError::Undefined("MISSING".to_string()), InterpolationError::Undefined("OTHER".to_string())]));
+    }
+
+    #[test]
+    fn secrets_are_refused() {
+        let tokens = GitlabTokens::with_vars(&[("TOKEN_SECRET", "jwt"), ("DEPLOY_TOKEN", "glpat"), ("HOST", "gitlab.example")]);
+        let config: SecretsConfig = serde_yaml::from_str("registry: {DEPLOY_TOKEN: {groups: [ops]}}").unwrap();
+        tokens.adopt(&tokens.staged(&config, &HashMap::new(), None).unwrap());
+        for reference in ["${TOKEN_SECRET}", "${DEPLOY_TOKEN:-x}", "https://${HOST}/${DEPLOY_TOKEN}"] {
+            let errors = interpolate_str(reference, &tokens).unwrap_err();
+            assert!(matches!(errors.as_slice(), [InterpolationError::Refused(_)]), "{}: {:?}", reference, errors);
+        }
+        assert_eq!(interpolate_str("https://${HOST}", &tokens).unwrap(), "https://gitlab.example");
+    }
+
+    #[test]
+    fn single_references_keep_their_yaml_type() {
+        let mut value: Value = serde_yaml::from_str(r#"
+port: ${PORT}
+enabled: ${ENABLED}
+url: "${HOST}:${PORT}"
+label: port ${PORT}
+host: ${HOST}
+empty: ${EMPTY}
+escaped: $${PORT}
+"#).unwrap();
+        assert!(interpolate_value(&mut value, String::new(), &tokens()).is_empty());
+        assert_eq!(value["port"], Value::from(8443));
+        assert_eq!(value["enabled"], Value::from(true));
+        assert_eq!(value["url"], Value::from("gitlab.example:8443"));
+        assert_eq!(value["label"], Value::from("port 8443"));
+        assert_eq!(value["host"], Value::from("gitlab.example"));
+        assert_eq!(value["empty"], Value::from(""));
+        assert_eq!(value["escaped"], Value::from("${PORT}"));
+    }
+
+    #[test]
+    fn undefined_variables_are_reported_with_their_path() {
+        let mut value: Value = serde_yaml::from_str("plans:\n  deploy:\n    views:\n      - key: ${MISSING}\n").unwrap();
+        let errors: Vec<String> = interpolate_value(&mut value, String::new(), &tokens()).iter()
+            .map(|(path, error)| format!("{}: {}", path, error))
+            .collect();
+        assert_eq!(errors, vec!["plans.deploy.views[0].key: undefined variable ${MISSING}"]);
+    }
+}
@@ -5,7 +5,7 @@ use axum::{
     middleware::Next,
     response::Response,
 };
-use log::{ info, trace};
+use log::{ info, trace, warn};
 use crate::{ routes::FRONT_API_ROOT_PATH, state::AppState };
 use super::*;
 
@@ -21,7 +21,15 @@ pub async fn jwt_auth(
         return Ok(next.run(req).await);
     } 
     let headers = req.headers();
-    let claims = state.jwt.validate_jwt(headers).await?;
+    let mut claims = state.jwt.validate_jwt(headers).await?;
+    // users disabled, removed or regrouped after login get that applied to their token too
+    match state.config.load().users.get(&claims.username) {
+        Some(user) if !user.disabled => claims.roles = user.groups.clone(),
+        _ => {
+            warn!("JWT of unknown or disabled user {} rejected", claims.username);
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+    }
     info!("JWT claims: {:?}", claims);
     // Attach claims to request extensions for further use
     req.extensions_mut().insert(claims);
@@ -1,9 +1,18 @@
-use crate::{handlers::{admin_tools::gen_password_hash, users::get_users}, middleware::role_validate::authorize_role};
+use crate::{handlers::{admin_tools::{gen_password_hash, get_config_sources, get_etcd_health, reload_config}, ansible::decrypt_ansible_inventory, gitlab::flush_gitlab_file_cache, plan_admin::{create_etcd_plan, delete_etcd_plan, get_etcd_plan, list_etcd_plans, update_etcd_plan}, user_admin::{create_etcd_user, reset_etcd_user_password, update_etcd_user}, users::get_users}, middleware::role_validate::authorize_role};
 use super::routes::*;
 
 pub fn get_routes() -> Router<AppState>{
     Router::new()
     .route("/user-list", get(get_users))
     .route("/gen-password-hash", post(gen_password_hash))
+    .route("/ansible/inventory/decrypt", post(decrypt_ansible_inventory))
+    .route("/gitlab/file-cache/flush", post(flush_gitlab_file_cache))
+    .route("/config/reload", post(reload_config))
+    .route("/config/sources", get(get_config_sources))
+    .route("/etcd/health", get(get_etcd_health))
+    .route("/etcd/plans", get(list_etcd_plans))
+    .route("/etcd/plans/{*key}", get(get_etcd_plan).post(create_etcd_plan).put(update_etcd_plan).delete(delete_etcd_plan))
+    .route("/etcd/users/{key}", post(create_etcd_user).patch(update_etcd_user))
+    .route("/etcd/users/{key}/password", post(reset_etcd_user_password))
     .layer(axum_middleware::from_fn(|req, next| authorize_role(req, next, "admin")))
 }
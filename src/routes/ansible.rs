@@ -1,9 +1,14 @@
-use crate::handlers::ansible::{get_ansible_inventory, get_ansible_cmd};
+use crate::handlers::ansible::{export_ansible_inventory, get_ansible_cmd, get_ansible_inventory, get_dynamic_inventory_host, get_dynamic_inventory_list, match_ansible_hosts, encrypt_vault_value, get_playbook_summary};
 
 use super::routes::*;
 pub fn get_routes() -> Router<AppState>{
     Router::new()
     .route("/ansible/inventory", post(get_ansible_inventory))
+    .route("/ansible/inventory/export", post(export_ansible_inventory))
+    .route("/ansible/inventory/{plan_name}/list", get(get_dynamic_inventory_list))
+    .route("/ansible/inventory/{plan_name}/host/{host_name}", get(get_dynamic_inventory_host))
+    .route("/ansible/hosts/match", post(match_ansible_hosts))
+    .route("/ansible/vault/encrypt", post(encrypt_vault_value))
+    .route("/ansible/playbook/{plan_name}", get(get_playbook_summary))
     .route("/ansible/get-cmd", post(get_ansible_cmd))
 }
-
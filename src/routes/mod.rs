@@ -1,4 +1,5 @@
 use std::convert::Infallible;
+use std::path::Path;
 
 use crate::{middleware::jwt::jwt_auth, routes};
 use axum::{
@@ -24,30 +25,34 @@ mod etcd;
 pub const FRONT_API_ROOT_PATH: &str = "/api/v1";
 fn get_cors() -> CorsLayer {
     CorsLayer::new()
-    .allow_methods([axum::http::Method::GET, axum::http::Method::POST])
+    .allow_methods([axum::http::Method::GET, axum::http::Method::POST, axum::http::Method::PUT, axum::http::Method::PATCH, axum::http::Method::DELETE])
     .allow_origin(Any)
     .allow_headers([axum::http::header::CONTENT_TYPE, axum::http::header::AUTHORIZATION])
 }
 
 
-pub fn create_router(app_state: AppState) -> Router {
-    let index_fallback = service_fn(|_req: Request<Body>| async move {
-        match tokio::fs::read("static/index.html").await {
-            Ok(contents) => Ok::<_, Infallible>(
-                Response::builder()
-                    .header("Content-Type", "text/html")
-                    .body(Body::from(contents))
-                    .unwrap(),
-            ),
-            Err(_) => Ok(
-                Response::builder()
-                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body(Body::from("Internal Server Error"))
-                    .unwrap(),
-            ),
+pub fn create_router(app_state: AppState, static_dir: &str) -> Router {
+    let index_path = Path::new(static_dir).join("index.html");
+    let index_fallback = service_fn(move |_req: Request<Body>| {
+        let index_path = index_path.clone();
+        async move {
+            match tokio::fs::read(index_path).await {
+                Ok(contents) => Ok::<_, Infallible>(
+                    Response::builder()
+                        .header("Content-Type", "text/html")
+                        .body(Body::from(contents))
+                        .unwrap(),
+                ),
+                Err(_) => Ok(
+                    Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(Body::from("Internal Server Error"))
+                        .unwrap(),
+                ),
+            }
         }
     });
-    let static_service = get_service(ServeDir::new("static").not_found_service(index_fallback));
+    let static_service = get_service(ServeDir::new(static_dir).not_found_service(index_fallback));
 
     let no_auth_routes = Router::new().fallback_service(static_service);
 
@@ -1,4 +1,4 @@
-use crate::handlers::plans::{get_all_plans, get_plan};
+use crate::handlers::plans::{get_all_plans, get_config_schema, get_plan};
 
 use super::routes::*;
 
@@ -6,4 +6,5 @@ pub fn get_routes() -> Router<AppState>{
     Router::new()
     .route("/plans-list", get(get_all_plans))
     .route("/plans/{plan_name}", get(get_plan))
+    .route("/config/schema/{kind}", get(get_config_schema))
 }
@@ -0,0 +1,153 @@
+use std::collections::{BTreeSet, HashMap};
+
+use thiserror::Error;
+
+use crate::config::{EtcdDataMap, PlimPlan, RegisteredSecret, SecretsConfig};
+
+pub const DEFAULT_JWT_SECRET_VAR: &str = "TOKEN_SECRET";
+
+#[derive(Error, Debug)]
+pub enum SecretAccessError {
+    #[error("Secret {0} is not in secrets.registry")]
+    NotRegistered(String),
+    #[error("Plan {plan} is not allowed to use secret {name}")]
+    NotAllowed { name: String, plan: String },
+    #[error("{0} is reserved for Plim itself and can't be used by plans")]
+    Reserved(String),
+    #[error("Secret {0} can't be used, plans only reach the secrets in secrets.registry and it is not set")]
+    NoRegistry(String),
+    #[error("${{{0}}} names a secret, secrets can't be interpolated into the config")]
+    Interpolated(String),
+}
+
+/// Plan a secret is looked up for, the registry allows secrets by plan name or by plan group
+#[derive(Debug, Clone, Copy)]
+pub struct PlanScope<'a> {
+    pub name: &'a str,
+    pub groups: &'a [String],
+}
+
+impl<'a> PlanScope<'a> {
+    pub fn new(name: &'a str, plan: &'a PlimPlan) -> Self {
+        Self { name, groups: &plan.groups }
+    }
+}
+
+/// Secrets plans may use, only the names in `secrets.registry` are reachable and none without it.
+/// Plim's own credentials are never reachable
+pub struct SecretRegistry {
+    secrets: Option<HashMap<String, RegisteredSecret>>,
+    reserved: BTreeSet<String>,
+}
+
+impl Default for SecretRegistry {
+    fn default() -> Self {
+        Self { secrets: None, reserved: BTreeSet::from([DEFAULT_JWT_SECRET_VAR.to_string()]) }
+    }
+}
+
+impl SecretRegistry {
+    pub fn new(config: &SecretsConfig, etcd_data_map: &HashMap<String, EtcdDataMap>, jwt_secret_var: Option<&str>) -> Result<Self, SecretAccessError> {
+        let mut reserved = BTreeSet::from([jwt_secret_var.unwrap_or(DEFAULT_JWT_SECRET_VAR).to_string()]);
+        reserved.extend(config.vault.iter().map(|vault| vault.token_var.clone()));
+        reserved.extend(config.etcd.iter().map(|etcd| etcd.password_var.clone()));
+        reserved.extend(etcd_data_map.values().filter_map(|etcd| etcd.password_var.clone()));
+        let registry = Self { secrets: config.registry.clone(), reserved };
+        for (name, source) in registry.sources() {
+            if registry.reserved.contains(source) {
+                return Err(SecretAccessError::Reserved(format!("secrets.registry.{} source {}", name, source)));
+            }
+        }
+        Ok(registry)
+    }
+
+    /// Token variable which holds the secret `name` of `plan`
+    pub fn resolve(&self, name: &str, plan: PlanScope<'_>) -> Result<String, SecretAccessError> {
+        if self.reserved.contains(name) {
+            return Err(SecretAccessError::Reserved(name.to_string()));
+        }
+        let Some(secrets) = &self.secrets else {
+            return Err(SecretAccessError::NoRegistry(name.to_string()));
+        };
+        let secret = secrets.get(name).ok_or_else(|| SecretAccessError::NotRegistered(name.to_string()))?;
+        let allowed = secret.plans.iter().any(|allowed| allowed == plan.name)
+            || secret.groups.iter().any(|group| plan.groups.contains(group));
+        if !allowed {
+            return Err(SecretAccessError::NotAllowed { name: name.to_string(), plan: plan.name.to_string() });
+        }
+        Ok(secret.source.clone().unwrap_or_else(|| name.to_string()))
+    }
+
+    /// `${var}` in the config can't read Plim's credentials or a registered secret, by its name or its source
+    pub fn check_interpolation(&self, var: &str) -> Result<(), SecretAccessError> {
+        let registered = self.sources().into_iter().any(|(name, source)| name == var || source == var);
+        if registered || self.reserved.contains(var) {
+            return Err(SecretAccessError::Interpolated(var.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Registry names with the token variable each one reads
+    pub fn sources(&self) -> Vec<(&str, &str)> {
+        self.secrets.iter().flatten()
+            .map(|(name, secret)| (name.as_str(), secret.source.as_deref().unwrap_or(name)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry(yaml: &str) -> Result<SecretRegistry, SecretAccessError> {
+        let config: SecretsConfig = serde_yaml::from_str(yaml).unwrap();
+        let etcd_data_map = HashMap::from([("main".to_string(), EtcdDataMap { password_var: Some("ETCD_PASSWORD".to_string()), ..Default::default() })]);
+        SecretRegistry::new(&config, &etcd_data_map, Some("JWT_KEY"))
+    }
+
+    const REGISTRY: &str = r#"
+vault: { address: "http://127.0.0.1:8200", token_var: VAULT_TOKEN }
+registry:
+  deploy-gitlab: { source: "vault:plim/gitlab#token", plans: [deploy] }
+  OPS_TOKEN: { groups: [ops] }
+"#;
+
+    #[test]
+    fn registered_secrets_resolve_for_allowed_plans() {
+        let registry = registry(REGISTRY).unwrap();
+        let deploy = PlanScope { name: "deploy", groups: &[] };
+        let ops = PlanScope { name: "backup", groups: &["ops".to_string()] };
+        assert_eq!(registry.resolve("deploy-gitlab", deploy).unwrap(), "vault:plim/gitlab#token");
+        assert_eq!(registry.resolve("OPS_TOKEN", ops).unwrap(), "OPS_TOKEN");
+        assert_eq!(registry.resolve("deploy-gitlab", ops).unwrap_err().to_string(), "Plan backup is not allowed to use secret deploy-gitlab");
+        assert!(matches!(registry.resolve("vault:plim/gitlab#token", deploy), Err(SecretAccessError::NotRegistered(_))));
+        assert!(matches!(registry.resolve("PATH", deploy), Err(SecretAccessError::NotRegistered(_))));
+    }
+
+    #[test]
+    fn nothing_resolves_without_a_registry() {
+        let registry = registry("{}").unwrap();
+        let deploy = PlanScope { name: "deploy", groups: &[] };
+        assert!(matches!(registry.resolve("DEPLOY_TOKEN", deploy), Err(SecretAccessError::NoRegistry(_))));
+        for reserved in ["JWT_KEY", "ETCD_PASSWORD"] {
+            assert!(matches!(registry.resolve(reserved, deploy), Err(SecretAccessError::Reserved(_))), "{}", reserved);
+        }
+        assert!(matches!(SecretRegistry::default().resolve(DEFAULT_JWT_SECRET_VAR, deploy), Err(SecretAccessError::Reserved(_))));
+    }
+
+    #[test]
+    fn sources_can_not_name_reserved_variables() {
+        let error = registry("{vault: {address: x, token_var: VAULT_TOKEN}, registry: {leak: {source: VAULT_TOKEN, plans: [deploy]}}}").err().unwrap();
+        assert_eq!(error.to_string(), "secrets.registry.leak source VAULT_TOKEN is reserved for Plim itself and can't be used by plans");
+    }
+
+    #[test]
+    fn secrets_are_not_interpolated() {
+        let registry = registry(REGISTRY).unwrap();
+        for var in ["JWT_KEY", "VAULT_TOKEN", "ETCD_PASSWORD", "deploy-gitlab", "vault:plim/gitlab#token", "OPS_TOKEN"] {
+            assert!(matches!(registry.check_interpolation(var), Err(SecretAccessError::Interpolated(_))), "{}", var);
+        }
+        assert!(registry.check_interpolation("GITLAB_URL").is_ok());
+        assert!(registry.check_interpolation("vault:plim/admin#full_name").is_ok());
+    }
+}
@@ -0,0 +1,405 @@
+use std::collections::HashMap;
+use std::env;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Error};
+use etcd_client::Client;
+use log::{info, warn};
+use serde_json::Value;
+use tokio::sync::Mutex;
+
+use crate::config::{EtcdDataMap, EtcdSecretsConfig, FileSecretsConfig, SecretsConfig, VaultSecretsConfig};
+use crate::handlers::ansible::vault::VaultPayload;
+use crate::state::GitlabTokens;
+
+pub const DEFAULT_SECRET_CACHE_TTL_SECS: u64 = 300;
+const DEFAULT_VAULT_MOUNT: &str = "secret";
+const DEFAULT_VAULT_FIELD: &str = "value";
+const VAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub const ENV_PROVIDER: &str = "env";
+pub const FILE_PROVIDER: &str = "file";
+pub const ETCD_PROVIDER: &str = "etcd";
+pub const VAULT_PROVIDER: &str = "vault";
+const PROVIDERS: [&str; 4] = [ENV_PROVIDER, FILE_PROVIDER, ETCD_PROVIDER, VAULT_PROVIDER];
+
+pub type SecretFuture<'a> = Pin<Box<dyn Future<Output = Result<Option<String>, Error>> + Send + 'a>>;
+
+/// Source of secrets a `token_var` can name with a `<provider>:` prefix
+pub trait SecretProvider: Send + Sync {
+    /// Value of `key`, `None` when the provider has no such secret
+    fn fetch<'a>(&'a self, key: &'a str) -> SecretFuture<'a>;
+}
+
+/// Provider and key of a `<provider>:<key>` reference, plain names are env variables
+pub fn split_secret_ref(var: &str) -> Option<(&str, &str)> {
+    var.split_once(':').filter(|(provider, _)| PROVIDERS.contains(provider))
+}
+
+/// Process env with `__` folded to `_`, read once at startup
+#[derive(Default)]
+pub struct EnvProvider {
+    vars: HashMap<String, String>,
+}
+
+impl EnvProvider {
+    pub fn from_env() -> Self {
+        Self { vars: env::vars().map(|(key, value)| (key.replace("__", "_"), value)).collect() }
+    }
+
+    #[cfg(test)]
+    pub fn from_vars(vars: &[(&str, &str)]) -> Self {
+        Self { vars: vars.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect() }
+    }
+
+    pub fn var(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(String::as_str)
+    }
+}
+
+impl SecretProvider for EnvProvider {
+    fn fetch<'a>(&'a self, key: &'a str) -> SecretFuture<'a> {
+        Box::pin(async move { Ok(self.var(key).map(str::to_string)) })
+    }
+}
+
+/// One file per secret, as Kubernetes and Docker mount them, trailing newlines are dropped
+pub struct FileProvider {
+    dir: PathBuf,
+}
+
+impl FileProvider {
+    pub fn new(config: &FileSecretsConfig) -> Self {
+        Self { dir: PathBuf::from(&config.dir) }
+    }
+}
+
+impl SecretProvider for FileProvider {
+    fn fetch<'a>(&'a self, key: &'a str) -> SecretFuture<'a> {
+        Box::pin(async move {
+            // the key names a file inside the directory, never a path out of it
+            if !Path::new(key).components().all(|component| matches!(component, Component::Normal(_))) {
+                return Err(anyhow::anyhow!("Secret file name {} is not a plain relative path", key));
+            }
+            let path = self.dir.join(key);
+            match tokio::fs::read_to_string(&path).await {
+                Ok(value) => Ok(Some(value.trim_end_matches(['\r', '\n']).to_string())),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(Error::new(e).context(format!("Failed to read secret file {}", path.display()))),
+            }
+        })
+    }
+}
+
+/// Ansible vault encrypted values below an etcd prefix, decrypted with the password from `password_var`
+pub struct EtcdSecretProvider {
+    etcd: EtcdDataMap,
+    etcd_name: String,
+    key_prefix: String,
+    password: String,
+    client: Mutex<Option<Client>>,
+}
+
+impl EtcdSecretProvider {
+    pub fn new(config: &EtcdSecretsConfig, etcd_data_map: &HashMap<String, EtcdDataMap>, env: &EnvProvider) -> Result<Self, Error> {
+        let etcd = etcd_data_map.get(&config.etcd_name)
+            .with_context(|| format!("Etcd {} of the etcd secret provider is not in etcd_data_map", config.etcd_name))?;
+        let password = env.var(&config.password_var)
+            .with_context(|| format!("Env variable {} of the etcd secret provider is not set", config.password_var))?;
+        Ok(Self {
+            etcd: etcd.clone(),
+            etcd_name: config.etcd_name.clone(),
+            key_prefix: format!("{}/", config.key_prefix_path.trim_end_matches('/')),
+            password: password.to_string(),
+            client: Mutex::new(None),
+        })
+    }
+
+    async fn client(&self) -> Result<Client, Error> {
+        let mut client = self.client.lock().await;
+        if let Some(client) = client.as_ref() {
+            return Ok(client.clone());
+        }
+        let connected = self.etcd.connect(&GitlabTokens::new()).await
+            .with_context(|| format!("Failed to connect to etcd {} for secrets", self.etcd_name))?;
+        *client = Some(connected.clone());
+        Ok(connected)
+    }
+}
+
+impl SecretProvider for EtcdSecretProvider {
+    fn fetch<'a>(&'a self, key: &'a str) -> SecretFuture<'a> {
+        Box::pin(async move {
+            let full_key = format!("{}{}", self.key_prefix, key.trim_start_matches('/'));
+            let response = match self.client().await?.get(full_key.as_str(), None).await {
+                Ok(response) => response,
+                Err(e) => {
+                    // connect again on the next fetch
+                    self.client.lock().await.take();
+                    return Err(Error::new(e).context(format!("Failed to read secret {} from etcd {}", full_key, self.etcd_name)));
+                }
+            };
+            let Some(kv) = response.kvs().first() else { return Ok(None) };
+            let text = kv.value_str().with_context(|| format!("Secret {} is not utf-8", full_key))?;
+            let payload = VaultPayload::parse(text).with_context(|| format!("Secret {} is not an ansible vault value", full_key))?;
+            Ok(Some(payload.decrypt(&self.password).with_context(|| format!("Failed to decrypt secret {}", full_key))?))
+        })
+    }
+}
+
+/// HashiCorp Vault KV version 2, keys are `<path>` or `<path>#<field>` with `value` as the default field
+pub struct VaultProvider {
+    client: reqwest::Client,
+    address: String,
+    token: String,
+    mount: String,
+}
+
+impl VaultProvider {
+    pub fn new(config: &VaultSecretsConfig, env: &EnvProvider) -> Result<Self, Error> {
+        let token = env.var(&config.token_var)
+            .with_context(|| format!("Env variable {} of the vault secret provider is not set", config.token_var))?;
+        let client = reqwest::Client::builder().timeout(VAULT_REQUEST_TIMEOUT).build()?;
+        Ok(Self {
+            client,
+            address: config.address.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            mount: config.mount.clone().unwrap_or_else(|| DEFAULT_VAULT_MOUNT.to_string()),
+        })
+    }
+}
+
+impl SecretProvider for VaultProvider {
+    fn fetch<'a>(&'a self, key: &'a str) -> SecretFuture<'a> {
+        Box::pin(async move {
+            let (path, field) = key.split_once('#').unwrap_or((key, DEFAULT_VAULT_FIELD));
+            let url = format!("{}/v1/{}/data/{}", self.address, self.mount, path.trim_start_matches('/'));
+            let response = self.client.get(&url).header("X-Vault-Token", &self.token).send().await
+                .with_context(|| format!("Vault request for {} failed", path))?;
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
+                return Ok(None);
+            }
+            if !response.status().is_success() {
+                return Err(anyhow::anyhow!("Vault answered {} for {}", response.status(), path));
+            }
+            let body: Value = response.json().await.with_context(|| format!("Vault response for {} is not json", path))?;
+            match body.pointer("/data/data").and_then(|data| data.get(field)) {
+                Some(Value::String(value)) => Ok(Some(value.clone())),
+                Some(value) => Ok(Some(value.to_string())),
+                None => Ok(None),
+            }
+        })
+    }
+}
+
+struct CachedSecret {
+    value: String,
+    fetched_at: Option<Instant>, // None for a value of the previous providers, only used when a fetch fails
+}
+
+struct Providers {
+    providers: HashMap<&'static str, Arc<dyn SecretProvider>>,
+    cache_ttl: Duration,
+}
+
+/// Configured providers and the values fetched from them, shared by every clone of `GitlabTokens`
+pub struct SecretStore {
+    providers: Providers,
+    cache: RwLock<HashMap<String, CachedSecret>>,
+}
+
+impl SecretStore {
+    pub fn new(env: Arc<EnvProvider>) -> Self {
+        let providers = Providers {
+            providers: HashMap::from([(ENV_PROVIDER, env as Arc<dyn SecretProvider>)]),
+            cache_ttl: Duration::from_secs(DEFAULT_SECRET_CACHE_TTL_SECS),
+        };
+        Self { providers, cache: RwLock::new(HashMap::new()) }
+    }
+
+    /// Store with the providers of `config`. The values of `previous` are fetched again on first use,
+    /// they are only kept as the fallback for a provider which fails
+    pub fn configured(config: &SecretsConfig, etcd_data_map: &HashMap<String, EtcdDataMap>, env: Arc<EnvProvider>, previous: &SecretStore) -> Result<Self, Error> {
+        let mut providers: HashMap<&'static str, Arc<dyn SecretProvider>> = HashMap::new();
+        if let Some(file) = &config.file {
+            providers.insert(FILE_PROVIDER, Arc::new(FileProvider::new(file)));
+        }
+        if let Some(etcd) = &config.etcd {
+            providers.insert(ETCD_PROVIDER, Arc::new(EtcdSecretProvider::new(etcd, etcd_data_map, &env)?));
+        }
+        if let Some(vault) = &config.vault {
+            providers.insert(VAULT_PROVIDER, Arc::new(VaultProvider::new(vault, &env)?));
+        }
+        providers.insert(ENV_PROVIDER, env);
+        let cache_ttl = Duration::from_secs(config.cache_ttl_secs.unwrap_or(DEFAULT_SECRET_CACHE_TTL_SECS));
+        let mut names: Vec<&str> = providers.keys().copied().collect();
+        names.sort();
+        info!("Secret providers: {}", names.join(", "));
+        let cache = previous.cache.read().unwrap_or_else(|e| e.into_inner()).iter()
+            .map(|(var, cached)| (var.clone(), CachedSecret { value: cached.value.clone(), fetched_at: None }))
+            .collect();
+        Ok(Self { providers: Providers { providers, cache_ttl }, cache: RwLock::new(cache) })
+    }
+
+    /// Cached value of a `<provider>:<key>` reference, also when it is older than the cache ttl
+    pub fn cached(&self, var: &str) -> Option<String> {
+        self.cache.read().unwrap_or_else(|e| e.into_inner()).get(var).map(|cached| cached.value.clone())
+    }
+
+    /// Fetch a `<provider>:<key>` reference unless a fresh value is cached.
+    /// A failed fetch falls back to the cached value so a provider outage doesn't break running plans
+    pub async fn get(&self, var: &str) -> Result<String, Error> {
+        let (provider_name, key) = split_secret_ref(var).with_context(|| format!("{} is not a <provider>:<key> reference", var))?;
+        let providers = &self.providers;
+        if let Some(cached) = self.cache.read().unwrap_or_else(|e| e.into_inner()).get(var)
+            && cached.fetched_at.is_some_and(|fetched_at| fetched_at.elapsed() < providers.cache_ttl) {
+            return Ok(cached.value.clone());
+        }
+        let provider = providers.providers.get(provider_name)
+            .with_context(|| format!("Secret provider {} is not configured", provider_name))?;
+        let fetched = match provider.fetch(key).await {
+            Ok(Some(value)) => value,
+            Ok(None) => return Err(anyhow::anyhow!("Secret {} not found", var)),
+            Err(e) => {
+                if let Some(stale) = self.cached(var) {
+                    warn!("Failed to refresh secret {}, using the cached value: {:#}", var, e);
+                    return Ok(stale);
+                }
+                return Err(e);
+            }
+        };
+        self.cache.write().unwrap_or_else(|e| e.into_inner())
+            .insert(var.to_string(), CachedSecret { value: fetched.clone(), fetched_at: Some(Instant::now()) });
+        Ok(fetched)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::Matcher;
+
+    use super::*;
+
+    const VAULT_TOKEN: &str = "dev-root-token";
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("plim-secrets-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn env_provider() -> Arc<EnvProvider> {
+        Arc::new(EnvProvider::from_vars(&[("VAULT_TOKEN", VAULT_TOKEN)]))
+    }
+
+    fn vault_config(address: &str) -> SecretsConfig {
+        SecretsConfig {
+            vault: Some(VaultSecretsConfig { address: format!("{}/", address), token_var: "VAULT_TOKEN".to_string(), mount: None }),
+            ..Default::default()
+        }
+    }
+
+    async fn vault_secret(server: &mut mockito::Server, path: &str, status: usize, body: &str, hits: usize) -> mockito::Mock {
+        server.mock("GET", path)
+            .match_header("X-Vault-Token", VAULT_TOKEN)
+            .with_status(status)
+            .with_body(body)
+            .expect(hits)
+            .create_async().await
+    }
+
+    #[tokio::test]
+    async fn file_provider_reads_files_of_its_directory() {
+        let dir = temp_dir("file");
+        std::fs::write(dir.join("db_password"), "s3cret\r\n").unwrap();
+        let provider = FileProvider::new(&FileSecretsConfig { dir: dir.to_string_lossy().to_string() });
+
+        assert_eq!(provider.fetch("db_password").await.unwrap().as_deref(), Some("s3cret"));
+        assert_eq!(provider.fetch("missing").await.unwrap(), None);
+        for key in ["../db_password", "/etc/passwd", "sub/../db_password"] {
+            assert!(provider.fetch(key).await.is_err(), "{}", key);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn vault_provider_reads_kv2_fields() {
+        let mut server = mockito::Server::new_async().await;
+        let secret = vault_secret(&mut server, "/v1/secret/data/plim/gitlab", 200,
+            r#"{"data": {"data": {"value": "glpat-1", "token": "glpat-2", "port": 8443}, "metadata": {"version": 3}}}"#, 4).await;
+        let missing = vault_secret(&mut server, "/v1/secret/data/plim/missing", 404, r#"{"errors": []}"#, 1).await;
+        let denied = vault_secret(&mut server, "/v1/secret/data/plim/denied", 403, r#"{"errors": ["permission denied"]}"#, 1).await;
+        let provider = VaultProvider::new(&vault_config(&server.url()).vault.unwrap(), &env_provider()).unwrap();
+
+        assert_eq!(provider.fetch("plim/gitlab").await.unwrap().as_deref(), Some("glpat-1"));
+        assert_eq!(provider.fetch("/plim/gitlab#token").await.unwrap().as_deref(), Some("glpat-2"));
+        assert_eq!(provider.fetch("plim/gitlab#port").await.unwrap().as_deref(), Some("8443"));
+        assert_eq!(provider.fetch("plim/gitlab#other").await.unwrap(), None);
+        assert_eq!(provider.fetch("plim/missing").await.unwrap(), None);
+        let error = provider.fetch("plim/denied").await.unwrap_err();
+        assert_eq!(error.to_string(), "Vault answered 403 Forbidden for plim/denied");
+        secret.assert_async().await;
+        missing.assert_async().await;
+        denied.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn vault_provider_uses_its_mount() {
+        let mut server = mockito::Server::new_async().await;
+        let secret = vault_secret(&mut server, "/v1/kv/data/plim", 200, r#"{"data": {"data": {"value": "x"}}}"#, 1).await;
+        let mut config = vault_config(&server.url()).vault.unwrap();
+        config.mount = Some("kv".to_string());
+        let provider = VaultProvider::new(&config, &env_provider()).unwrap();
+        assert_eq!(provider.fetch("plim").await.unwrap().as_deref(), Some("x"));
+        secret.assert_async().await;
+
+        config.token_var = "UNSET".to_string();
+        assert!(VaultProvider::new(&config, &env_provider()).is_err());
+    }
+
+    #[tokio::test]
+    async fn store_caches_values_and_falls_back_to_them() {
+        let mut server = mockito::Server::new_async().await;
+        let secret = vault_secret(&mut server, "/v1/secret/data/plim", 200, r#"{"data": {"data": {"value": "first"}}}"#, 1).await;
+        let initial = SecretStore::new(env_provider());
+        let store = SecretStore::configured(&vault_config(&server.url()), &HashMap::new(), env_provider(), &initial).unwrap();
+        assert_eq!(store.get("vault:plim").await.unwrap(), "first");
+        assert_eq!(store.get("vault:plim").await.unwrap(), "first");
+        secret.assert_async().await;
+        secret.remove_async().await;
+
+        // a new store fetches again and keeps the old value for when that fails
+        let failing = server.mock("GET", Matcher::Any).with_status(503).expect(2).create_async().await;
+        let next = SecretStore::configured(&vault_config(&server.url()), &HashMap::new(), env_provider(), &store).unwrap();
+        assert_eq!(next.cached("vault:plim").as_deref(), Some("first"));
+        assert_eq!(next.get("vault:plim").await.unwrap(), "first");
+        assert!(next.get("vault:other").await.is_err());
+        failing.assert_async().await;
+
+        assert!(store.get("file:plim").await.unwrap_err().to_string().contains("Secret provider file is not configured"));
+    }
+
+    #[tokio::test]
+    async fn staged_tokens_change_nothing_until_adopted() {
+        let dir = temp_dir("staged");
+        std::fs::write(dir.join("deploy_token"), "from-file").unwrap();
+        let tokens = GitlabTokens::with_vars(&[]);
+        let config = SecretsConfig { file: Some(FileSecretsConfig { dir: dir.to_string_lossy().to_string() }), ..Default::default() };
+
+        let staged = tokens.staged(&config, &HashMap::new(), None).unwrap();
+        staged.prefetch(["file:deploy_token"]).await;
+        assert_eq!(staged.lookup("file:deploy_token").as_deref(), Some("from-file"));
+        assert_eq!(tokens.lookup("file:deploy_token"), None);
+        assert!(tokens.get("file:deploy_token").await.is_err());
+
+        tokens.adopt(&staged);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(tokens.lookup("file:deploy_token").as_deref(), Some("from-file"));
+        assert_eq!(tokens.clone().get("file:deploy_token").await.unwrap(), "from-file");
+    }
+}
@@ -1,13 +1,18 @@
-use crate::config::Config;
+use crate::config::{Config, EtcdDataMap, SecretsConfig};
 use crate::handlers::ansible::AnsibleGenCmd;
 use crate::http_client::GitlabClient;
+use crate::http_client::gitlab::file_cache::{GitlabFileCache, DEFAULT_FILE_CACHE_MAX_ENTRIES, DEFAULT_FILE_CACHE_TTL_SECS};
 use crate::jwt::JwtKey;
 use anyhow::Error;
-use etcd_client::Client;
-use log::error;
+use arc_swap::ArcSwap;
+use crate::etcd_manager::EtcdManager;
+use crate::secret_registry::{PlanScope, SecretAccessError, SecretRegistry};
+use crate::secrets::{split_secret_ref, EnvProvider, SecretStore};
+use log::{error, info, warn};
+use tokio::sync::Mutex;
 
 
-use std::{collections::HashMap, env, ops::Deref, sync::Arc};
+use std::{collections::HashMap, ops::Deref, sync::Arc};
 
 #[derive(Clone)]
 pub struct AppState {
@@ -28,58 +33,183 @@ impl AppState {
         config: Config,
         gitlab_client: GitlabClient,
         gitlab_tokens: GitlabTokens,
-        etcd_clients_map: HashMap<String, Client>,
+        etcd: EtcdManager,
+        config_paths: Vec<String>,
     ) -> Self {
+        let gitlab_file_cache = GitlabFileCache::new(
+            config.gitlab.file_cache_ttl_secs.unwrap_or(DEFAULT_FILE_CACHE_TTL_SECS),
+            config.gitlab.file_cache_max_entries.unwrap_or(DEFAULT_FILE_CACHE_MAX_ENTRIES),
+        );
         Self {
             inner: Arc::new(StateInner { 
                 jwt,
-                config,
+                config: ConfigHandle::new(config),
+                config_paths,
                 gitlab_client,
+                gitlab_file_cache,
                 ansible_command_generator: AnsibleGenCmd,
                 gitlab_tokens,
-                etcd_clients_map,
+                etcd,
             }),
         }
     }
+
+    /// Load the config again and swap it in, the running config stays when loading or validation fails
+    pub async fn reload_config(&self) -> Result<Arc<Config>, Error> {
+        let _guard = self.config.reload_lock.lock().await;
+        let current = self.config.load();
+        let config = Arc::new(crate::config::reload(&current, &self.config_paths, &self.gitlab_tokens, &self.etcd).await?);
+        self.config.current.store(config.clone());
+        info!("Config reloaded: {} plans, {} users", config.plans.len(), config.users.len());
+        Ok(config)
+    }
+}
+
+/// Swappable config, readers get the config which was current when they asked
+pub struct ConfigHandle {
+    current: ArcSwap<Config>,
+    reload_lock: Mutex<()>,
+}
+
+impl ConfigHandle {
+    pub fn new(config: Config) -> Self {
+        Self { current: ArcSwap::from_pointee(config), reload_lock: Mutex::new(()) }
+    }
+
+    pub fn load(&self) -> Arc<Config> {
+        self.current.load_full()
+    }
+
+    /// Change the running config in place, never interleaved with a reload
+    pub async fn update(&self, change: impl FnOnce(&mut Config)) {
+        let _guard = self.reload_lock.lock().await;
+        let mut config = Config::clone(&self.current.load());
+        change(&mut config);
+        self.current.store(Arc::new(config));
+    }
 }
 
 pub struct StateInner {
     pub jwt: JwtKey,
-    pub config: Config,
+    pub config: ConfigHandle,
+    /// Config sources in merge order, read again on reload
+    pub config_paths: Vec<String>,
     pub gitlab_client: GitlabClient,
+    pub gitlab_file_cache: GitlabFileCache,
     pub ansible_command_generator: AnsibleGenCmd,
     pub gitlab_tokens: GitlabTokens,
-    pub etcd_clients_map: HashMap<String, Client>,
+    pub etcd: EtcdManager,
 }
-#[derive(Default)]
+/// Token variables of the config: plain names are env variables, `<provider>:<key>` names are read from a secret provider
+#[derive(Clone)]
 pub struct GitlabTokens {
-    all_vars: HashMap<String, String>,
+    env: Arc<EnvProvider>,
+    secrets: Arc<ArcSwap<SecretStore>>,
+    registry: Arc<ArcSwap<SecretRegistry>>,
+}
+
+impl Default for GitlabTokens {
+    fn default() -> Self {
+        Self::with_env(EnvProvider::default())
+    }
 }
 
 impl GitlabTokens {
 
     pub fn new() -> Self {
-        let all_vars = Self::load_normalized_env();
-        Self { all_vars }
+        Self::with_env(EnvProvider::from_env())
+    }
+
+    /// Tokens of `vars` only, instead of the process env
+    #[cfg(test)]
+    pub fn with_vars(vars: &[(&str, &str)]) -> Self {
+        Self::with_env(EnvProvider::from_vars(vars))
     }
 
-    fn load_normalized_env() -> HashMap<String, String> {
-        env::vars()
-            .map(|(key, value)| (key.replace("__", "_"), value))
-            .collect()
+    fn with_env(env: EnvProvider) -> Self {
+        let env = Arc::new(env);
+        Self {
+            secrets: Arc::new(ArcSwap::from_pointee(SecretStore::new(env.clone()))),
+            registry: Arc::new(ArcSwap::from_pointee(SecretRegistry::default())),
+            env,
+        }
     }
 
+    /// Tokens with the secret providers and registry of `config`, built next to these so a config which is rejected changes nothing.
+    /// `adopt` makes them the ones every clone uses
+    pub fn staged(&self, config: &SecretsConfig, etcd_data_map: &HashMap<String, EtcdDataMap>, jwt_secret_var: Option<&str>) -> Result<GitlabTokens, Error> {
+        let registry = SecretRegistry::new(config, etcd_data_map, jwt_secret_var)?;
+        let secrets = SecretStore::configured(config, etcd_data_map, self.env.clone(), &self.secrets.load())?;
+        Ok(Self {
+            env: self.env.clone(),
+            secrets: Arc::new(ArcSwap::from_pointee(secrets)),
+            registry: Arc::new(ArcSwap::from_pointee(registry)),
+        })
+    }
 
-    pub async fn get(&self, token_var: &str) -> Result<String, Error> {
-        match self.all_vars.get(token_var) {
-            Some(token) => Ok(token.clone()),
-            None => {
-                let error_message = format!("Your token {} is missing", &token_var);
-                error!("{}", error_message);
-                Err(anyhow::anyhow!(error_message))
+    /// Take over the secret providers, their cached values and the registry of `staged`
+    pub fn adopt(&self, staged: &GitlabTokens) {
+        self.secrets.store(staged.secrets.load_full());
+        self.registry.store(staged.registry.load_full());
+    }
+
+    /// Token variables the registered secrets are read from
+    pub fn registry_sources(&self) -> Vec<String> {
+        self.registry.load().sources().into_iter().map(|(_, source)| source.to_string()).collect()
+    }
+
+    /// Token variable `token_var` of `plan` reads, an error when the registry doesn't allow it
+    pub fn resolve_for_plan(&self, token_var: &str, plan: PlanScope<'_>) -> Result<String, Error> {
+        Ok(self.registry.load().resolve(token_var, plan)?)
+    }
+
+    /// `lookup` for `${var}` references in the config, which must not name a secret of the registry
+    pub fn lookup_for_interpolation(&self, var: &str) -> Result<Option<String>, SecretAccessError> {
+        self.registry.load().check_interpolation(var)?;
+        Ok(self.lookup(var))
+    }
+
+    /// `get` for the token variables plans name, checked against the secret registry
+    pub async fn get_for_plan(&self, token_var: &str, plan: PlanScope<'_>) -> Result<String, Error> {
+        match self.resolve_for_plan(token_var, plan) {
+            Ok(source) => self.get(&source).await,
+            Err(e) => {
+                error!("{:#}", e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Fetch the provider references among `vars` so `contains` and `lookup` know them, failures are logged
+    pub async fn prefetch<'a>(&self, vars: impl IntoIterator<Item = &'a str>) {
+        for var in vars.into_iter().filter(|var| split_secret_ref(var).is_some()) {
+            if let Err(e) = self.secrets.load_full().get(var).await {
+                warn!("Failed to fetch secret {}: {:#}", var, e);
             }
         }
     }
 
+    pub fn contains(&self, token_var: &str) -> bool {
+        self.lookup(token_var).is_some()
+    }
+
+    /// Like `get` without logging a missing variable, for optional lookups.
+    /// Provider references only resolve after `prefetch` or an earlier `get`
+    pub fn lookup(&self, token_var: &str) -> Option<String> {
+        match split_secret_ref(token_var) {
+            Some(_) => self.secrets.load().cached(token_var),
+            None => self.env.var(token_var).map(str::to_string),
+        }
+    }
 
-}
\ No newline at end of file
+    pub async fn get(&self, token_var: &str) -> Result<String, Error> {
+        let token = match split_secret_ref(token_var) {
+            Some(_) => self.secrets.load_full().get(token_var).await,
+            None => self.env.var(token_var).map(str::to_string).ok_or_else(|| anyhow::anyhow!("Your token {} is missing", token_var)),
+        };
+        if let Err(e) = &token {
+            error!("{:#}", e);
+        }
+        token
+    }
+}